@@ -0,0 +1,770 @@
+//! Read and write BMS charts (`.bms`, `.bme` and `.bml` files).
+//!
+//! See https://hitkey.nekokan.dyndns.info/cmds.htm for a description of the format.
+
+use crate::{
+    node::simfilewrite::{chart_display_name, path_str},
+    prelude::*,
+    smfile::display_bpm_from,
+};
+use std::collections::BTreeMap;
+
+/// The lane digit of the turntable in BMS channels.
+const SCRATCH_LANE: u8 = b'6';
+const KEYS5: &[u8] = b"12345";
+const KEYS7: &[u8] = b"1234589";
+
+/// The largest two-digit base-36 object id.
+const MAX_ID: u32 = 36 * 36 - 1;
+/// The object id of the music file when writing.
+const MUSIC_ID: u32 = 1;
+/// An object id that is never defined when writing, used for notes without a keysound.
+const SILENT_ID: u32 = MAX_ID;
+
+/// Get the `(player, lane)` channel digits of every column for the given key count, or `None` if
+/// there is no BMS layout with that many keys.
+///
+/// Like in StepMania, the turntable is the leftmost column of player 1 and the rightmost column of
+/// player 2.
+fn columns(key_count: i32) -> Option<Vec<(u8, u8)>> {
+    let (keys, double) = match key_count {
+        6 => (KEYS5, false),
+        8 => (KEYS7, false),
+        12 => (KEYS5, true),
+        16 => (KEYS7, true),
+        _ => return None,
+    };
+    let mut cols = vec![(b'1', SCRATCH_LANE)];
+    cols.extend(keys.iter().map(|&lane| (b'1', lane)));
+    if double {
+        cols.extend(keys.iter().map(|&lane| (b'2', lane)));
+        cols.push((b'2', SCRATCH_LANE));
+    }
+    Some(cols)
+}
+
+fn parse_id(id: &str) -> Option<u32> {
+    u32::from_str_radix(id, 36).ok().filter(|&id| id <= MAX_ID)
+}
+
+fn fmt_id(id: u32) -> String {
+    const DIGITS: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+    let id = id.min(MAX_ID) as usize;
+    format!("{}{}", DIGITS[id / 36] as char, DIGITS[id % 36] as char)
+}
+
+/// A note object found in the main data field.
+struct RawObject {
+    beat: f64,
+    player: u8,
+    lane: u8,
+    id: u32,
+    /// Whether the object is in a long note channel.
+    long: bool,
+}
+
+/// Parse a BMS chart into a single simfile.
+///
+/// Only the first branch of `#RANDOM` blocks is loaded.
+/// BMS charts have no music file, so the first background sound is used as the music and any
+/// other background sounds are dropped.
+pub fn load(path: &Path) -> Result<Simfile> {
+    let raw = fs::read(path).context("read file")?;
+    //BMS files are traditionally Shift-JIS, but newer ones may be UTF-8
+    let text = match std::str::from_utf8(&raw) {
+        Ok(text) => Cow::Borrowed(text),
+        Err(_) => encoding_rs::SHIFT_JIS.decode(&raw).0,
+    };
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut sm = Simfile::default();
+    let mut init_bpm = 130.;
+    let mut player = 1;
+    let mut lnobj = None;
+    let mut wavs: HashMap<u32, String> = default();
+    let mut ext_bpms: HashMap<u32, f64> = default();
+    let mut ext_stops: HashMap<u32, f64> = default();
+    let mut measure_lens: HashMap<u32, f64> = default();
+    let mut data_lines = Vec::new();
+    //One entry per nested `#IF` block, `true` if the block is being skipped
+    let mut skip_stack: Vec<bool> = Vec::new();
+    for line in text.lines() {
+        let line = match line.trim().strip_prefix('#') {
+            Some(line) => line,
+            None => continue,
+        };
+        let (name, value) = line
+            .split_once(char::is_whitespace)
+            .map(|(name, value)| (name, value.trim()))
+            .unwrap_or((line, ""));
+        let name = name.to_ascii_uppercase();
+        match &name[..] {
+            "IF" => skip_stack.push(value != "1"),
+            "ELSEIF" => {
+                if let Some(skip) = skip_stack.last_mut() {
+                    *skip = true;
+                }
+            }
+            "ELSE" => {
+                if let Some(skip) = skip_stack.last_mut() {
+                    *skip = !*skip;
+                }
+            }
+            "ENDIF" => {
+                skip_stack.pop();
+            }
+            _ if skip_stack.contains(&true) => {}
+            _ => {
+                if let Some((measure, channel, data)) = parse_data_line(line) {
+                    if channel == "02" {
+                        match data.trim().parse::<f64>() {
+                            Ok(len) if len > 0. => {
+                                measure_lens.insert(measure, len);
+                            }
+                            _ => warn!("  invalid length \"{}\" for measure {}", data, measure),
+                        }
+                    } else {
+                        data_lines.push((measure, channel, data));
+                    }
+                    continue;
+                }
+                let result = (|| -> Result<()> {
+                    let num = || {
+                        value
+                            .parse::<f64>()
+                            .map_err(|_| anyhow!("invalid number \"{}\"", value))
+                    };
+                    let id = |prefix: &str| parse_id(&name[prefix.len()..]);
+                    match &name[..] {
+                        "PLAYER" => player = num()? as i32,
+                        "GENRE" => sm.genre = value.to_string(),
+                        "TITLE" => sm.title = value.to_string(),
+                        "SUBTITLE" => sm.subtitle = value.to_string(),
+                        "ARTIST" => sm.artist = value.to_string(),
+                        "SUBARTIST" => sm.credit = value.to_string(),
+                        "BPM" => init_bpm = num()?,
+                        "PLAYLEVEL" => sm.difficulty_num = num()?,
+                        "DIFFICULTY" => {
+                            use crate::simfile::Difficulty::*;
+                            sm.difficulty = match num()? as i32 {
+                                1 => Beginner,
+                                2 => Easy,
+                                3 => Medium,
+                                4 => Hard,
+                                5 => Challenge,
+                                _ => Edit,
+                            }
+                        }
+                        "STAGEFILE" => sm.background = Some(value.into()),
+                        "BACKBMP" => {
+                            sm.background.get_or_insert_with(|| value.into());
+                        }
+                        "BANNER" => sm.banner = Some(value.into()),
+                        "LNOBJ" => lnobj = parse_id(value),
+                        _ if name.starts_with("WAV") => {
+                            if let Some(id) = id("WAV") {
+                                wavs.insert(id, value.to_string());
+                            }
+                        }
+                        _ if name.starts_with("BPM") => {
+                            if let Some(id) = id("BPM") {
+                                ext_bpms.insert(id, num()?);
+                            }
+                        }
+                        _ if name.starts_with("EXBPM") => {
+                            if let Some(id) = id("EXBPM") {
+                                ext_bpms.insert(id, num()?);
+                            }
+                        }
+                        _ if name.starts_with("STOP") => {
+                            if let Some(id) = id("STOP") {
+                                ext_stops.insert(id, num()?);
+                            }
+                        }
+                        _ => {}
+                    }
+                    Ok(())
+                })();
+                if let Err(err) = result {
+                    warn!(
+                        "  invalid #{} header in \"{}\": {:#}",
+                        name,
+                        path.display(),
+                        err
+                    );
+                }
+            }
+        }
+    }
+
+    //Find the starting beat of every measure
+    let last_measure = data_lines.iter().map(|line| line.0).max().unwrap_or(0);
+    let mut measure_starts = Vec::with_capacity(last_measure as usize + 1);
+    let mut beat = 0.;
+    sm.time_signatures.clear();
+    for measure in 0..=last_measure {
+        measure_starts.push(beat);
+        let len = measure_lens.get(&measure).copied().unwrap_or(1.);
+        let (num, den) = time_signature(len);
+        if sm.time_signatures.last().map(|&(_, n, d)| (n, d)) != Some((num, den)) {
+            sm.time_signatures.push((beat, num, den));
+        }
+        beat += 4. * len;
+    }
+
+    //Read the objects in every channel
+    let mut bpm_changes = Vec::new();
+    let mut stops = Vec::new();
+    let mut bgm = Vec::new();
+    let mut objects = Vec::new();
+    for (measure, channel, data) in data_lines {
+        let data = data
+            .bytes()
+            .filter(|c| !c.is_ascii_whitespace())
+            .collect::<Vec<_>>();
+        let start = measure_starts[measure as usize];
+        let len = 4. * measure_lens.get(&measure).copied().unwrap_or(1.);
+        let count = data.len() / 2;
+        for (idx, pair) in data.chunks_exact(2).enumerate() {
+            let pair = String::from_utf8_lossy(pair);
+            if pair == "00" {
+                continue;
+            }
+            let beat = start + len * idx as f64 / count as f64;
+            let id = match parse_id(&pair) {
+                Some(id) => id,
+                None => {
+                    warn!("  invalid object \"{}\" in measure {}", pair, measure);
+                    continue;
+                }
+            };
+            match channel.as_bytes() {
+                b"01" => bgm.push((beat, id)),
+                b"03" => match u32::from_str_radix(&pair, 16) {
+                    Ok(bpm) => bpm_changes.push((beat, bpm as f64)),
+                    Err(_) => warn!("  invalid bpm \"{}\" in measure {}", pair, measure),
+                },
+                b"08" => match ext_bpms.get(&id) {
+                    Some(&bpm) => bpm_changes.push((beat, bpm)),
+                    None => warn!("  undefined #BPM{} in measure {}", pair, measure),
+                },
+                b"09" => match ext_stops.get(&id) {
+                    Some(&len) => stops.push((beat, len)),
+                    None => warn!("  undefined #STOP{} in measure {}", pair, measure),
+                },
+                &[player @ b'1'..=b'2', lane] => objects.push(RawObject {
+                    beat,
+                    player,
+                    lane,
+                    id,
+                    long: false,
+                }),
+                &[player @ b'5'..=b'6', lane] => objects.push(RawObject {
+                    beat,
+                    player: player - 4,
+                    lane,
+                    id,
+                    long: true,
+                }),
+                _ => {}
+            }
+        }
+    }
+
+    //Build timing data
+    bpm_changes.sort_by_key(|&(beat, _)| SortableFloat(beat));
+    sm.bpms = vec![ControlPoint {
+        beat: BeatPos::from(0.),
+        beat_len: 60. / init_bpm,
+    }];
+    for (beat, bpm) in bpm_changes {
+        if bpm <= 0. || !bpm.is_finite() {
+            warn!("  ignoring unsupported bpm {} at beat {}", bpm, beat);
+            continue;
+        }
+        let beat = BeatPos::from(beat);
+        if sm.bpms.last().map(|cp| cp.beat) == Some(beat) {
+            sm.bpms.pop();
+        }
+        sm.bpms.push(ControlPoint {
+            beat,
+            beat_len: 60. / bpm,
+        });
+    }
+    ensure!(
+        sm.bpms
+            .iter()
+            .all(|cp| cp.beat_len.is_finite() && cp.beat_len > 0.),
+        "invalid initial bpm {}",
+        init_bpm
+    );
+    sm.display_bpm = display_bpm_from(&sm.bpms);
+    stops.sort_by_key(|&(beat, _)| SortableFloat(beat));
+    sm.stops = stops
+        .into_iter()
+        .map(|(beat, len)| {
+            //Stop lengths are measured in 192nds of a 4/4 measure
+            let beat_len = sm
+                .bpms
+                .iter()
+                .take_while(|cp| cp.beat <= BeatPos::from(beat))
+                .last()
+                .map(|cp| cp.beat_len)
+                .unwrap_or(sm.bpms[0].beat_len);
+            (beat, len / 48. * beat_len)
+        })
+        .collect();
+
+    //Use the first background sound as music
+    bgm.sort_by_key(|&(beat, _)| SortableFloat(beat));
+    if let Some(&(beat, id)) = bgm.first() {
+        if let Some(name) = wavs.get(&id) {
+            sm.music = Some(resolve_sound(dir, name));
+            let music_time = sm.beat_to_time().beat_to_time(BeatPos::from(beat));
+            sm.offset = music_time;
+        }
+        if bgm.len() > 1 {
+            debug!(
+                "  dropping {} background sounds other than the music",
+                bgm.len() - 1
+            );
+        }
+    }
+
+    //Figure out the key count from the used channels
+    let double = player == 3 || objects.iter().any(|obj| obj.player == b'2');
+    let seven = objects.iter().any(|obj| KEYS7[5..].contains(&obj.lane))
+        || path
+            .extension()
+            .map(|ext| ext.eq_ignore_ascii_case("bme") || ext.eq_ignore_ascii_case("bml"))
+            .unwrap_or(false);
+    let (key_count, gamemode) = match (seven, double) {
+        (false, false) => (6, Gamemode::BmSingle5),
+        (true, false) => (8, Gamemode::BmSingle7),
+        (false, true) => (12, Gamemode::BmDouble5),
+        (true, true) => (16, Gamemode::BmDouble7),
+    };
+    sm.gamemode = gamemode;
+    let cols = columns(key_count).unwrap_or_default();
+
+    //Convert objects into notes
+    objects.sort_by_key(|obj| SortableFloat(obj.beat));
+    let mut keysound_ids: HashMap<u32, u32> = default();
+    let mut keysound = |id: u32| -> Option<u32> {
+        let name = wavs.get(&id)?;
+        let keysounds = &mut sm.keysounds;
+        Some(*keysound_ids.entry(id).or_insert_with(|| {
+            keysounds.push(resolve_sound(dir, name));
+            keysounds.len() as u32 - 1
+        }))
+    };
+    let mut notes: Vec<Note> = Vec::new();
+    //The last hit note in each column, which becomes a long note head if followed by `#LNOBJ`
+    let mut last_hit: Vec<Option<usize>> = vec![None; cols.len()];
+    //The head of the long note currently open in each column, if any
+    let mut open_long = vec![None; cols.len()];
+    for obj in objects {
+        let key = match cols.iter().position(|&col| col == (obj.player, obj.lane)) {
+            Some(key) => key,
+            None => continue,
+        };
+        let beat = BeatPos::from(obj.beat);
+        let mut push = |kind: char, keysound: Option<u32>| {
+            notes.push(Note {
+                kind,
+                beat,
+                key: key as i32,
+                keysound,
+                player: 0,
+            });
+            notes.len() - 1
+        };
+        if obj.long {
+            if open_long[key].take().is_some() {
+                push(Note::KIND_TAIL, None);
+            } else {
+                open_long[key] = Some(push(Note::KIND_HEAD, keysound(obj.id)));
+            }
+        } else if Some(obj.id) == lnobj {
+            if let Some(head) = last_hit[key].take() {
+                push(Note::KIND_TAIL, None);
+                notes[head].kind = Note::KIND_HEAD;
+            }
+        } else {
+            last_hit[key] = Some(push(Note::KIND_HIT, keysound(obj.id)));
+        }
+    }
+    //Long notes that never end are turned into regular notes
+    for head in open_long.into_iter().flatten() {
+        notes[head].kind = Note::KIND_HIT;
+    }
+    notes.sort_by_key(|note| note.beat);
+    sm.notes = notes;
+
+    let stem = path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    sm.chart_name = if sm.subtitle.is_empty() {
+        stem
+    } else {
+        sm.subtitle.clone()
+    };
+    sm.desc = sm.chart_name.clone();
+    Ok(sm)
+}
+
+/// Split a main data field line (without the leading `#`) into measure, channel and data.
+fn parse_data_line(line: &str) -> Option<(u32, String, &str)> {
+    let (head, data) = line.split_once(':')?;
+    if head.len() != 5 || !head.is_char_boundary(3) {
+        return None;
+    }
+    let measure = head[..3].parse::<u32>().ok()?;
+    Some((measure, head[3..].to_ascii_uppercase(), data.trim()))
+}
+
+/// Find a time signature for a measure that is `len` times as long as a 4/4 measure.
+fn time_signature(len: f64) -> (i32, i32) {
+    for &den in [4, 8, 16, 32, 64].iter() {
+        let num = len * den as f64;
+        if (num - num.round()).abs() < 1e-6 {
+            return (num.round() as i32, den);
+        }
+    }
+    (((len * 4.).round() as i32).max(1), 4)
+}
+
+/// BMS players fall back to other audio formats when a sound file is missing, because charts often
+/// reference `.wav` files that were later converted to `.ogg`.
+fn resolve_sound(dir: &Path, name: &str) -> PathBuf {
+    let path = PathBuf::from(name);
+    if !dir.join(&path).is_file() {
+        for ext in ["ogg", "wav", "flac", "mp3"].iter() {
+            let alt = path.with_extension(ext);
+            if dir.join(&alt).is_file() {
+                return alt;
+            }
+        }
+    }
+    path
+}
+
+/// The extension that BMS players expect for a chart with the given amount of keys.
+pub fn extension(key_count: i32) -> &'static str {
+    match key_count {
+        8 | 16 => "bme",
+        _ => "bms",
+    }
+}
+
+/// Write a simfile as a BMS chart.
+///
+/// The music is placed as a background sound, and keysounds are kept.
+pub fn write_to(sm: &Simfile, mut file: impl Write) -> Result<()> {
+    let key_count = sm.gamemode.key_count();
+    let cols = columns(key_count).ok_or_else(|| {
+        anyhow!(
+            "cannot write {} chart as BMS: only 6, 8, 12 and 16 keys are supported, with the \
+            first key of each side being the turntable",
+            sm.gamemode.id()
+        )
+    })?;
+    ensure!(!sm.bpms.is_empty(), "simfile has no bpms");
+    ensure!(
+        sm.keysounds.len() as u32 + MUSIC_ID < SILENT_ID,
+        "too many keysounds ({}) for a BMS chart",
+        sm.keysounds.len()
+    );
+
+    //BMS charts start at the first measure, so shift everything forward if anything comes before
+    let music_beat = sm.music.as_ref().map(|_| time_to_beat(sm, 0.));
+    let first_beat = sm
+        .notes
+        .iter()
+        .map(|note| note.beat.as_num())
+        .chain(music_beat)
+        .fold(0., f64::min);
+    let shift_measures = (-first_beat / 4.).ceil().max(0.);
+    let shift = BeatPos::from(shift_measures * 4.);
+    if shift_measures > 0. {
+        trace!("    shifting chart by {} measures", shift_measures);
+    }
+
+    let mut channels: BTreeMap<(u32, String), Vec<(BeatPos, u32)>> = BTreeMap::new();
+    let mut put = |beat: BeatPos, channel: String, id: u32| -> Result<()> {
+        let beat = beat + shift;
+        let measure = (beat.as_num() / 4.).floor();
+        ensure!(
+            (0. ..1000.).contains(&measure),
+            "beat {} is out of the range of BMS measures",
+            beat
+        );
+        let pos = beat - BeatPos::from(measure * 4.);
+        channels
+            .entry((measure as u32, channel))
+            .or_default()
+            .push((pos, id));
+        Ok(())
+    };
+    if let Some(beat) = music_beat {
+        put(BeatPos::from(beat), "01".to_string(), MUSIC_ID)?;
+    }
+    //Use extended bpms, since they are not limited to integers
+    let mut bpm_values = Vec::new();
+    for cp in sm.bpms.iter().skip(1) {
+        let bpm = cp.bpm();
+        let idx = match bpm_values.iter().position(|&b| b == bpm) {
+            Some(idx) => idx,
+            None => {
+                bpm_values.push(bpm);
+                bpm_values.len() - 1
+            }
+        };
+        put(cp.beat, "08".to_string(), idx as u32 + 1)?;
+    }
+    //BMS only has stops, so delays are folded into them
+    if !sm.warps.is_empty() {
+        warn!("    BMS charts cannot have warps, notes after them will be late");
+    }
+    let mut stop_values = Vec::new();
+    for (beat, secs) in sm.folded_stops() {
+        let beat_len = sm
+            .bpms
+            .iter()
+            .take_while(|cp| cp.beat <= BeatPos::from(beat))
+            .last()
+            .unwrap_or(&sm.bpms[0])
+            .beat_len;
+        let len = (secs / beat_len * 48.).round();
+        if len <= 0. {
+            continue;
+        }
+        let idx = match stop_values.iter().position(|&l| l == len) {
+            Some(idx) => idx,
+            None => {
+                stop_values.push(len);
+                stop_values.len() - 1
+            }
+        };
+        put(BeatPos::from(beat), "09".to_string(), idx as u32 + 1)?;
+    }
+    ensure!(
+        bpm_values.len() <= MAX_ID as usize && stop_values.len() <= MAX_ID as usize,
+        "too many bpm changes or stops for a BMS chart"
+    );
+    //Long notes use the same object on their head and tail
+    let mut long_ids = vec![SILENT_ID; cols.len()];
+    for note in sm.notes.iter() {
+        let (player, lane) = cols[note.key as usize];
+        let id = note
+            .keysound
+            .filter(|&idx| (idx as usize) < sm.keysounds.len())
+            .map(|idx| idx + MUSIC_ID + 1)
+            .unwrap_or(SILENT_ID);
+        if note.is_hit() || note.is_lift() {
+            put(note.beat, format!("{}{}", player as char, lane as char), id)?;
+        } else if note.is_head() || note.is_tail() {
+            let id = if note.is_head() {
+                long_ids[note.key as usize] = id;
+                id
+            } else {
+                long_ids[note.key as usize]
+            };
+            put(
+                note.beat,
+                format!("{}{}", (player + 4) as char, lane as char),
+                id,
+            )?;
+        }
+    }
+
+    //Header
+    let name = chart_display_name(sm);
+    writeln!(file, "*---------------------- HEADER FIELD")?;
+    writeln!(file, "#PLAYER {}", if key_count > 8 { 3 } else { 1 })?;
+    writeln!(file, "#GENRE {}", sm.genre)?;
+    writeln!(file, "#TITLE {}", sm.title)?;
+    writeln!(file, "#SUBTITLE {}", name)?;
+    writeln!(file, "#ARTIST {}", sm.artist)?;
+    if !sm.credit.is_empty() {
+        writeln!(file, "#SUBARTIST {}", sm.credit)?;
+    }
+    writeln!(file, "#BPM {}", sm.bpms[0].bpm())?;
+    if sm.difficulty_num.is_finite() {
+        writeln!(file, "#PLAYLEVEL {}", sm.difficulty_num.round())?;
+    }
+    {
+        use crate::simfile::Difficulty::*;
+        let difficulty = match sm.difficulty {
+            Beginner => 1,
+            Easy => 2,
+            Medium => 3,
+            Hard => 4,
+            Challenge | Edit => 5,
+        };
+        writeln!(file, "#DIFFICULTY {}", difficulty)?;
+    }
+    writeln!(file, "#RANK 2")?;
+    let background = path_str(&sm.background);
+    if !background.is_empty() {
+        writeln!(file, "#STAGEFILE {}", background)?;
+    }
+    let banner = path_str(&sm.banner);
+    if !banner.is_empty() {
+        writeln!(file, "#BANNER {}", banner)?;
+    }
+    writeln!(file, "#LNTYPE 1")?;
+    writeln!(file)?;
+    if sm.music.is_some() {
+        writeln!(file, "#WAV{} {}", fmt_id(MUSIC_ID), path_str(&sm.music))?;
+    }
+    for (idx, keysound) in sm.keysounds.iter().enumerate() {
+        writeln!(
+            file,
+            "#WAV{} {}",
+            fmt_id(idx as u32 + MUSIC_ID + 1),
+            keysound.to_string_lossy()
+        )?;
+    }
+    for (idx, bpm) in bpm_values.iter().enumerate() {
+        writeln!(file, "#BPM{} {}", fmt_id(idx as u32 + 1), bpm)?;
+    }
+    for (idx, len) in stop_values.iter().enumerate() {
+        writeln!(file, "#STOP{} {}", fmt_id(idx as u32 + 1), len)?;
+    }
+    writeln!(file)?;
+
+    //Main data field
+    writeln!(file, "*---------------------- MAIN DATA FIELD")?;
+    for ((measure, channel), objs) in channels {
+        let per_beat = objs
+            .iter()
+            .fold(1, |acc, (pos, _)| lcm(acc, pos.denominator()));
+        let mut data = vec!["00".to_string(); 4 * per_beat as usize];
+        for (pos, id) in objs {
+            let idx = (pos.as_num() * per_beat as f64).round() as usize;
+            let last_idx = data.len() - 1;
+            data[idx.min(last_idx)] = fmt_id(id);
+        }
+        writeln!(file, "#{:03}{}:{}", measure, channel, data.concat())?;
+    }
+    Ok(())
+}
+
+/// Write a simfile as a BMS chart file.
+pub fn save(sm: &Simfile, path: &Path) -> Result<()> {
+    let mut file = BufWriter::new(File::create(path).context("create file")?);
+    write_to(sm, &mut file)?;
+    file.flush()?;
+    Ok(())
+}
+
+/// The inverse of `ToTime`, for a single point in time.
+/// Times that fall within a stop map to the beat of the stop.
+fn time_to_beat(sm: &Simfile, time: f64) -> f64 {
+    //Remove the time taken by the stops that end before the given time
+    let mut to_time = ToTime::from_raw(&sm.bpms, sm.offset);
+    let mut stop_time = 0.;
+    for (beat, secs) in sm.folded_stops() {
+        let start = to_time.beat_to_time(BeatPos::from(beat)) + stop_time;
+        if time <= start {
+            break;
+        }
+        if time <= start + secs {
+            return beat;
+        }
+        stop_time += secs;
+    }
+    let time = time - stop_time;
+    //Now only bpm changes are left
+    let mut cp_time = -sm.offset;
+    let mut idx = 0;
+    while let Some(next) = sm.bpms.get(idx + 1) {
+        let cur = &sm.bpms[idx];
+        let next_time = cp_time + (next.beat - cur.beat).as_num() * cur.beat_len;
+        if time < next_time {
+            break;
+        }
+        cp_time = next_time;
+        idx += 1;
+    }
+    let cp = &sm.bpms[idx];
+    cp.beat.as_num() + (time - cp_time) / cp.beat_len
+}
+
+fn lcm(a: i32, b: i32) -> i32 {
+    fn gcd(a: i32, b: i32) -> i32 {
+        if b == 0 {
+            a
+        } else {
+            gcd(b, a % b)
+        }
+    }
+    a / gcd(a, b) * b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(kind: char, beat: f64, key: i32, keysound: Option<u32>) -> Note {
+        Note {
+            kind,
+            beat: BeatPos::from(beat),
+            key,
+            keysound,
+            player: 0,
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let sm = Simfile {
+            title: "Song".to_string(),
+            artist: "Artist".to_string(),
+            gamemode: Gamemode::BmSingle7,
+            music: Some("song.ogg".into()),
+            keysounds: vec!["kick.wav".into(), "snare.wav".into()],
+            bpms: vec![
+                ControlPoint {
+                    beat: BeatPos::from(0.),
+                    beat_len: 0.5,
+                },
+                ControlPoint {
+                    beat: BeatPos::from(8.),
+                    beat_len: 0.25,
+                },
+            ],
+            //The stop comes before the music, which starts on beat 4
+            stops: vec![(1., 0.5)],
+            offset: 2.5,
+            notes: vec![
+                note(Note::KIND_HIT, 0., 1, Some(0)),
+                note(Note::KIND_HIT, 2., 0, Some(1)),
+                note(Note::KIND_HEAD, 4., 2, Some(0)),
+                note(Note::KIND_TAIL, 6., 2, None),
+                note(Note::KIND_HIT, 9.5, 7, None),
+            ],
+            ..default()
+        };
+        let path =
+            std::env::temp_dir().join(format!("osu2sm-test-{}-roundtrip.bme", std::process::id()));
+        save(&sm, &path).unwrap();
+        let loaded = load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.gamemode, sm.gamemode);
+        assert_eq!(loaded.bpms, sm.bpms);
+        assert_eq!(loaded.stops, sm.stops);
+        assert_eq!(loaded.offset, sm.offset);
+        assert_eq!(loaded.music, sm.music);
+        assert_eq!(loaded.keysounds, sm.keysounds);
+        let simplify = |sm: &Simfile| {
+            sm.notes
+                .iter()
+                .map(|note| (note.kind, note.beat, note.key, note.keysound))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(simplify(&loaded), simplify(&sm));
+    }
+}
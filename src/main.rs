@@ -5,7 +5,10 @@ mod prelude {
         linear_map,
         node::{ConcreteNode, Node, SimfileStore},
         osufile::{self, Beatmap, TimingPoint},
-        simfile::{BeatPos, ControlPoint, Difficulty, DisplayBpm, Gamemode, Note, Simfile, ToTime},
+        simfile::{
+            BeatPos, ControlPoint, Difficulty, DisplayBpm, Gamemode, Note, Simfile, Skillset,
+            Skillsets, SourceMeta, ToTime,
+        },
         simfile_rng, symlink_dir, symlink_file, BaseDirFinder,
     };
     pub use anyhow::{anyhow, bail, ensure, Context, Error, Result};
@@ -62,9 +65,14 @@ mod prelude {
     impl Eq for SortableFloat {}
 }
 
+pub mod bmsfile;
+pub mod mcfile;
 pub mod node;
+pub mod osudb;
 pub mod osufile;
+pub mod quafile;
 pub mod simfile;
+pub mod smfile;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
@@ -0,0 +1,100 @@
+//! Write Malody `.mc` charts, which are JSON documents.
+//!
+//! Positions are stored as `[whole beats, numerator, denominator]` triples.
+
+use crate::prelude::*;
+
+/// The Malody key mode.
+pub const MODE_KEY: i32 = 0;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Mc {
+    pub meta: McMeta,
+    pub time: Vec<McBpm>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub effect: Vec<McEffect>,
+    pub note: Vec<McNote>,
+}
+impl Mc {
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file = BufWriter::new(File::create(path).context("create file")?);
+        serde_json::to_writer(file, self).context("write json")?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct McMeta {
+    #[serde(rename = "$ver")]
+    pub ver: i32,
+    pub creator: String,
+    pub background: String,
+    pub version: String,
+    /// Preview start time in milliseconds.
+    pub preview: i32,
+    pub id: i64,
+    pub mode: i32,
+    /// Unix timestamp of the last edit.
+    pub time: i64,
+    pub song: McSong,
+    pub mode_ext: McModeExt,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct McSong {
+    pub title: String,
+    pub artist: String,
+    pub id: i64,
+    /// Original (unicode) title.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub titleorg: String,
+    /// Original (unicode) artist.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub artistorg: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct McModeExt {
+    pub column: i32,
+    pub bar_begin: i32,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct McBpm {
+    pub beat: [i32; 3],
+    pub bpm: f64,
+}
+
+/// A scroll speed change.
+#[derive(Clone, Debug, Serialize)]
+pub struct McEffect {
+    pub beat: [i32; 3],
+    pub scroll: f64,
+}
+
+/// Either a regular note, a long note (with `endbeat`) or the music (with `sound` and `type` 1).
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct McNote {
+    pub beat: [i32; 3],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub endbeat: Option<[i32; 3]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sound: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vol: Option<i32>,
+    /// The time in the music file at which beat 0 happens, in milliseconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none", rename = "type")]
+    pub ty: Option<i32>,
+}
+
+/// Convert a beat into an exact `[whole, numerator, denominator]` triple.
+pub fn beat_triple(beat: BeatPos) -> [i32; 3] {
+    let den = beat.denominator();
+    let whole = beat.as_num().floor();
+    let num = ((beat.as_num() - whole) * den as f64).round() as i32;
+    [whole as i32, num, den]
+}
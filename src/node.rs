@@ -5,25 +5,38 @@ use crate::node::prelude::*;
 mod prelude {
     pub use crate::{
         node::{
-            align::Align, filter::Filter, osuload::OsuLoad, pipe::Pipe, rate::Rate, rekey::Rekey,
+            align::Align, bmsload::BmsLoad, bmswrite::BmsWrite, filter::Filter, join::Join,
+            mcwrite::McWrite, mines::Mines, osuload::OsuLoad, osuwrite::OsuWrite, pipe::Pipe,
+            quaload::QuaLoad, quawrite::QuaWrite, radar::Radar, rate::Rate, rekey::Rekey,
             remap::Remap, select::Select, simfilewrite::SimfileWrite, simultaneous::Simultaneous,
-            space::Space, BucketId, BucketIter, BucketKind,
+            smload::SmLoad, space::Space, split::Split, BucketId, BucketIter, BucketKind,
         },
         prelude::*,
     };
 }
 
 pub mod align;
+pub mod bmsload;
+pub mod bmswrite;
 pub mod filter;
+pub mod join;
+pub mod mcwrite;
+pub mod mines;
 pub mod osuload;
+pub mod osuwrite;
 pub mod pipe;
+pub mod quaload;
+pub mod quawrite;
+pub mod radar;
 pub mod rate;
 pub mod rekey;
 pub mod remap;
 pub mod select;
 pub mod simfilewrite;
 pub mod simultaneous;
+pub mod smload;
 pub mod space;
+pub mod split;
 
 #[derive(Clone, Default)]
 struct Bucket {
@@ -320,7 +333,12 @@ pub fn resolve_buckets(nodes: &[ConcreteNode]) -> Result<Vec<Box<dyn Node>>> {
                 last_magnetic_out = input.map(str::to_string);
             }
             let mut insert_idx = ctx.out.len();
+            //Nodes with several inputs feed the input of the node into all of them
+            let node_input = last_magnetic_out.clone();
             //Resolve each bucket
+            //All automatic outputs of a node, including the outputs of nested output lists, end up
+            //in the same bucket, so a node can route some simfiles through a `Nest` and merge them
+            //back with the rest
             for (kind, bucket) in node.buckets_mut() {
                 let is_chained = match bucket {
                     BucketId::Chain(..) => true,
@@ -330,6 +348,7 @@ pub fn resolve_buckets(nodes: &[ConcreteNode]) -> Result<Vec<Box<dyn Node>>> {
                     BucketId::Auto => match kind {
                         BucketKind::Input => last_magnetic_out
                             .take()
+                            .or_else(|| node_input.clone())
                             .ok_or_else(|| anyhow!("attempt to use input, but previous node does not output (in node {:?})", orig_node))?,
                         BucketKind::Output => magnetic_out
                             .get_or_insert_with(|| ctx.gen_unique_name())
@@ -351,6 +370,7 @@ pub fn resolve_buckets(nodes: &[ConcreteNode]) -> Result<Vec<Box<dyn Node>>> {
                             BucketKind::Input => {
                                 let into_nested = last_magnetic_out
                                     .take()
+                                    .or_else(|| node_input.clone())
                                     .ok_or_else(|| anyhow!("attempt to use input, but previous node does not output (in node {:?})", orig_node))?;
                                 let from_nested = ctx.gen_unique_name();
                                 resolve_layer(ctx, Some(&into_nested), Some(&from_nested), inner_list, is_chained)?;
@@ -418,6 +438,7 @@ pub fn resolve_buckets(nodes: &[ConcreteNode]) -> Result<Vec<Box<dyn Node>>> {
 macro_rules! make_concrete {
     ($($node:ident,)*) => {
         #[derive(Debug, Clone, Serialize, Deserialize)]
+        #[allow(clippy::large_enum_variant)]
         pub enum ConcreteNode {
             $($node($node),)*
         }
@@ -457,7 +478,69 @@ make_concrete!(
     Align,
     Select,
     Rate,
+    Radar,
     Space,
+    Split,
+    Join,
+    Mines,
     OsuLoad,
+    SmLoad,
+    QuaLoad,
+    BmsLoad,
     SimfileWrite,
+    OsuWrite,
+    QuaWrite,
+    BmsWrite,
+    McWrite,
 );
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bucket_names(node: &mut Box<dyn Node>) -> Vec<(String, bool)> {
+        node.buckets_mut()
+            .map(|(_, bucket)| {
+                let (name, take) = bucket.unwrap_resolved();
+                (name.to_string(), take)
+            })
+            .collect()
+    }
+
+    fn load() -> ConcreteNode {
+        SmLoad {
+            input: "songs".into(),
+            ..default()
+        }
+        .into()
+    }
+
+    #[test]
+    fn auto_inputs_share_node_input() {
+        let mut nodes = resolve_buckets(&[load(), Join::default().into()]).unwrap();
+        assert_eq!(nodes.len(), 2);
+        let loaded = bucket_names(&mut nodes[0])[0].0.clone();
+        let join = bucket_names(&mut nodes[1]);
+        //Both inputs read the output of the loader, and only the last read takes it
+        assert_eq!(join[0], (loaded.clone(), false));
+        assert_eq!(join[1], (loaded.clone(), true));
+        assert_ne!(join[2].0, loaded);
+    }
+
+    #[test]
+    fn nested_input_gets_node_input() {
+        let join = Join {
+            second: BucketId::Nest(vec![Rekey::default().into()]),
+            ..default()
+        };
+        let mut nodes = resolve_buckets(&[load(), join.into()]).unwrap();
+        //The nested node runs before the node that uses it
+        assert_eq!(nodes.len(), 3);
+        let loaded = bucket_names(&mut nodes[0])[0].0.clone();
+        let rekey = bucket_names(&mut nodes[1]);
+        let join = bucket_names(&mut nodes[2]);
+        assert_eq!(rekey[0].0, loaded);
+        assert_eq!(join[0].0, loaded);
+        assert_eq!(join[1].0, rekey[1].0);
+    }
+}
@@ -5,6 +5,8 @@ use crate::node::prelude::*;
 pub struct Align {
     pub from: BucketId,
     pub into: BucketId,
+    /// Remove every note that does not fall on a multiple of this many beats.
+    /// Mines and fakes are removed just like steps, instead of being snapped onto the grid.
     pub to: f64,
 }
 impl Default for Align {
@@ -40,13 +42,13 @@ fn align(sm: &mut Simfile, conf: &Align) -> Result<()> {
     for i in 0..sm.notes.len() {
         let note = &mut sm.notes[i];
         if !note.is_tail() && !note.beat.is_aligned(align_to) {
-            let head_key = note.key;
+            let (head_key, head_player) = (note.key, note.player);
             note.key = -1;
             if note.is_head() {
                 //If note is a head, also remove its tail
                 for j in i + 1..sm.notes.len() {
                     let note = &mut sm.notes[j];
-                    if note.key == head_key && note.is_tail() {
+                    if note.key == head_key && note.player == head_player && note.is_tail() {
                         note.key = -1;
                         break;
                     }
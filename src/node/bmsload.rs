@@ -0,0 +1,113 @@
+//! Take a folder of BMS songs and parse their `.bms`, `.bme` and `.bml` charts.
+
+use crate::{
+    bmsfile,
+    node::{prelude::*, smload::group_by_song},
+};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BmsLoad {
+    /// Into what bucket to output the loaded simfiles.
+    pub into: BucketId,
+    /// The input folder, containing one subfolder per song.
+    pub input: String,
+    /// Only load charts in these gamemodes (`BmSingle5`, `BmSingle7`, `BmDouble5` or
+    /// `BmDouble7`).
+    /// If empty, all gamemodes are loaded.
+    pub gamemodes: Vec<Gamemode>,
+}
+
+impl Default for BmsLoad {
+    fn default() -> Self {
+        Self {
+            into: default(),
+            input: "".into(),
+            gamemodes: vec![],
+        }
+    }
+}
+
+impl Node for BmsLoad {
+    fn prepare(&mut self) -> Result<()> {
+        if self.input.is_empty() {
+            eprintln!();
+            eprintln!("drag and drop your BMS song folder into this window, then press enter");
+            self.input = crate::read_path_from_stdin()?;
+        }
+        info!("scanning for charts in \"{}\"", self.input);
+        Ok(())
+    }
+    fn apply(&self, _store: &mut SimfileStore) -> Result<()> {
+        Ok(())
+    }
+    fn buckets_mut(&mut self) -> BucketIter<'_> {
+        Box::new(iter::once((BucketKind::Output, &mut self.into)))
+    }
+    fn entry(
+        &self,
+        store: &mut SimfileStore,
+        on_bmset: &mut dyn FnMut(&mut SimfileStore) -> Result<()>,
+    ) -> Result<()> {
+        //Group charts by song folder
+        let by_song = group_by_song(&self.input, |path| {
+            path.extension()
+                .map(|ext| {
+                    ["bms", "bme", "bml"]
+                        .iter()
+                        .any(|chart_ext| ext.eq_ignore_ascii_case(chart_ext))
+                })
+                .unwrap_or(false)
+        });
+        for (song_path, bms_paths) in by_song {
+            if let Err(err) = process_song(self, store, &song_path, &bms_paths, on_bmset) {
+                error!(
+                    "  error processing song at \"{}\": {:#}",
+                    song_path.display(),
+                    err
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+fn process_song(
+    conf: &BmsLoad,
+    store: &mut SimfileStore,
+    song_path: &Path,
+    bms_paths: &[PathBuf],
+    on_bmset: &mut dyn FnMut(&mut SimfileStore) -> Result<()>,
+) -> Result<()> {
+    info!("processing \"{}\":", song_path.display());
+    let mut simfiles = Vec::new();
+    for bms_path in bms_paths {
+        let bms_name = bms_path.file_name().unwrap_or_default().to_string_lossy();
+        match bmsfile::load(bms_path) {
+            Ok(sm) => {
+                if conf.gamemodes.is_empty() || conf.gamemodes.contains(&sm.gamemode) {
+                    debug!("  loaded chart \"{}\" as {}", bms_name, sm.gamemode.id());
+                    simfiles.push(Box::new(sm));
+                } else {
+                    debug!("  skipping {} chart \"{}\"", sm.gamemode.id(), bms_name);
+                }
+            }
+            Err(err) => {
+                error!("  error processing chart \"{}\": {:#}", bms_name, err);
+            }
+        }
+    }
+    //Report song
+    store.reset();
+    store.global_set("root", conf.input.clone());
+    store.global_set(
+        "base",
+        song_path
+            .to_str()
+            .ok_or(anyhow!("non utf-8 song path \"{}\"", song_path.display()))?
+            .to_string(),
+    );
+    store.put(&conf.into, simfiles);
+    on_bmset(store)?;
+    Ok(())
+}
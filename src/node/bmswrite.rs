@@ -0,0 +1,90 @@
+//! Takes a bunch of simfiles as input and writes them out as BMS charts.
+
+use crate::{
+    bmsfile,
+    node::{
+        prelude::*,
+        simfilewrite::{
+            chart_display_name, copy_set_deps, resolve_out_dir, sanitize_file_name,
+            unique_file_name, CopyMethod,
+        },
+    },
+};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BmsWrite {
+    pub from: BucketId,
+    /// The path to the output BMS song folder.
+    pub output: String,
+    /// Which methods to try for copying "dependency" files, such as `.mp3` and `.jpg` files.
+    pub copy: Vec<CopyMethod>,
+}
+
+impl Default for BmsWrite {
+    fn default() -> Self {
+        Self {
+            from: default(),
+            output: "".into(),
+            copy: vec![
+                CopyMethod::Hardlink,
+                CopyMethod::Copy,
+                CopyMethod::AssertIdentical,
+            ],
+        }
+    }
+}
+
+impl Node for BmsWrite {
+    fn prepare(&mut self) -> Result<()> {
+        if self.output.is_empty() {
+            eprintln!();
+            eprintln!("drag and drop your BMS song folder into this window, then press enter");
+            self.output = crate::read_path_from_stdin()?;
+        }
+        info!("outputting charts in \"{}\"", self.output);
+        Ok(())
+    }
+    fn apply(&self, store: &mut SimfileStore) -> Result<()> {
+        //Serialize every chart in memory, skipping the ones that BMS cannot represent
+        let mut simfiles = Vec::new();
+        let mut charts = Vec::new();
+        store.get_each(&self.from, |_, sm| {
+            let mut raw = Vec::new();
+            match bmsfile::write_to(&sm, &mut raw) {
+                Ok(()) => {
+                    charts.push(raw);
+                    simfiles.push(sm);
+                }
+                Err(err) => warn!("  skipping chart: {:#}", err),
+            }
+            Ok(())
+        })?;
+        if simfiles.is_empty() {
+            return Ok(());
+        }
+        let out_base = resolve_out_dir(store, &self.output)?;
+        let mut used_names = HashSet::default();
+        for (sm, raw) in simfiles.iter().zip(charts) {
+            let file_name = unique_file_name(
+                &mut used_names,
+                &format!("{} - {} [{}]", sm.artist, sm.title, chart_display_name(sm)),
+            );
+            let file_name = sanitize_file_name(&format!(
+                "{}.{}",
+                file_name,
+                bmsfile::extension(sm.gamemode.key_count())
+            ));
+            let out_path = out_base.join(file_name);
+            debug!("  writing chart to \"{}\"", out_path.display());
+            fs::write(&out_path, raw)
+                .with_context(|| anyhow!("write chart to \"{}\"", out_path.display()))?;
+        }
+        //Copy over dependencies (backgrounds, audio, etc...)
+        copy_set_deps(store, &self.copy, &out_base, &simfiles)?;
+        Ok(())
+    }
+    fn buckets_mut(&mut self) -> BucketIter<'_> {
+        Box::new(iter::once((BucketKind::Input, &mut self.from)))
+    }
+}
@@ -1,10 +1,18 @@
-use crate::node::prelude::*;
+use crate::node::{prelude::*, rate};
+use regex::Regex;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(default)]
 pub struct Filter {
     pub from: BucketId,
+    /// Where the simfiles that pass all `ops` go.
     pub into: BucketId,
+    /// Where the simfiles that fail any of the `ops` go.
+    /// Dropped by default.
+    ///
+    /// Usually a `Nest` that processes the rejected simfiles separately before merging them back
+    /// into `into`.
+    pub rejected: BucketId,
     pub ops: Vec<(Property, FilterOp)>,
 }
 impl Default for Filter {
@@ -12,6 +20,7 @@ impl Default for Filter {
         Self {
             from: default(),
             into: default(),
+            rejected: BucketId::Null,
             ops: vec![],
         }
     }
@@ -39,6 +48,39 @@ pub enum Property {
     Desc,
     Difficulty,
     Meter,
+    /// A skillset rating, as computed by `RateMethod::Skillsets`.
+    Skillset(Skillset),
+    /// The file name of the source chart (eg. the `.osu` file).
+    SourceFile,
+    BeatmapId,
+    BeatmapSetId,
+    /// The difficulty name of the source beatmap.
+    Version,
+    /// The creator of the source beatmapset.
+    Creator,
+    /// The guest mapper of the source beatmap, or empty if it is not a guest difficulty.
+    Guest,
+    /// The guest mapper of the source beatmap, or the creator if it is not a guest difficulty.
+    Mapper,
+    Source,
+    Tags,
+    HpDrain,
+    CircleSize,
+    OverallDifficulty,
+    /// The osu!mania star rating of the source beatmap.
+    /// Taken from `osu!.db` when possible, approximated with the `Strain` method otherwise.
+    Stars,
+    /// The time between the first and last notes, in seconds.
+    Length,
+    /// The amount of notes that have to be hit.
+    NoteCount,
+    /// The fraction of notes that are holds or rolls, between `0` and `1`.
+    HoldRatio,
+    /// The most notes hit within any 1-second window.
+    PeakNps,
+    MinBpm,
+    MaxBpm,
+    KeyCount,
 }
 impl Property {
     fn get<'a>(&self, sm: &'a Simfile) -> Cow<'a, str> {
@@ -84,16 +126,120 @@ impl Property {
             Desc => Cow::Borrowed(&sm.desc),
             Difficulty => Cow::Owned(format!("{:?}", sm.difficulty)),
             Meter => Cow::Owned(sm.difficulty_num.to_string()),
+            Property::Skillset(skillset) => Cow::Owned(sm.skillsets.get(*skillset).to_string()),
+            SourceFile => sm
+                .source_meta
+                .path
+                .as_deref()
+                .and_then(Path::file_name)
+                .map(OsStr::to_string_lossy)
+                .unwrap_or_default(),
+            BeatmapId => opt_to_string(sm.source_meta.beatmap_id),
+            BeatmapSetId => opt_to_string(sm.source_meta.set_id),
+            Version => Cow::Borrowed(&sm.source_meta.version),
+            Creator => Cow::Borrowed(&sm.source_meta.creator),
+            Guest => Cow::Borrowed(sm.source_meta.guest.as_deref().unwrap_or_default()),
+            Mapper => Cow::Borrowed(sm.source_meta.mapper()),
+            Source => Cow::Borrowed(&sm.source_meta.source),
+            Tags => Cow::Borrowed(&sm.source_meta.tags),
+            HpDrain => opt_to_string(sm.source_meta.hp_drain),
+            CircleSize => opt_to_string(sm.source_meta.circle_size),
+            OverallDifficulty => opt_to_string(sm.source_meta.overall_difficulty),
+            Stars => opt_to_string(rate::source_stars(sm)),
+            Length => {
+                let mut to_time = sm.beat_to_time();
+                let mut steps = sm
+                    .notes
+                    .iter()
+                    .filter(|note| note.is_step() || note.is_tail());
+                let len = match (steps.next(), steps.next_back()) {
+                    (Some(first), Some(last)) => {
+                        let first = to_time.beat_to_time(first.beat);
+                        to_time.beat_to_time(last.beat) - first
+                    }
+                    _ => 0.,
+                };
+                Cow::Owned(len.to_string())
+            }
+            NoteCount => Cow::Owned(
+                sm.notes
+                    .iter()
+                    .filter(|note| note.is_step())
+                    .count()
+                    .to_string(),
+            ),
+            HoldRatio => {
+                let steps = sm.notes.iter().filter(|note| note.is_step()).count();
+                let holds = sm.notes.iter().filter(|note| note.is_head()).count();
+                let ratio = if steps > 0 {
+                    holds as f64 / steps as f64
+                } else {
+                    0.
+                };
+                Cow::Owned(ratio.to_string())
+            }
+            PeakNps => {
+                let mut to_time = sm.beat_to_time();
+                let times = sm
+                    .notes
+                    .iter()
+                    .filter(|note| note.is_step())
+                    .map(|note| to_time.beat_to_time(note.beat))
+                    .collect::<Vec<_>>();
+                let mut peak = 0;
+                let mut window_start = 0;
+                for (idx, &time) in times.iter().enumerate() {
+                    while time - times[window_start] >= 1. {
+                        window_start += 1;
+                    }
+                    peak = peak.max(idx + 1 - window_start);
+                }
+                Cow::Owned(peak.to_string())
+            }
+            MinBpm => opt_to_string(
+                sm.bpms
+                    .iter()
+                    .map(|cp| SortableFloat(cp.bpm()))
+                    .min()
+                    .map(|bpm| bpm.0),
+            ),
+            MaxBpm => opt_to_string(
+                sm.bpms
+                    .iter()
+                    .map(|cp| SortableFloat(cp.bpm()))
+                    .max()
+                    .map(|bpm| bpm.0),
+            ),
+            KeyCount => Cow::Owned(sm.gamemode.key_count().to_string()),
         }
     }
 }
 
+/// Missing values are empty strings.
+fn opt_to_string<T: ToString>(val: Option<T>) -> Cow<'static, str> {
+    val.map(|val| Cow::Owned(val.to_string()))
+        .unwrap_or_default()
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum FilterOp {
     Allow(Vec<String>),
     Deny(Vec<String>),
+    /// Compare as text, in natural order.
     LessThan(String),
+    /// Compare as text, in natural order.
     GreaterThan(String),
+    /// Compare as a number.
+    /// Values that are not numbers never match.
+    Below(f64),
+    /// Compare as a number.
+    /// Values that are not numbers never match.
+    Above(f64),
+    /// Whether the value is a number between the given bounds, inclusive.
+    Between(f64, f64),
+    /// Whether the value matches a regular expression anywhere.
+    /// Use `^` and `$` to match the entire value.
+    Matches(Pattern),
     Not(Box<FilterOp>),
     And(Vec<FilterOp>),
     Or(Vec<FilterOp>),
@@ -110,6 +256,12 @@ impl FilterOp {
                 .any(|w| natord::compare_ignore_case(w, val) == cmp::Ordering::Equal),
             LessThan(top) => natord::compare_ignore_case(val, top) == cmp::Ordering::Less,
             GreaterThan(top) => natord::compare_ignore_case(val, top) == cmp::Ordering::Greater,
+            Below(top) => parse_num(val).map(|val| val < *top).unwrap_or(false),
+            Above(bottom) => parse_num(val).map(|val| val > *bottom).unwrap_or(false),
+            Between(bottom, top) => parse_num(val)
+                .map(|val| val >= *bottom && val <= *top)
+                .unwrap_or(false),
+            Matches(pattern) => pattern.0.is_match(val),
             Not(op) => !op.matches(val),
             And(ops) => ops.iter().all(|op| op.matches(val)),
             Or(ops) => ops.iter().any(|op| op.matches(val)),
@@ -117,18 +269,53 @@ impl FilterOp {
     }
 }
 
+fn parse_num(val: &str) -> Option<f64> {
+    val.trim().parse::<f64>().ok().filter(|val| !val.is_nan())
+}
+
+/// A regular expression, compiled when the configuration is loaded.
+#[derive(Clone, Debug)]
+pub struct Pattern(pub Regex);
+impl Serialize for Pattern {
+    fn serialize<S: serde::Serializer>(&self, ser: S) -> std::result::Result<S::Ok, S::Error> {
+        ser.serialize_str(self.0.as_str())
+    }
+}
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: serde::Deserializer<'de>>(de: D) -> std::result::Result<Self, D::Error> {
+        let src = String::deserialize(de)?;
+        Regex::new(&src)
+            .map(Pattern)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
 impl Node for Filter {
+    fn prepare(&mut self) -> Result<()> {
+        //Both outputs set to `Auto` resolve into the same bucket, undoing the filter
+        let into = self.into.unwrap_name();
+        ensure!(
+            into.is_empty() || into != self.rejected.unwrap_name(),
+            "filter outputs accepted and rejected simfiles into the same bucket, so it would do \
+            nothing (set `rejected` to `Null` or to a different bucket)"
+        );
+        Ok(())
+    }
     fn apply(&self, store: &mut SimfileStore) -> Result<()> {
         store.get(&self.from, |store, list| {
-            list.retain(|sm| self.ops.iter().all(|(prop, op)| op.matches(&*prop.get(sm))));
-            store.put(&self.into, mem::replace(list, Vec::new()));
+            let (kept, rejected): (Vec<_>, Vec<_>) = mem::take(list)
+                .into_iter()
+                .partition(|sm| self.ops.iter().all(|(prop, op)| op.matches(&*prop.get(sm))));
+            store.put(&self.into, kept);
+            store.put(&self.rejected, rejected);
             Ok(())
         })
     }
     fn buckets_mut<'a>(&'a mut self) -> BucketIter<'a> {
         Box::new(
             iter::once((BucketKind::Input, &mut self.from))
-                .chain(iter::once((BucketKind::Output, &mut self.into))),
+                .chain(iter::once((BucketKind::Output, &mut self.into)))
+                .chain(iter::once((BucketKind::Output, &mut self.rejected))),
         )
     }
 }
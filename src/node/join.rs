@@ -0,0 +1,220 @@
+//! Join pairs of charts side by side into a single wider chart, for example two 4K charts into a
+//! single `DanceDouble` chart.
+
+use crate::node::prelude::*;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Join {
+    /// The charts that go on the left side.
+    pub first: BucketId,
+    /// The charts that go on the right side.
+    /// Usually a `Nest` that transforms the input (eg. mirroring it or selecting another
+    /// difficulty), since both inputs receive the same simfiles unless told otherwise.
+    ///
+    /// Each chart is paired with the chart from the other side that was converted from the same
+    /// source chart with the same difficulty.
+    /// Charts left without such a match are paired in order of difficulty, easiest first.
+    pub second: BucketId,
+    pub into: BucketId,
+    /// The gamemode of the joined charts.
+    /// Its keycount must be the sum of the keycounts of each pair of charts.
+    pub gamemode: Gamemode,
+}
+impl Default for Join {
+    fn default() -> Self {
+        Self {
+            first: default(),
+            second: default(),
+            into: default(),
+            gamemode: Gamemode::DanceDouble,
+        }
+    }
+}
+
+impl Node for Join {
+    fn apply(&self, store: &mut SimfileStore) -> Result<()> {
+        let mut firsts = Vec::new();
+        store.get_each(&self.first, |_, sm| {
+            firsts.push(sm);
+            Ok(())
+        })?;
+        let mut seconds = Vec::new();
+        store.get_each(&self.second, |_, sm| {
+            seconds.push(sm);
+            Ok(())
+        })?;
+        if firsts.len() != seconds.len() {
+            warn!(
+                "  joining {} charts with {} charts, unpaired charts will be dropped",
+                firsts.len(),
+                seconds.len()
+            );
+        }
+        let mut out = Vec::with_capacity(firsts.len().min(seconds.len()));
+        for (mut first, second) in pair_up(firsts, seconds) {
+            match join(&mut first, &second, self) {
+                Ok(()) => out.push(first),
+                Err(err) => warn!(
+                    "  failed to join charts \"{}\" and \"{}\": {:#}",
+                    first.desc, second.desc, err
+                ),
+            }
+        }
+        store.put(&self.into, out);
+        Ok(())
+    }
+    fn buckets_mut(&mut self) -> BucketIter<'_> {
+        Box::new(
+            iter::once((BucketKind::Input, &mut self.first))
+                .chain(iter::once((BucketKind::Input, &mut self.second)))
+                .chain(iter::once((BucketKind::Output, &mut self.into))),
+        )
+    }
+}
+
+/// Pair charts converted from the same source chart, and then pair the leftovers from easiest to
+/// hardest.
+fn pair_up(
+    firsts: impl IntoIterator<Item = Box<Simfile>>,
+    seconds: impl IntoIterator<Item = Box<Simfile>>,
+) -> Vec<(Box<Simfile>, Box<Simfile>)> {
+    let by_difficulty = |sm: &Simfile| (sm.difficulty as u8, SortableFloat(sm.difficulty_num));
+    let mut firsts = firsts.into_iter().collect::<Vec<_>>();
+    let mut seconds = seconds.into_iter().collect::<Vec<_>>();
+    firsts.sort_by_key(|sm| by_difficulty(sm));
+    seconds.sort_by_key(|sm| by_difficulty(sm));
+    let same_source = |a: &Simfile, b: &Simfile| {
+        a.source_meta.path.is_some()
+            && a.source_meta.path == b.source_meta.path
+            && a.difficulty == b.difficulty
+            && a.desc == b.desc
+    };
+    let mut seconds = seconds.into_iter().map(Some).collect::<Vec<_>>();
+    let mut pairs = Vec::new();
+    let mut leftovers = Vec::new();
+    for first in firsts {
+        let matching = seconds.iter_mut().find(|second| {
+            second
+                .as_deref()
+                .map(|second| same_source(&first, second))
+                .unwrap_or(false)
+        });
+        match matching.and_then(Option::take) {
+            Some(second) => pairs.push((first, second)),
+            None => leftovers.push(first),
+        }
+    }
+    pairs.extend(leftovers.into_iter().zip(seconds.into_iter().flatten()));
+    pairs
+}
+
+/// Place the notes of `second` to the right of the notes of `first`.
+fn join(first: &mut Simfile, second: &Simfile, conf: &Join) -> Result<()> {
+    let key_offset = first.gamemode.key_count();
+    ensure!(
+        key_offset + second.gamemode.key_count() == conf.gamemode.key_count(),
+        "cannot join {} and {} into {}, keycounts do not add up",
+        first.gamemode.id(),
+        second.gamemode.id(),
+        conf.gamemode.id()
+    );
+    ensure!(
+        first.bpms == second.bpms
+            && first.offset == second.offset
+            && first.stops == second.stops
+            && first.delays == second.delays
+            && first.warps == second.warps,
+        "charts have different timing"
+    );
+    trace!(
+        "    joining {} and {} into {}",
+        first.gamemode.id(),
+        second.gamemode.id(),
+        conf.gamemode.id()
+    );
+    //Share a single keysound list
+    let keysound_map = second
+        .keysounds
+        .iter()
+        .map(
+            |path| match first.keysounds.iter().position(|ks| ks == path) {
+                Some(idx) => idx as u32,
+                None => {
+                    first.keysounds.push(path.clone());
+                    first.keysounds.len() as u32 - 1
+                }
+            },
+        )
+        .collect::<Vec<_>>();
+    //Merge notes in beat order
+    let mut notes = mem::take(&mut first.notes);
+    notes.extend(second.notes.iter().map(|note| {
+        Note {
+            key: note.key + key_offset,
+            keysound: note
+                .keysound
+                .and_then(|idx| keysound_map.get(idx as usize).copied()),
+            ..note.clone()
+        }
+    }));
+    notes.sort_by_key(|note| note.beat);
+    first.notes = notes;
+    first.fix_tails()?;
+    first.gamemode = conf.gamemode;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chart(source: &str, difficulty: Difficulty) -> Box<Simfile> {
+        let mut sm = Simfile {
+            difficulty,
+            desc: format!("{:?}", difficulty),
+            ..default()
+        };
+        sm.source_meta.path = Some(source.into());
+        Box::new(sm)
+    }
+
+    fn names(pairs: &[(Box<Simfile>, Box<Simfile>)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(a, b)| (a.desc.clone(), b.desc.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn pairs_same_source() {
+        use Difficulty::*;
+        //One of the transformed charts was dropped, and the rest are in another order
+        let firsts = vec![chart("a", Hard), chart("b", Easy), chart("c", Medium)];
+        let seconds = vec![chart("c", Medium), chart("a", Hard)];
+        let pairs = pair_up(firsts, seconds);
+        assert_eq!(
+            names(&pairs),
+            vec![
+                ("Medium".to_string(), "Medium".to_string()),
+                ("Hard".to_string(), "Hard".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn pairs_leftovers_by_difficulty() {
+        use Difficulty::*;
+        //The right side is another difficulty of the same song
+        let firsts = vec![chart("b", Hard), chart("a", Easy)];
+        let seconds = vec![chart("d", Challenge), chart("c", Medium)];
+        let pairs = pair_up(firsts, seconds);
+        assert_eq!(
+            names(&pairs),
+            vec![
+                ("Easy".to_string(), "Medium".to_string()),
+                ("Hard".to_string(), "Challenge".to_string()),
+            ]
+        );
+    }
+}
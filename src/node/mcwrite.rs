@@ -0,0 +1,309 @@
+//! Takes a bunch of simfiles as input and writes them out as Malody `.mc` charts.
+
+use crate::{
+    mcfile::{self, Mc, McBpm, McEffect, McMeta, McModeExt, McNote, McSong},
+    node::{
+        prelude::*,
+        simfilewrite::{
+            chart_display_name, copy_set_deps, or_trans, path_str, resolve_out_dir,
+            sanitize_file_name, unique_file_name, CopyMethod,
+        },
+    },
+};
+
+/// Malody has no stops, delays or warps.
+/// Stops and delays are written as very slow bpm segments one beat subdivision long, which keeps
+/// notes in sync, but the scroll speed during a stop will not match the source chart.
+/// Warps are dropped entirely, so any chart with warps is lossy.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct McWrite {
+    pub from: BucketId,
+    /// The path to the output Malody song folder.
+    pub output: String,
+    /// Which methods to try for copying "dependency" files, such as `.mp3` and `.jpg` files.
+    pub copy: Vec<CopyMethod>,
+}
+
+impl Default for McWrite {
+    fn default() -> Self {
+        Self {
+            from: default(),
+            output: "".into(),
+            copy: vec![
+                CopyMethod::Hardlink,
+                CopyMethod::Copy,
+                CopyMethod::AssertIdentical,
+            ],
+        }
+    }
+}
+
+impl Node for McWrite {
+    fn prepare(&mut self) -> Result<()> {
+        if self.output.is_empty() {
+            eprintln!();
+            eprintln!("drag and drop your Malody song folder into this window, then press enter");
+            self.output = crate::read_path_from_stdin()?;
+        }
+        info!("outputting charts in \"{}\"", self.output);
+        Ok(())
+    }
+    fn apply(&self, store: &mut SimfileStore) -> Result<()> {
+        //Convert every chart, skipping the ones that Malody cannot represent
+        let mut simfiles = Vec::new();
+        let mut mcs = Vec::new();
+        store.get_each(&self.from, |_, sm| {
+            match to_mc(&sm) {
+                Ok(mc) => {
+                    mcs.push(mc);
+                    simfiles.push(sm);
+                }
+                Err(err) => warn!("  skipping chart: {:#}", err),
+            }
+            Ok(())
+        })?;
+        if simfiles.is_empty() {
+            return Ok(());
+        }
+        let out_base = resolve_out_dir(store, &self.output)?;
+        //Write one chart per simfile
+        let mut used_names = HashSet::default();
+        for mut mc in mcs {
+            mc.meta.version = unique_file_name(&mut used_names, &mc.meta.version);
+            let filename = sanitize_file_name(&format!(
+                "{} - {} [{}].mc",
+                mc.meta.song.artist, mc.meta.song.title, mc.meta.version
+            ));
+            let out_path = out_base.join(filename);
+            debug!("  writing chart to \"{}\"", out_path.display());
+            mc.save(&out_path)
+                .with_context(|| anyhow!("write chart to \"{}\"", out_path.display()))?;
+        }
+        //Copy over dependencies (backgrounds, audio, etc...)
+        copy_set_deps(store, &self.copy, &out_base, &simfiles)?;
+        Ok(())
+    }
+    fn buckets_mut(&mut self) -> BucketIter<'_> {
+        Box::new(iter::once((BucketKind::Input, &mut self.from)))
+    }
+}
+
+fn to_mc(sm: &Simfile) -> Result<Mc> {
+    ensure!(!sm.bpms.is_empty(), "simfile has no bpms");
+    ensure!(
+        (4..=10).contains(&sm.gamemode.key_count()) && !sm.gamemode.is_routine(),
+        "cannot write {} chart \"{} - {}\": Malody only supports single-player 4K to 10K charts",
+        sm.gamemode.id(),
+        sm.artist,
+        sm.title,
+    );
+    let name = chart_display_name(sm);
+    let unicode_or_empty = |text: &str, trans: &str| {
+        if trans.is_empty() || trans == text {
+            String::new()
+        } else {
+            text.to_string()
+        }
+    };
+    let mut mc = Mc {
+        meta: McMeta {
+            ver: 0,
+            creator: sm.credit.clone(),
+            background: path_str(&sm.background),
+            version: name.to_string(),
+            preview: sm
+                .sample_start
+                .map(|start| (start * 1000.).round() as i32)
+                .unwrap_or(0),
+            id: 0,
+            mode: mcfile::MODE_KEY,
+            //Keep the output the same across runs
+            time: 0,
+            song: McSong {
+                title: or_trans(&sm.title, &sm.title_trans),
+                artist: or_trans(&sm.artist, &sm.artist_trans),
+                id: 0,
+                titleorg: unicode_or_empty(&sm.title, &sm.title_trans),
+                artistorg: unicode_or_empty(&sm.artist, &sm.artist_trans),
+            },
+            mode_ext: McModeExt {
+                column: sm.gamemode.key_count(),
+                bar_begin: 0,
+            },
+        },
+        time: to_mc_timing(sm),
+        effect: sm
+            .scrolls
+            .iter()
+            .map(|&(beat, scroll)| McEffect {
+                beat: mcfile::beat_triple(BeatPos::from(beat)),
+                scroll,
+            })
+            .collect(),
+        note: Vec::new(),
+    };
+
+    //Convert notes, pairing long note heads with their tails and dropping mines and fakes
+    let key_count = sm.gamemode.key_count();
+    let mut pending_heads: Vec<Option<usize>> = vec![None; key_count as usize];
+    for note in sm.notes.iter() {
+        if note.is_step() {
+            if note.is_head() {
+                pending_heads[note.key as usize] = Some(mc.note.len());
+            }
+            let sound = note
+                .keysound
+                .and_then(|idx| sm.keysounds.get(idx as usize))
+                .map(|path| path.to_string_lossy().into_owned());
+            mc.note.push(McNote {
+                beat: mcfile::beat_triple(note.beat),
+                column: Some(note.key),
+                vol: sound.as_ref().map(|_| 100),
+                sound,
+                ..default()
+            });
+        } else if note.is_tail() {
+            let head_idx = pending_heads[note.key as usize]
+                .take()
+                .ok_or_else(|| anyhow!("tail at beat {} has no head", note.beat))?;
+            mc.note[head_idx].endbeat = Some(mcfile::beat_triple(note.beat));
+        }
+    }
+    //The music is a special sound note, offset so that beat 0 lines up with the simfile
+    if let Some(music) = &sm.music {
+        mc.note.push(McNote {
+            beat: [0, 0, 1],
+            sound: Some(music.to_string_lossy().into_owned()),
+            vol: Some(100),
+            offset: Some(sm.beat_to_time().beat_to_time(BeatPos::from(0.)) * 1000.),
+            ty: Some(1),
+            ..default()
+        });
+    }
+    Ok(mc)
+}
+
+/// Malody has no stops, so turn stops and delays into very slow bpm segments that take up the
+/// same amount of time.
+///
+/// Each segment is as short as the finest beat subdivision, so no note or bpm change can fall
+/// inside of one.
+fn to_mc_timing(sm: &Simfile) -> Vec<McBpm> {
+    if !sm.warps.is_empty() {
+        warn!("    Malody charts cannot have warps, notes after them will be late");
+    }
+    let beat_len_at = |beat: BeatPos| {
+        sm.bpms
+            .iter()
+            .take_while(|cp| cp.beat <= beat)
+            .last()
+            .unwrap_or(&sm.bpms[0])
+            .beat_len
+    };
+    //Stops pause after the notes on their beat, delays pause before them
+    //Stops and delays that share a segment are added together
+    let mut segments: Vec<(BeatPos, f64)> = sm
+        .stops
+        .iter()
+        .map(|&(beat, secs)| (BeatPos::from(beat), secs))
+        .chain(
+            sm.delays
+                .iter()
+                .map(|&(beat, secs)| (BeatPos::from(beat) - BeatPos::EPSILON, secs)),
+        )
+        .filter(|&(_, secs)| secs > 0.)
+        .collect();
+    segments.sort_by_key(|&(start, _)| start);
+    segments.dedup_by(|next, prev| {
+        if next.0 == prev.0 {
+            prev.1 += next.1;
+            true
+        } else {
+            false
+        }
+    });
+    //Segments override plain bpm changes on the same beat
+    let mut time: Vec<(BeatPos, bool, f64)> = sm
+        .bpms
+        .iter()
+        .map(|cp| (cp.beat, false, cp.bpm()))
+        .collect();
+    for &(start, secs) in segments.iter() {
+        let step = BeatPos::EPSILON.as_num();
+        let len = step * beat_len_at(start) + secs;
+        time.push((start, true, 60. * step / len));
+        let end = start + BeatPos::EPSILON;
+        time.push((end, false, 60. / beat_len_at(end)));
+    }
+    time.sort_by_key(|&(beat, is_segment, _)| (beat, is_segment));
+    let mut merged: Vec<(BeatPos, f64)> = Vec::with_capacity(time.len());
+    for (beat, _, bpm) in time {
+        match merged.last_mut() {
+            Some(last) if last.0 == beat => last.1 = bpm,
+            _ => merged.push((beat, bpm)),
+        }
+    }
+    merged
+        .into_iter()
+        .map(|(beat, bpm)| McBpm {
+            beat: mcfile::beat_triple(beat),
+            bpm,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The time at which a beat is reached with the given Malody bpm list.
+    fn mc_time(time: &[McBpm], beat: f64) -> f64 {
+        let as_beat = |triple: [i32; 3]| triple[0] as f64 + triple[1] as f64 / triple[2] as f64;
+        let mut secs = 0.;
+        for (idx, cp) in time.iter().enumerate() {
+            let start = as_beat(cp.beat);
+            let end = time
+                .get(idx + 1)
+                .map(|next| as_beat(next.beat))
+                .unwrap_or(f64::INFINITY)
+                .min(beat);
+            if end > start {
+                secs += (end - start) * 60. / cp.bpm;
+            }
+        }
+        secs
+    }
+
+    #[test]
+    fn stops_and_delays_keep_sync() {
+        let sm = Simfile {
+            bpms: vec![
+                ControlPoint {
+                    beat: BeatPos::from(0.),
+                    beat_len: 0.5,
+                },
+                ControlPoint {
+                    beat: BeatPos::from(3.),
+                    beat_len: 0.25,
+                },
+            ],
+            stops: vec![(2., 1.)],
+            delays: vec![(2., 0.5), (3., 0.25)],
+            ..default()
+        };
+        let time = to_mc_timing(&sm);
+        let mut to_time = sm.beat_to_time();
+        for &beat in [1., 2., 2. + 1. / 48., 3. - 1. / 48., 3., 4.].iter() {
+            let expected = to_time.beat_to_time(BeatPos::from(beat));
+            let actual = mc_time(&time, beat);
+            assert!(
+                (expected - actual).abs() < 1e-9,
+                "beat {}: expected {}, got {}",
+                beat,
+                expected,
+                actual
+            );
+        }
+    }
+}
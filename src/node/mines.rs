@@ -0,0 +1,158 @@
+//! Place mines in the gaps between notes.
+
+use crate::node::{prelude::*, rekey::KeyAlloc};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Mines {
+    pub from: BucketId,
+    pub into: BucketId,
+    /// Mines are placed on a grid with this spacing, in beats.
+    pub unit: f64,
+    /// The chance of placing a mine on each grid position that has no notes.
+    pub chance: f64,
+    /// The minimum distance between a mine and any note or hold on the same key, in beats.
+    pub clearance: f64,
+    /// Similar to `Rekey::weight_curve`.
+    /// By default, keys that were just hit are more likely to get a mine, like in hand-made
+    /// charts.
+    pub weight_curve: Vec<(f32, f32)>,
+}
+impl Default for Mines {
+    fn default() -> Self {
+        Self {
+            from: default(),
+            into: default(),
+            unit: 0.5,
+            chance: 0.1,
+            clearance: 0.5,
+            weight_curve: vec![(0., 300.), (0.4, 200.), (0.8, 10.), (1.4, 1.)],
+        }
+    }
+}
+
+impl Node for Mines {
+    fn prepare(&mut self) -> Result<()> {
+        ensure!(
+            self.unit.is_finite() && self.unit > 0.,
+            "mine unit must be a positive amount of beats"
+        );
+        ensure!(
+            (0. ..=1.).contains(&self.chance),
+            "mine chance must be between 0 and 1"
+        );
+        Ok(())
+    }
+    fn apply(&self, store: &mut SimfileStore) -> Result<()> {
+        store.get(&self.from, |store, list| {
+            for sm in list.iter_mut() {
+                place_mines(sm, self);
+            }
+            store.put(&self.into, mem::replace(list, default()));
+            Ok(())
+        })
+    }
+    fn buckets_mut(&mut self) -> BucketIter<'_> {
+        Box::new(
+            iter::once((BucketKind::Input, &mut self.from))
+                .chain(iter::once((BucketKind::Output, &mut self.into))),
+        )
+    }
+}
+
+fn place_mines(sm: &mut Simfile, conf: &Mines) {
+    let key_count = sm.gamemode.key_count() as usize;
+    let clearance = BeatPos::from(conf.clearance.max(0.));
+    //Find the beat ranges around notes and holds where each key cannot get a mine
+    let mut busy = vec![Vec::new(); key_count];
+    let mut open_heads = vec![None; key_count];
+    for note in sm.notes.iter() {
+        let key = note.key as usize;
+        if note.is_head() {
+            open_heads[key] = Some(note.beat);
+        } else if note.is_tail() {
+            let start = open_heads[key].take().unwrap_or(note.beat);
+            busy[key].push((start - clearance, note.beat + clearance));
+        } else {
+            busy[key].push((note.beat - clearance, note.beat + clearance));
+        }
+    }
+    for ranges in busy.iter_mut() {
+        ranges.sort_by_key(|&(start, _end)| start);
+    }
+    let mut busy_idx = vec![0; key_count];
+    let is_free = |busy_idx: &mut Vec<usize>, key: usize, beat: BeatPos| {
+        let ranges = &busy[key];
+        while busy_idx[key] < ranges.len() && ranges[busy_idx[key]].1 < beat {
+            busy_idx[key] += 1;
+        }
+        ranges[busy_idx[key]..]
+            .iter()
+            .take_while(|&&(start, _end)| start <= beat)
+            .all(|&(_start, end)| end < beat)
+    };
+
+    //Only place mines between the first and last notes
+    let (first, last) = match (
+        sm.notes.iter().find(|note| note.is_step()),
+        sm.notes
+            .iter()
+            .rev()
+            .find(|note| !note.is_mine() && !note.is_fake()),
+    ) {
+        (Some(first), Some(last)) => (first.beat.as_num(), last.beat.as_num()),
+        _ => return,
+    };
+    trace!(
+        "    placing mines every {} beats with a chance of {}",
+        conf.unit,
+        conf.chance
+    );
+    let mut rng = simfile_rng(sm, "mines");
+    let mut key_alloc = KeyAlloc::new(key_count);
+    key_alloc.set_weight_curve(&conf.weight_curve);
+    let mut to_time = sm.beat_to_time();
+    let mut mines = Vec::new();
+    let mut free_keys = Vec::with_capacity(key_count);
+    let mut note_idx = 0;
+    let mut grid_idx = (first / conf.unit).floor() as i64 + 1;
+    loop {
+        let beat = BeatPos::from(grid_idx as f64 * conf.unit);
+        if beat.as_num() >= last {
+            break;
+        }
+        grid_idx += 1;
+        //Keep track of key activity up to this grid position
+        let mut occupied = false;
+        while note_idx < sm.notes.len() && sm.notes[note_idx].beat <= beat {
+            let note = &sm.notes[note_idx];
+            if note.beat == beat {
+                occupied = true;
+            }
+            if note.is_step() || note.is_tail() {
+                key_alloc.touch(note.key as usize, to_time.beat_to_time(note.beat));
+            }
+            note_idx += 1;
+        }
+        if occupied || !rng.gen_bool(conf.chance) {
+            continue;
+        }
+        //Place a mine on a free key
+        free_keys.clear();
+        free_keys.extend((0..key_count).filter(|&key| is_free(&mut busy_idx, key, beat)));
+        let time = to_time.beat_to_time(beat);
+        if let Some(key) = key_alloc.alloc(&free_keys, time, &mut rng) {
+            mines.push(Note {
+                kind: Note::KIND_MINE,
+                beat,
+                key: key as i32,
+                keysound: None,
+                player: 0,
+            });
+        }
+    }
+    if !mines.is_empty() {
+        sm.notes.extend(mines);
+        sm.notes.sort_by_key(|note| note.beat);
+    }
+}
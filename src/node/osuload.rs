@@ -1,6 +1,9 @@
 //! Take an osu! input directory and parse its beatmaps.
 
-use crate::node::prelude::*;
+use crate::{
+    node::prelude::*,
+    osudb::{CollectionDb, DbBeatmap, OsuDb, RankedStatus},
+};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(default)]
@@ -15,12 +18,29 @@ pub struct OsuLoad {
     /// Whether to read `.mp3` files to query audio length (for proper preview audio in the song
     /// wheel select).
     pub query_audio_len: bool,
+    /// List beatmaps from the osu! database instead of scanning the input folder.
+    pub osu_db: OsuDatabase,
+    /// Read osu! collections and tag simfiles with them.
+    pub collections: OsuCollections,
+    /// Maps `.osu` MD5 hashes to the names of the collections they belong to.
+    /// Cannot be set from the config, it is loaded from `collections.path`.
+    #[serde(skip)]
+    pub collections_by_md5: HashMap<String, Vec<String>>,
     /// Which gamemodes to generate.
+    /// Beatmaps are converted into every gamemode in this list with the same keycount, unless a
+    /// route in `routes` matches them.
     pub gamemodes: Vec<Gamemode>,
+    /// Route beatmaps with a specific keycount into specific gamemodes.
+    /// The first matching route is used, and if no route matches `gamemodes` is used instead.
+    pub routes: Vec<GamemodeRoute>,
     /// Options for mania beatmaps.
     pub mania: OsuMania,
     /// Options for beatmaps converted from osu!standard.
     pub standard: OsuStd,
+    /// Options for beatmaps converted from osu!taiko.
+    pub taiko: OsuTaiko,
+    /// Options for beatmaps converted from osu!catch.
+    pub catch: OsuCatch,
     /// Whether to use the osu! unicode names or not.
     pub unicode: bool,
     /// Whether to use or ignore video files.
@@ -43,6 +63,10 @@ pub struct OsuLoad {
     ///
     /// If no roundings are supplied, it is equivalent to `vec![0.]` (no rounding at all).
     pub rounding: Vec<f64>,
+    /// When rounding a timing point leaves a gap in time, whether to fill it with a stop (or a
+    /// delay, if there is no room for a stop) instead of a short bpm change.
+    /// Short bpm changes can have absurd bpm values.
+    pub gap_stops: bool,
 }
 
 impl Default for OsuLoad {
@@ -52,6 +76,9 @@ impl Default for OsuLoad {
             fix_input: true,
             offset: 0.,
             query_audio_len: true,
+            osu_db: default(),
+            collections: default(),
+            collections_by_md5: default(),
             gamemodes: {
                 use crate::simfile::Gamemode::*;
                 // Supported: 3K - 10K
@@ -68,8 +95,15 @@ impl Default for OsuLoad {
                     PnmNine,
                 ]
             },
+            routes: vec![GamemodeRoute {
+                keys: 8,
+                scratch: Some(true),
+                gamemodes: vec![Gamemode::BmSingle7],
+            }],
             mania: default(),
             standard: default(),
+            taiko: default(),
+            catch: default(),
             unicode: false,
             video: true,
             debug_allow_chance: 1.,
@@ -78,6 +112,104 @@ impl Default for OsuLoad {
             whitelist: vec![],
             ignore_mode_errors: true,
             rounding: vec![4., 1., 0.5, 0.25, 0.125, 0.],
+            gap_stops: true,
+        }
+    }
+}
+
+/// Sends beatmaps with a certain keycount into a fixed set of gamemodes.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GamemodeRoute {
+    /// The keycount of the beatmaps to route, including the scratch column.
+    pub keys: i32,
+    /// Whether the route only matches osu!mania beatmaps that use the `SpecialStyle` scratch
+    /// column (`Some(true)`), only those that do not (`Some(false)`), or both (`None`).
+    /// Beatmaps from other osu! gamemodes never have a scratch column.
+    ///
+    /// The scratch column is always the first one, as in `BmSingle5` and `BmSingle7`.
+    pub scratch: Option<bool>,
+    /// The gamemodes to generate, all of which must have `keys` keys.
+    /// If empty, matching beatmaps are not converted.
+    pub gamemodes: Vec<Gamemode>,
+}
+
+impl GamemodeRoute {
+    fn matches(&self, key_count: i32, scratch: bool) -> bool {
+        self.keys == key_count && self.scratch.map(|s| s == scratch).unwrap_or(true)
+    }
+}
+
+impl OsuLoad {
+    /// The gamemodes to convert a beatmap with the given keycount into.
+    pub fn gamemodes_for(&self, key_count: i32, scratch: bool) -> Vec<Gamemode> {
+        match self
+            .routes
+            .iter()
+            .find(|route| route.matches(key_count, scratch))
+        {
+            Some(route) => route.gamemodes.clone(),
+            None => self
+                .gamemodes
+                .iter()
+                .copied()
+                .filter(|gm| gm.key_count() == key_count)
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OsuDatabase {
+    /// Whether to read `osu!.db` to list beatmaps, filtering them before they are parsed.
+    /// If the database cannot be read, the input folder is scanned as usual.
+    pub enable: bool,
+    /// The path to the `osu!.db` file.
+    /// If empty, it is looked up in the parent of the input folder (ie. the osu! installation).
+    pub path: String,
+    /// Only load beatmaps with any of these ranked statuses.
+    /// If empty, all beatmaps are loaded.
+    pub ranked: Vec<RankedStatus>,
+    /// Only load osu!mania beatmaps with any of these keycounts.
+    /// If empty, all keycounts are loaded.
+    pub keycounts: Vec<i32>,
+    /// Only load beatmaps whose cached no-mod star rating falls within this range.
+    /// Beatmaps without a cached star rating are always loaded.
+    pub stars: Option<(f64, f64)>,
+    /// Only load beatmaps that were played within the last given amount of days.
+    pub played_within_days: Option<f64>,
+}
+
+impl Default for OsuDatabase {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            path: "".into(),
+            ranked: vec![],
+            keycounts: vec![],
+            stars: None,
+            played_within_days: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OsuCollections {
+    /// Whether to read `collection.db` and tag each simfile with the collections its beatmap is
+    /// in.
+    pub enable: bool,
+    /// The path to the `collection.db` file.
+    /// If empty, it is looked up in the parent of the input folder (ie. the osu! installation).
+    pub path: String,
+}
+
+impl Default for OsuCollections {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            path: "".into(),
         }
     }
 }
@@ -88,6 +220,8 @@ pub struct OsuMania {
     pub into: BucketId,
     /// Whether to check the error in milliseconds introduced by the conversion/quantization.
     pub check_error: bool,
+    /// Whether to turn the custom hitsounds of keysounded beatmaps into StepMania keysounds.
+    pub keysounds: bool,
 }
 
 impl Default for OsuMania {
@@ -95,6 +229,7 @@ impl Default for OsuMania {
         Self {
             into: default(),
             check_error: false,
+            keysounds: true,
         }
     }
 }
@@ -131,6 +266,86 @@ impl Default for OsuStd {
     }
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OsuTaiko {
+    pub into: BucketId,
+    /// How many keys to convert taiko beatmaps into.
+    /// `0` disables the taiko gamemode parser.
+    pub keycount: i32,
+    /// Which keys can be used for don (red) notes.
+    pub don_keys: Vec<i32>,
+    /// Which keys can be used for kat (blue) notes.
+    pub kat_keys: Vec<i32>,
+    /// How many simultaneous keys to use for big (finisher) notes.
+    pub big_keys: usize,
+    /// Similar to `Rekey::weight_curve`.
+    pub weight_curve: Vec<(f32, f32)>,
+    /// How to convert drumrolls.
+    pub drumroll: LongConv,
+    /// How many notes to generate per denden spin.
+    pub steps_per_spin: f64,
+}
+
+impl Default for OsuTaiko {
+    fn default() -> Self {
+        Self {
+            into: default(),
+            keycount: 4,
+            don_keys: vec![1, 2],
+            kat_keys: vec![0, 3],
+            big_keys: 2,
+            weight_curve: vec![(0., 1.), (0.4, 10.), (0.8, 200.), (1.4, 300.)],
+            drumroll: LongConv::Hold,
+            steps_per_spin: 1.,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OsuCatch {
+    pub into: BucketId,
+    /// How many keys to convert catch beatmaps into.
+    /// `0` disables the catch gamemode parser.
+    pub keycount: i32,
+    /// Fruits are mapped to the key under their x-position, but to avoid jacks any key up to this
+    /// many keys away can be chosen instead.
+    pub spread: i32,
+    /// How many simultaneous keys to use for fruits that require a hyperdash to catch.
+    pub hyperdash_keys: usize,
+    /// Similar to `Rekey::weight_curve`.
+    pub weight_curve: Vec<(f32, f32)>,
+    /// How to convert juice streams.
+    /// When converted into a stream, notes follow the x-position of the juice stream.
+    pub juice_stream: LongConv,
+    /// How to convert banana showers.
+    pub banana_shower: LongConv,
+}
+
+impl Default for OsuCatch {
+    fn default() -> Self {
+        Self {
+            into: default(),
+            keycount: 4,
+            spread: 1,
+            hyperdash_keys: 2,
+            weight_curve: vec![(0., 1.), (0.4, 10.), (0.8, 200.), (1.4, 300.)],
+            juice_stream: LongConv::Hold,
+            banana_shower: LongConv::Stream(0.5),
+        }
+    }
+}
+
+/// How to convert long osu! objects that have no direct mania counterpart.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum LongConv {
+    /// Convert into hold notes.
+    Hold,
+    /// Convert into a stream of hit notes, one every given amount of beats.
+    Stream(f64),
+}
+
 const OSU_AUTODETECT: BaseDirFinder = BaseDirFinder {
     base_files: &[
         "collection.db",
@@ -151,6 +366,17 @@ impl Node for OsuLoad {
             eprintln!("drag and drop your osu! song folder into this window, then press enter");
             self.input = crate::read_path_from_stdin()?;
         }
+        for route in self.routes.iter() {
+            for gm in route.gamemodes.iter() {
+                ensure!(
+                    gm.key_count() == route.keys,
+                    "cannot route {}K beatmaps into gamemode {}, which has {} keys",
+                    route.keys,
+                    gm.id(),
+                    gm.key_count()
+                );
+            }
+        }
         if self.fix_input {
             debug!("autodetecting osu! installation");
             match OSU_AUTODETECT.find_base(self.input.as_ref(), true) {
@@ -176,6 +402,40 @@ impl Node for OsuLoad {
                 }
             }
         }
+        if self.collections.enable {
+            let path = if self.collections.path.is_empty() {
+                Path::new(&self.input)
+                    .parent()
+                    .unwrap_or_else(|| Path::new(""))
+                    .join("collection.db")
+            } else {
+                PathBuf::from(&self.collections.path)
+            };
+            match CollectionDb::load(&path) {
+                Ok(db) => {
+                    info!(
+                        "loaded {} osu! collections from \"{}\"",
+                        db.collections.len(),
+                        path.display()
+                    );
+                    for collection in db.collections {
+                        for md5 in collection.md5s {
+                            self.collections_by_md5
+                                .entry(md5.to_lowercase())
+                                .or_default()
+                                .push(collection.name.clone());
+                        }
+                    }
+                }
+                Err(err) => {
+                    warn!(
+                        "failed to read osu! collections from \"{}\": {:#}",
+                        path.display(),
+                        err
+                    );
+                }
+            }
+        }
         info!("scanning for beatmaps in \"{}\"", self.input);
         Ok(())
     }
@@ -185,7 +445,9 @@ impl Node for OsuLoad {
     fn buckets_mut(&mut self) -> BucketIter {
         Box::new(
             iter::once((BucketKind::Output, &mut self.mania.into))
-                .chain(iter::once((BucketKind::Output, &mut self.standard.into))),
+                .chain(iter::once((BucketKind::Output, &mut self.standard.into)))
+                .chain(iter::once((BucketKind::Output, &mut self.taiko.into)))
+                .chain(iter::once((BucketKind::Output, &mut self.catch.into))),
         )
     }
     fn entry(
@@ -193,16 +455,141 @@ impl Node for OsuLoad {
         store: &mut SimfileStore,
         on_bmset: &mut dyn FnMut(&mut SimfileStore) -> Result<()>,
     ) -> Result<()> {
+        if self.osu_db.enable {
+            match scan_db(self, store, on_bmset) {
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    warn!(
+                        "failed to list beatmaps from osu! database, scanning input folder instead: {:#}",
+                        err
+                    );
+                }
+            }
+        }
         scan_folder(self, store, on_bmset)
     }
 }
 
+/// The path of a `.osu` file, with its star rating cached in `osu!.db` if known.
+type BeatmapEntry = (PathBuf, Option<f64>);
+
+fn scan_db(
+    conf: &OsuLoad,
+    store: &mut SimfileStore,
+    on_bmset: &mut dyn FnMut(&mut SimfileStore) -> Result<()>,
+) -> Result<()> {
+    let db_path = if conf.osu_db.path.is_empty() {
+        Path::new(&conf.input)
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join("osu!.db")
+    } else {
+        PathBuf::from(&conf.osu_db.path)
+    };
+    info!("reading osu! database at \"{}\"", db_path.display());
+    let db = OsuDb::load(&db_path)
+        .with_context(|| anyhow!("read osu! database at \"{}\"", db_path.display()))?;
+    debug!(
+        "  osu! database version {} lists {} beatmaps",
+        db.version,
+        db.beatmaps.len()
+    );
+    //Filter beatmaps and group them by beatmapset folder, keeping database order
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|since| since.as_secs_f64())
+        .unwrap_or_default();
+    let mut folders: Vec<(&str, Vec<BeatmapEntry>)> = Vec::new();
+    let mut folder_idx: HashMap<&str, usize> = default();
+    let mut skipped = 0;
+    for bm in db.beatmaps.iter() {
+        if !db_filter(conf, bm, now) {
+            skipped += 1;
+            continue;
+        }
+        let idx = *folder_idx.entry(&bm.folder).or_insert_with(|| {
+            folders.push((&bm.folder, Vec::new()));
+            folders.len() - 1
+        });
+        folders[idx].1.push((
+            Path::new(&conf.input).join(&bm.folder).join(&bm.file),
+            Some(bm.stars).filter(|stars| !stars.is_nan()),
+        ));
+    }
+    debug!(
+        "  skipped {} beatmaps, loading {} beatmaps in {} beatmapsets",
+        skipped,
+        db.beatmaps.len() - skipped,
+        folders.len()
+    );
+    //Process beatmapsets
+    let mut randtrim = if conf.debug_allow_chance < 1. {
+        Some(FastRng::seed_from_u64(conf.debug_allow_seed))
+    } else {
+        None
+    };
+    for (folder, bm_paths) in folders {
+        let bmset_path = Path::new(&conf.input).join(folder);
+        if !allow_bmset(conf, &mut randtrim, &bmset_path) {
+            continue;
+        }
+        if let Err(e) = process_beatmapset(conf, store, &bmset_path, &bm_paths, None, on_bmset) {
+            error!(
+                "  error processing beatmapset at \"{}\": {:#}",
+                bmset_path.display(),
+                e
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Check whether a beatmap listed in the osu! database should be loaded.
+fn db_filter(conf: &OsuLoad, bm: &DbBeatmap, now: f64) -> bool {
+    let filter = &conf.osu_db;
+    //Skip beatmaps that would not be converted anyway
+    let key_count = match bm.mode {
+        osufile::MODE_MANIA => bm.circle_size.round() as i32,
+        osufile::MODE_STD => conf.standard.keycount,
+        osufile::MODE_TAIKO => conf.taiko.keycount,
+        osufile::MODE_CATCH => conf.catch.keycount,
+        _ => 0,
+    };
+    //The database does not know about the mania scratch column, so try both
+    if conf.gamemodes_for(key_count, false).is_empty()
+        && (bm.mode != osufile::MODE_MANIA || conf.gamemodes_for(key_count, true).is_empty())
+    {
+        return false;
+    }
+    if bm.mode == osufile::MODE_MANIA
+        && !filter.keycounts.is_empty()
+        && !filter.keycounts.contains(&key_count)
+    {
+        return false;
+    }
+    if !filter.ranked.is_empty() && !filter.ranked.contains(&bm.ranked) {
+        return false;
+    }
+    if let Some((min, max)) = filter.stars {
+        if !bm.stars.is_nan() && (bm.stars < min || bm.stars > max) {
+            return false;
+        }
+    }
+    if let Some(days) = filter.played_within_days {
+        match bm.last_played {
+            Some(last_played) if now - last_played <= days * 24. * 3600. => {}
+            _ => return false,
+        }
+    }
+    true
+}
+
 fn scan_folder(
     conf: &OsuLoad,
     store: &mut SimfileStore,
     on_bmset: &mut dyn FnMut(&mut SimfileStore) -> Result<()>,
 ) -> Result<()> {
-    let mut by_depth: Vec<Vec<PathBuf>> = Vec::new();
+    let mut by_depth: Vec<Vec<BeatmapEntry>> = Vec::new();
     let mut randtrim = if conf.debug_allow_chance < 1. {
         Some(FastRng::seed_from_u64(conf.debug_allow_seed))
     } else {
@@ -222,32 +609,11 @@ fn scan_folder(
         if depth < by_depth.len() {
             //Close directories
             for dir in by_depth.drain(depth..) {
-                if let Some(rng) = &mut randtrim {
-                    if !rng.gen_bool(conf.debug_allow_chance) {
-                        continue;
-                    }
-                }
-                if !conf.blacklist.is_empty() || !conf.whitelist.is_empty() {
-                    let path = entry
-                        .path()
-                        .strip_prefix(&conf.input)
-                        .ok()
-                        .and_then(Path::to_str)
-                        .unwrap_or_default()
-                        .to_lowercase();
-                    if conf.blacklist.iter().any(|black| path.contains(black)) {
-                        //Path contains blacklisted keywords
-                        continue;
-                    }
-                    if !conf.whitelist.is_empty()
-                        && !conf.whitelist.iter().any(|white| path.contains(white))
-                    {
-                        //Path is not whitelisted
-                        continue;
-                    }
+                if !allow_bmset(conf, &mut randtrim, entry.path()) {
+                    continue;
                 }
                 if !dir.is_empty() {
-                    match process_beatmapset(conf, store, entry.path(), &dir[..], on_bmset) {
+                    match process_beatmapset(conf, store, entry.path(), &dir[..], None, on_bmset) {
                         Ok(()) => {}
                         Err(e) => {
                             error!(
@@ -269,33 +635,102 @@ fn scan_folder(
             if entry.path().extension() == Some("osu".as_ref()) {
                 let bm_path = entry.into_path();
                 if depth > 0 {
-                    by_depth[depth - 1].push(bm_path);
+                    by_depth[depth - 1].push((bm_path, None));
                 } else {
                     warn!("do not run on a .osu file, run on the beatmapset folder instead");
                 }
+            } else if entry
+                .path()
+                .extension()
+                .map(|ext| ext.eq_ignore_ascii_case("osz"))
+                .unwrap_or(false)
+                && allow_bmset(conf, &mut randtrim, entry.path())
+            {
+                //Treat `.osz` archives as beatmapset folders
+                let result = osufile::OszArchive::open(entry.path()).and_then(|archive| {
+                    let bm_paths = archive
+                        .beatmap_names()
+                        .into_iter()
+                        .map(|name| (entry.path().join(name), None))
+                        .collect::<Vec<_>>();
+                    process_beatmapset(
+                        conf,
+                        store,
+                        entry.path(),
+                        &bm_paths,
+                        Some(archive),
+                        on_bmset,
+                    )
+                });
+                if let Err(e) = result {
+                    error!(
+                        "  error processing beatmapset archive at \"{}\": {:#}",
+                        entry.path().display(),
+                        e
+                    );
+                }
             }
         }
     }
     Ok(())
 }
 
+/// Check the blacklist, whitelist and debug chance to see whether a beatmapset should be loaded.
+fn allow_bmset(conf: &OsuLoad, randtrim: &mut Option<FastRng>, bmset_path: &Path) -> bool {
+    if let Some(rng) = randtrim {
+        if !rng.gen_bool(conf.debug_allow_chance) {
+            return false;
+        }
+    }
+    if !conf.blacklist.is_empty() || !conf.whitelist.is_empty() {
+        let path = bmset_path
+            .strip_prefix(&conf.input)
+            .ok()
+            .and_then(Path::to_str)
+            .unwrap_or_default()
+            .to_lowercase();
+        if conf.blacklist.iter().any(|black| path.contains(black)) {
+            //Path contains blacklisted keywords
+            return false;
+        }
+        if !conf.whitelist.is_empty() && !conf.whitelist.iter().any(|white| path.contains(white)) {
+            //Path is not whitelisted
+            return false;
+        }
+    }
+    true
+}
+
 fn process_beatmapset(
     conf: &OsuLoad,
     store: &mut SimfileStore,
     bmset_path: &Path,
-    bm_paths: &[PathBuf],
+    bm_paths: &[BeatmapEntry],
+    archive: Option<osufile::OszArchive>,
     on_bmset: &mut dyn FnMut(&mut SimfileStore) -> Result<()>,
 ) -> Result<()> {
     info!("processing \"{}\":", bmset_path.display());
     //Parse and convert beatmaps
-    let mut bmset_cache = BmsetCache::default();
+    let is_archive = archive.is_some();
+    let mut bmset_cache = BmsetCache {
+        audio_len: default(),
+        file_exists: default(),
+        archive,
+    };
     let mut by_mode = [Vec::new(), Vec::new(), Vec::new(), Vec::new()];
-    for bm_path in bm_paths {
+    for (bm_path, db_stars) in bm_paths {
         let mut simfile_count = 0;
-        let result = process_beatmap(conf, &mut bmset_cache, bmset_path, bm_path, |mode, sm| {
-            simfile_count += 1;
-            by_mode[mode].push(sm)
-        });
+        let result = process_beatmap(
+            conf,
+            &mut bmset_cache,
+            bmset_path,
+            bm_path,
+            *db_stars,
+            |mode, sm| {
+                simfile_count += 1;
+                by_mode[mode].push(sm)
+            },
+        );
         let bm_name = bm_path.file_name().unwrap_or_default().to_string_lossy();
         match result {
             Ok(()) => {
@@ -324,6 +759,10 @@ fn process_beatmapset(
             ))?
             .to_string(),
     );
+    if is_archive {
+        //Let the writer know that files must be extracted from the `base` archive
+        store.global_set("archive", "osz".to_string());
+    }
     for (mode, simfiles) in by_mode.iter_mut().enumerate() {
         if simfiles.is_empty() {
             continue;
@@ -331,6 +770,8 @@ fn process_beatmapset(
         let bucket = match mode as i32 {
             osufile::MODE_MANIA => &conf.mania.into,
             osufile::MODE_STD => &conf.standard.into,
+            osufile::MODE_TAIKO => &conf.taiko.into,
+            osufile::MODE_CATCH => &conf.catch.into,
             _ => panic!("mode {} is unimplemented", mode),
         };
         store.put(bucket, simfiles.drain(..));
@@ -339,27 +780,68 @@ fn process_beatmapset(
     Ok(())
 }
 
-#[derive(Default)]
 struct BmsetCache {
     audio_len: HashMap<PathBuf, f64>,
+    file_exists: HashMap<PathBuf, bool>,
+    /// If the beatmapset is an `.osz` archive instead of a folder, the opened archive.
+    archive: Option<osufile::OszArchive>,
 }
 impl BmsetCache {
+    /// Read the raw contents of a beatmap from the beatmapset folder or archive.
+    fn read_beatmap(&mut self, bmset_path: &Path, bm_path: &Path) -> Result<Vec<u8>> {
+        let mut raw = Vec::new();
+        match &mut self.archive {
+            Some(archive) => {
+                let name = bm_path.strip_prefix(bmset_path).unwrap_or(bm_path);
+                archive.open_file(name)?.read_to_end(&mut raw)?;
+            }
+            None => {
+                File::open(bm_path)
+                    .context("open file")?
+                    .read_to_end(&mut raw)?;
+            }
+        }
+        Ok(raw)
+    }
+
+    /// Check whether a file exists in the beatmapset folder or archive.
+    fn has_file(&mut self, bmset_path: &Path, name: &Path) -> bool {
+        let archive = &self.archive;
+        *self
+            .file_exists
+            .entry(name.to_path_buf())
+            .or_insert_with(|| match archive {
+                Some(archive) => archive.contains(name),
+                None => bmset_path.join(name).is_file(),
+            })
+    }
+
     /// Get the length of an audio file in seconds.
-    fn get_audio_len(&mut self, path: &Path) -> (f64, Result<()>) {
+    fn get_audio_len(&mut self, bmset_path: &Path, name: &Path) -> (f64, Result<()>) {
+        let path = bmset_path.join(name);
         let mut result = Ok(());
-        let len = match self.audio_len.get(path) {
+        let len = match self.audio_len.get(&path) {
             Some(len) => *len,
             None => {
-                let len = match mp3_duration::from_path(path) {
+                let len = match &mut self.archive {
+                    Some(archive) => archive
+                        .open_file(name)
+                        .and_then(|mut file| Ok(mp3_duration::from_read(&mut file)?)),
+                    None => mp3_duration::from_path(&path).map_err(Error::from),
+                };
+                let len = match len {
                     Ok(len) => len,
                     Err(err) => {
-                        let len = err.at_duration;
-                        result = Err(err.into());
+                        let len = err
+                            .downcast_ref::<mp3_duration::MP3DurationError>()
+                            .map(|err| err.at_duration)
+                            .unwrap_or_default();
+                        result = Err(err);
                         len
                     }
                 }
                 .as_secs_f64();
-                self.audio_len.insert(path.to_path_buf(), len);
+                self.audio_len.insert(path, len);
                 len
             }
         };
@@ -373,11 +855,17 @@ struct ConvCtx<'a> {
     cur_time: f64,
     cur_beat: BeatPos,
     rounding: BeatPos,
+    gap_stops: bool,
     inherited_multiplier: f64,
     out_beatlen_range: (f64, f64),
     out_offset: f64,
     out_bpms: Vec<ControlPoint>,
+    out_stops: Vec<(f64, f64)>,
+    out_delays: Vec<(f64, f64)>,
     out_notes: Vec<Note>,
+    out_keysounds: Vec<PathBuf>,
+    out_time_signatures: Vec<(f64, i32, i32)>,
+    out_scrolls: Vec<(f64, f64)>,
 }
 impl ConvCtx<'_> {
     fn new<'a>(conf: &OsuLoad, bm: &'a Beatmap) -> Result<ConvCtx<'a>> {
@@ -461,11 +949,17 @@ impl ConvCtx<'_> {
             cur_time: first_tp.time,
             cur_beat: BeatPos::from(0.),
             rounding: final_rounding,
+            gap_stops: conf.gap_stops,
             inherited_multiplier: 1.,
             out_beatlen_range: (first_tp.beat_len, first_tp.beat_len),
             out_offset: first_tp.time / -1000.,
             out_bpms: vec![first_controlpoint],
+            out_stops: Vec::new(),
+            out_delays: Vec::new(),
             out_notes: Vec::new(),
+            out_keysounds: Vec::new(),
+            out_time_signatures: vec![(0., first_tp.meter, 4)],
+            out_scrolls: Vec::new(),
             cur_tp: first_tp,
         })
     }
@@ -485,38 +979,60 @@ impl ConvCtx<'_> {
                 if next_tp.beat_len <= 0. {
                     //Inherited timing points are only cosmetic (and they alter slider lengths)
                     self.inherited_multiplier = next_tp.beat_len / -100.;
+                    let scroll = 1. / self.inherited_multiplier;
+                    if scroll.is_finite() {
+                        let beat = self.cur_beat
+                            + BeatPos::from(
+                                (next_tp.time - self.cur_tp.time) / self.cur_tp.beat_len,
+                            );
+                        self.push_scroll(beat.as_num(), scroll);
+                    }
                 } else {
                     //Advance to this timing point
                     let raw_beat_adv = (next_tp.time - self.cur_time) / self.cur_tp.beat_len;
                     let beat_adv = BeatPos::from_num_ceil(raw_beat_adv).ceil(self.rounding);
-                    let tp_beat = self.cur_beat + beat_adv;
+                    let mut tp_beat = self.cur_beat + beat_adv;
                     let mut tp_time = self.cur_time + beat_adv.as_num() * self.cur_tp.beat_len;
-                    if (tp_time - next_tp.time).abs() >= 4. {
-                        let last_beat = self
-                            .out_notes
-                            .last()
-                            .map(|note| note.beat)
-                            .unwrap_or(BeatPos::from(0.))
-                            .max(self.cur_beat);
-                        let pivot_max = self.cur_beat + BeatPos::from_num_ceil(raw_beat_adv);
-                        let mut pivot = None;
-                        for &beat_gap in [
-                            BeatPos::from(1.),
-                            BeatPos::from(0.5),
-                            BeatPos::from(0.25),
-                            BeatPos::from(1. / 8.),
-                            BeatPos::from(1. / 16.),
-                            BeatPos::EPSILON,
-                        ]
-                        .iter()
+                    let last_beat = self
+                        .out_notes
+                        .last()
+                        .map(|note| note.beat)
+                        .unwrap_or(BeatPos::from(0.))
+                        .max(self.cur_beat);
+                    //Rounding down instead leaves a gap of time that can be filled with a stop
+                    let floor_beat =
+                        self.cur_beat + BeatPos::from_num_floor(raw_beat_adv).floor(self.rounding);
+                    if (tp_time - next_tp.time).abs() < 4. {
+                        //Close enough
+                    } else if self.gap_stops && floor_beat > self.cur_beat && floor_beat > last_beat
+                    {
+                        let floor_time = self.cur_time
+                            + (floor_beat - self.cur_beat).as_num() * self.cur_tp.beat_len;
+                        let gap = (next_tp.time - floor_time) / 1000.;
+                        //Stop after the last note, so that notes on the timing point are not hit
+                        //early
+                        //If the last note is too close, delay the timing point notes instead
+                        match find_pivot(last_beat, floor_beat)
+                            .filter(|&pivot| pivot + BeatPos::EPSILON < floor_beat)
                         {
-                            let potential_pivot = pivot_max.ceil(beat_gap) - beat_gap;
-                            if potential_pivot >= last_beat {
-                                pivot = Some(potential_pivot);
-                                break;
+                            Some(pivot) => {
+                                self.out_stops.push((pivot.as_num(), gap));
+                                trace!("      filled {}s gap with a stop at beat {}", gap, pivot);
+                            }
+                            None => {
+                                self.out_delays.push((floor_beat.as_num(), gap));
+                                trace!(
+                                    "      filled {}s gap with a delay at beat {}",
+                                    gap,
+                                    floor_beat
+                                );
                             }
                         }
-                        if let Some(pivot) = pivot {
+                        tp_beat = floor_beat;
+                        tp_time = next_tp.time;
+                    } else {
+                        let pivot_max = self.cur_beat + BeatPos::from_num_ceil(raw_beat_adv);
+                        if let Some(pivot) = find_pivot(last_beat, pivot_max) {
                             let target_time = next_tp.time - self.cur_time;
                             let time_to_pivot =
                                 (pivot - self.cur_beat).as_num() * self.cur_tp.beat_len;
@@ -540,6 +1056,14 @@ impl ConvCtx<'_> {
                         }
                     }
                     trace!("      advancing from timing point at beat {}, time {}, to beat {} ({:?} -> {:?})", self.cur_beat, self.cur_time, tp_beat, self.cur_tp, next_tp);
+                    if next_tp.meter != self.cur_tp.meter {
+                        self.out_time_signatures
+                            .push((tp_beat.as_num(), next_tp.meter, 4));
+                    }
+                    if self.inherited_multiplier != 1. {
+                        //Absolute timing points reset the slider velocity
+                        self.push_scroll(tp_beat.as_num(), 1.);
+                    }
                     self.cur_beat = tp_beat;
                     self.cur_time = tp_time;
                     self.cur_tp = next_tp.clone();
@@ -562,9 +1086,45 @@ impl ConvCtx<'_> {
         self.cur_beat + BeatPos::from((time - self.cur_tp.time) / self.cur_tp.beat_len)
     }
 
+    /// Add an output scroll speed change, replacing any change at the same beat and skipping
+    /// redundant changes.
+    fn push_scroll(&mut self, beat: f64, scroll: f64) {
+        if let Some(&(last_beat, _)) = self.out_scrolls.last() {
+            if last_beat == beat {
+                self.out_scrolls.pop();
+            }
+        }
+        let last_scroll = self.out_scrolls.last().map(|&(_, s)| s).unwrap_or(1.);
+        if scroll != last_scroll {
+            self.out_scrolls.push((beat, scroll));
+        }
+    }
+
     /// Add an output note.
     fn push_note(&mut self, beat: BeatPos, key: i32, kind: char) {
-        self.out_notes.push(Note { beat, key, kind });
+        self.push_keysounded_note(beat, key, kind, None);
+    }
+
+    /// Add an output note that may play a keysound.
+    fn push_keysounded_note(&mut self, beat: BeatPos, key: i32, kind: char, keysound: Option<u32>) {
+        self.out_notes.push(Note {
+            beat,
+            key,
+            kind,
+            keysound,
+            player: 0,
+        });
+    }
+
+    /// Get the keysound index of a sound file, adding it to the keysound list if necessary.
+    fn keysound_idx(&mut self, name: &Path) -> u32 {
+        match self.out_keysounds.iter().position(|ks| ks == name) {
+            Some(idx) => idx as u32,
+            None => {
+                self.out_keysounds.push(name.to_path_buf());
+                self.out_keysounds.len() as u32 - 1
+            }
+        }
     }
 
     /// Output the final simfile in all supported gamemodes.
@@ -583,27 +1143,36 @@ impl ConvCtx<'_> {
         let sample_len = if bm.audio.is_empty() || !conf.query_audio_len {
             default_len
         } else {
-            let audio_path = bmset_path.join(&bm.audio);
-            let (len, result) = bmset_cache.get_audio_len(&audio_path);
+            let (len, result) = bmset_cache.get_audio_len(bmset_path, bm.audio.as_ref());
             if let Err(err) = result {
                 warn!(
                     "    failed to get full audio length for \"{}\": {:#}",
-                    audio_path.display(),
+                    bmset_path.join(&bm.audio).display(),
                     err
                 );
             }
             (len - bm.preview_start / 1000.).max(10.)
         };
         // Create the final SM file in all supported gamemodes
-        let mut at_least_one = false;
-        for gamemode in conf
-            .gamemodes
-            .iter()
-            .copied()
-            .filter(|gm| gm.key_count() == key_count as i32)
-        {
-            at_least_one = true;
-            out(Box::new(Simfile {
+        let scratch = bm.mode == osufile::MODE_MANIA && bm.mania_special;
+        let gamemodes = conf.gamemodes_for(key_count, scratch);
+        let source_meta = SourceMeta {
+            path: Some(bm_path.to_path_buf()),
+            beatmap_id: Some(bm.id).filter(|&id| id >= 0),
+            set_id: Some(bm.set_id).filter(|&id| id >= 0),
+            version: bm.version.clone(),
+            creator: bm.creator.clone(),
+            guest: SourceMeta::guest_from_version(&bm.version, &bm.creator),
+            source: bm.source.clone(),
+            tags: bm.tags.clone(),
+            hp_drain: Some(bm.hp_drain),
+            circle_size: Some(bm.circle_size),
+            overall_difficulty: Some(bm.overall_difficulty),
+            stars: None,
+            osu_mode: Some(bm.mode),
+        };
+        for &gamemode in gamemodes.iter() {
+            let mut sm = Box::new(Simfile {
                 title: if conf.unicode {
                     bm.title_unicode.clone()
                 } else {
@@ -634,7 +1203,9 @@ impl ConvCtx<'_> {
                 music: Some(bm.audio.clone().into()),
                 offset: self.out_offset,
                 bpms: self.out_bpms.clone(),
-                stops: vec![],
+                stops: self.out_stops.clone(),
+                delays: self.out_delays.clone(),
+                warps: vec![],
                 sample_start: Some(bm.preview_start / 1000.),
                 sample_len: Some(sample_len),
                 display_bpm: if self.out_beatlen_range.0 == self.out_beatlen_range.1 {
@@ -647,39 +1218,82 @@ impl ConvCtx<'_> {
                         60000. / self.out_beatlen_range.0,
                     )
                 },
+                time_signatures: self.out_time_signatures.clone(),
+                //Slider velocity only changes the scroll speed in mania and taiko
+                scrolls: match bm.mode {
+                    osufile::MODE_MANIA | osufile::MODE_TAIKO => self.out_scrolls.clone(),
+                    _ => vec![],
+                },
                 gamemode,
+                chart_name: bm.version.clone(),
                 desc: bm.version.clone(),
                 difficulty: Difficulty::Edit,
                 difficulty_num: f64::NAN,
                 radar: [0., 0., 0., 0., 0.],
+                skillsets: default(),
                 notes: self.out_notes.clone(),
-            }));
+                collections: vec![],
+                keysounds: self.out_keysounds.clone(),
+                source_meta: default(),
+            });
+            sm.source_meta = source_meta.clone();
+            out(sm);
         }
-        if !at_least_one {
+        if gamemodes.is_empty() {
             warn!(
-                "  beatmap \"{}\" parsed correctly, but there are no compatible gamemodes with keycount {}",
+                "  beatmap \"{}\" parsed correctly, but there are no compatible gamemodes with keycount {}{}",
                 bm_path.display(),
-                key_count
+                key_count,
+                if scratch { " (with scratch)" } else { "" }
             );
         }
         Ok(())
     }
 }
 
+/// Find the latest beat on a reasonably coarse grid that is before `max` but not before `min`.
+fn find_pivot(min: BeatPos, max: BeatPos) -> Option<BeatPos> {
+    [
+        BeatPos::from(1.),
+        BeatPos::from(0.5),
+        BeatPos::from(0.25),
+        BeatPos::from(1. / 8.),
+        BeatPos::from(1. / 16.),
+        BeatPos::EPSILON,
+    ]
+    .iter()
+    .map(|&beat_gap| max.ceil(beat_gap) - beat_gap)
+    .find(|&pivot| pivot >= min)
+}
+
 fn process_beatmap(
     conf: &OsuLoad,
     bmset_cache: &mut BmsetCache,
     bmset_path: &Path,
     bm_path: &Path,
+    db_stars: Option<f64>,
     mut out: impl FnMut(usize, Box<Simfile>),
 ) -> Result<()> {
-    let bm = Beatmap::parse(conf.offset, bm_path).context("read/parse beatmap file")?;
+    let raw = bmset_cache
+        .read_beatmap(bmset_path, bm_path)
+        .context("read beatmap file")?;
+    let bm = Beatmap::parse_from(conf.offset, bm_path, &raw[..]).context("parse beatmap file")?;
+    //Look up the collections this beatmap is in
+    let collections = if conf.collections_by_md5.is_empty() {
+        &[][..]
+    } else {
+        let md5 = format!("{:x}", md5::compute(&raw));
+        conf.collections_by_md5
+            .get(&md5)
+            .map(|names| &names[..])
+            .unwrap_or_default()
+    };
     let mut conv = ConvCtx::new(conf, &bm)?;
     let key_count = match bm.mode {
-        osufile::MODE_MANIA => process_mania(conf, &bm, &mut conv)?,
+        osufile::MODE_MANIA => process_mania(conf, bmset_cache, bmset_path, &bm, &mut conv)?,
         osufile::MODE_STD => process_standard(conf, &bm, &mut conv)?,
-        osufile::MODE_CATCH => bail!("mode not supported: catch the beat"),
-        osufile::MODE_TAIKO => bail!("mode not supported: taiko"),
+        osufile::MODE_TAIKO => process_taiko(conf, &bm, &mut conv)?,
+        osufile::MODE_CATCH => process_catch(conf, &bm, &mut conv)?,
         unknown => bail!("mode not supported: unknown osu! gamemode {}", unknown),
     };
     //Finish up
@@ -691,13 +1305,54 @@ fn process_beatmap(
             bm_path,
             &bm,
             key_count,
-            |sm| out(bm.mode as usize, sm),
+            |mut sm| {
+                sm.collections = collections.to_vec();
+                //`osu!.db` stores star ratings in the beatmap's own gamemode
+                if bm.mode == osufile::MODE_MANIA {
+                    sm.source_meta.stars = db_stars;
+                }
+                out(bm.mode as usize, sm)
+            },
         )?;
     }
     Ok(())
 }
 
-fn process_mania(conf: &OsuLoad, bm: &Beatmap, conv: &mut ConvCtx) -> Result<i32> {
+/// Convert an osu!mania beatmap that did not come from a `.osu` file (eg. one translated from
+/// another chart format) into simfiles, with the same timing logic as regular beatmaps.
+pub fn convert_mania(
+    conf: &OsuLoad,
+    bmset_path: &Path,
+    bm_path: &Path,
+    bm: &Beatmap,
+    out: impl FnMut(Box<Simfile>),
+) -> Result<()> {
+    ensure!(!bm.timing_points.is_empty(), "no timing points");
+    let mut bmset_cache = BmsetCache {
+        audio_len: default(),
+        file_exists: default(),
+        archive: None,
+    };
+    let mut conv = ConvCtx::new(conf, bm)?;
+    let key_count = process_mania(conf, &mut bmset_cache, bmset_path, bm, &mut conv)?;
+    conv.finish(
+        conf,
+        &mut bmset_cache,
+        bmset_path,
+        bm_path,
+        bm,
+        key_count,
+        out,
+    )
+}
+
+fn process_mania(
+    conf: &OsuLoad,
+    bmset_cache: &mut BmsetCache,
+    bmset_path: &Path,
+    bm: &Beatmap,
+    conv: &mut ConvCtx,
+) -> Result<i32> {
     let key_count = bm.circle_size.round();
     ensure!(
         key_count.is_finite() && key_count >= 0. && key_count < 128.,
@@ -711,6 +1366,8 @@ fn process_mania(conf: &OsuLoad, bm: &Beatmap, conv: &mut ConvCtx) -> Result<i32
     );
     //Keep track of pending long note tails, and add them when it's time
     let mut pending_tails = Vec::new();
+    //Keep track of the active timing point, inherited or not, to get hitsound sample sets
+    let mut sample_tp_idx = 0;
     //Go through every osu! hit object
     for obj in bm.hit_objects.iter() {
         //Insert any pending long note tails
@@ -735,6 +1392,24 @@ fn process_mania(conf: &OsuLoad, bm: &Beatmap, conv: &mut ConvCtx) -> Result<i32
             obj_key
         );
         let obj_key = obj_key as i32;
+        //Find the first sample file of this object that actually exists in the beatmapset
+        let keysound = if conf.mania.keysounds {
+            while bm
+                .timing_points
+                .get(sample_tp_idx + 1)
+                .map(|tp| tp.time <= obj.time)
+                .unwrap_or(false)
+            {
+                sample_tp_idx += 1;
+            }
+            obj.hit_sample()
+                .file_names(bm, &bm.timing_points[sample_tp_idx], obj.hitsound)
+                .into_iter()
+                .find(|name| bmset_cache.has_file(bmset_path, name.as_ref()))
+                .map(|name| conv.keysound_idx(name.as_ref()))
+        } else {
+            None
+        };
         //Act depending on object type
         if obj.ty & osufile::TYPE_LONG != 0 {
             //Long note
@@ -759,10 +1434,10 @@ fn process_mania(conf: &OsuLoad, bm: &Beatmap, conv: &mut ConvCtx) -> Result<i32
                 .unwrap_or(pending_tails.len());
             pending_tails.insert(insert_idx, (end_time, obj_key));
             //Insert the long note head
-            conv.push_note(obj_beat, obj_key, Note::KIND_HEAD);
+            conv.push_keysounded_note(obj_beat, obj_key, Note::KIND_HEAD, keysound);
         } else if obj.ty & osufile::TYPE_HIT != 0 {
             //Hit note
-            conv.push_note(obj_beat, obj_key, Note::KIND_HIT);
+            conv.push_keysounded_note(obj_beat, obj_key, Note::KIND_HIT, keysound);
         }
     }
     // Push out any pending long note tails
@@ -788,16 +1463,25 @@ fn process_mania(conf: &OsuLoad, bm: &Beatmap, conv: &mut ConvCtx) -> Result<i32
             music: default(),
             offset: conv.out_offset,
             bpms: conv.out_bpms.clone(),
-            stops: default(),
+            stops: conv.out_stops.clone(),
+            delays: conv.out_delays.clone(),
+            warps: default(),
             sample_start: default(),
             sample_len: default(),
             display_bpm: DisplayBpm::Random,
+            time_signatures: default(),
+            scrolls: default(),
             gamemode: Gamemode::DanceSingle,
+            chart_name: default(),
             desc: default(),
             difficulty: Difficulty::Edit,
             difficulty_num: f64::NAN,
             radar: default(),
+            skillsets: default(),
             notes: vec![],
+            collections: default(),
+            keysounds: default(),
+            source_meta: default(),
         };
         let mut notes = conv.out_notes.clone();
         let mut check_dist = |key: i32, kind: char, time: f64| -> Result<f64> {
@@ -939,29 +1623,9 @@ fn process_standard(conf: &OsuLoad, bm: &Beatmap, conv: &mut ConvCtx) -> Result<
             let keys = get_key_count(last_pos, (obj.x, obj.y));
             if keys > 0 {
                 //Parse slider properties
-                let mut extras = obj.extras.split(',');
-                let curve = extras.next().unwrap_or_default();
-                let mut slides = extras
-                    .next()
-                    .unwrap_or_default()
-                    .parse::<i32>()
-                    .map_err(|_| {
-                        anyhow!("invalid spinner extras \"{}\", expected slides", obj.extras)
-                    })?
-                    .max(1) as usize;
-                let length_pixels =
-                    extras
-                        .next()
-                        .unwrap_or_default()
-                        .parse::<f64>()
-                        .map_err(|_| {
-                            anyhow!("invalid spinner extras \"{}\", expected length", obj.extras)
-                        })?;
-                //The length of _the entire_ slider in milliseconds, factoring in multiple slides
-                //Note that only the beat length of the starting timing point is considered, to be
-                //consistent with how osu! works does it.
-                let slider_len = slides as f64 * length_pixels / (100. * bm.slider_multiplier)
-                    * (conv.cur_tp.beat_len * conv.inherited_multiplier);
+                let (curve, slide_count, length_pixels) = parse_slider(obj)?;
+                let mut slides = slide_count;
+                let slider_len = slider_duration(bm, conv, slides, length_pixels);
                 //Convert the length to beats
                 let beat_len = conv.get_beat(obj.time + slider_len) - beat;
                 if beat_len.as_num() / (slides as f64) < conf.standard.min_slider_bounce {
@@ -1000,84 +1664,514 @@ fn process_standard(conf: &OsuLoad, bm: &Beatmap, conv: &mut ConvCtx) -> Result<
                         conv.push_note(cur_slide_start, tmp_choose_vec[i] as i32, Note::KIND_TAIL);
                     }
                 }
-                //Use the last control point as the final slider position
-                //Kinda hacky, but very simple
-                let mut end_pos = (obj.x, obj.y);
-                //Make sure the end position is only used if the slider does not roll back to its
-                //initial position
-                if slides % 2 == 1 {
-                    //Parse curve
-                    let mut curve = curve.split('|');
-                    let _curve_ty = curve.next().unwrap();
-                    let last_point = curve.next_back().unwrap_or_default();
-                    let mut point = last_point.split(':');
-                    let x = point
-                        .next()
-                        .unwrap_or_default()
-                        .parse::<f64>()
-                        .map_err(|_| {
-                            anyhow!("invalid slider point \"{}\", expected x", last_point)
-                        })?;
-                    let y = point
-                        .next()
-                        .unwrap_or_default()
-                        .parse::<f64>()
-                        .map_err(|_| {
-                            anyhow!("invalid slider point \"{}\", expected y", last_point)
-                        })?;
-                    end_pos = (x, y);
-                }
-                last_pos = Some(end_pos);
+                let path = osufile::SliderPath::parse((obj.x, obj.y), curve, length_pixels)?;
+                last_pos = Some(path.end_position(slide_count));
             }
         } else if obj.ty & osufile::TYPE_SPINNER != 0 {
             //Convert spinners to stairs
-            //Parse spinner endtime
-            let end_time = obj
-                .extras
-                .split(',')
-                .next()
-                .unwrap_or_default()
-                .parse::<f64>()
-                .map_err(|_| {
-                    anyhow!(
-                        "invalid spinner extras \"{}\", expected endTime",
-                        obj.extras
-                    )
-                })?
-                + bm.offset_ms;
+            spinner_stairs(
+                conv,
+                bm,
+                obj,
+                conf.standard.steps_per_spin,
+                &mut key_alloc,
+                &mut rng,
+            )?;
+            last_pos = None;
+        }
+    }
+
+    Ok(key_count as i32)
+}
+
+fn process_taiko(conf: &OsuLoad, bm: &Beatmap, conv: &mut ConvCtx) -> Result<i32> {
+    use crate::node::rekey::KeyAlloc;
+
+    let key_count = conf.taiko.keycount;
+    if key_count == 0 {
+        //Disable the taiko parser
+        return Ok(0);
+    }
+    ensure!(key_count > 0, "keycount must be positive");
+    let to_keys = |keys: &[i32], name: &str| -> Result<Vec<usize>> {
+        ensure!(!keys.is_empty(), "no {} keys specified", name);
+        keys.iter()
+            .map(|&key| {
+                ensure!(
+                    key >= 0 && key < key_count,
+                    "{} key {} outside range [0, {})",
+                    name,
+                    key,
+                    key_count
+                );
+                Ok(key as usize)
+            })
+            .collect()
+    };
+    let don_keys = to_keys(&conf.taiko.don_keys, "don")?;
+    let kat_keys = to_keys(&conf.taiko.kat_keys, "kat")?;
+    //Drumrolls can be hit with any key
+    let mut roll_keys = don_keys.clone();
+    for &key in kat_keys.iter() {
+        if !roll_keys.contains(&key) {
+            roll_keys.push(key);
+        }
+    }
+    let mut key_alloc = KeyAlloc::new(key_count as usize);
+    key_alloc.set_weight_curve(&conf.taiko.weight_curve);
+    let mut rng = FastRng::seed_from_u64(fxhash::hash64(&(
+        &bm.title,
+        &bm.artist,
+        &bm.version,
+        bm.set_id,
+        bm.id,
+        "osuload-taiko",
+    )));
+
+    trace!(
+        "    processing {} osu!taiko hitobjects into {}K simfile",
+        bm.hit_objects.len(),
+        key_count
+    );
+
+    let mut chosen = Vec::with_capacity(key_count as usize);
+    for obj in bm.hit_objects.iter() {
+        let beat = conv.get_beat(obj.time);
+        let time = obj.time / 1000.;
+        let is_big = obj.hitsound & osufile::HITSOUND_FINISH != 0;
+        let keys = if is_big { conf.taiko.big_keys } else { 1 };
+        chosen.clear();
+        if obj.ty & osufile::TYPE_HIT != 0 {
+            //Dons and kats are told apart by their hitsounds
+            let is_kat = obj.hitsound & (osufile::HITSOUND_WHISTLE | osufile::HITSOUND_CLAP) != 0;
+            let pool = if is_kat { &kat_keys } else { &don_keys };
+            key_alloc.alloc_many(pool, keys, time, &mut rng, &mut chosen);
+            for &key in chosen.iter() {
+                conv.push_note(beat, key as i32, Note::KIND_HIT);
+            }
+        } else if obj.ty & osufile::TYPE_SLIDER != 0 {
+            //Convert drumrolls
+            let (_curve, slides, length_pixels) = parse_slider(obj)?;
+            let end_time = obj.time + slider_duration(bm, conv, slides, length_pixels);
             let end_beat = conv.get_beat(end_time);
-            //Taken from the osu! wiki
-            let spins_per_sec = if bm.overall_difficulty < 5. {
-                5. - 2. * (5. - bm.overall_difficulty) / 5.
+            match &conf.taiko.drumroll {
+                LongConv::Hold if end_beat > beat => {
+                    key_alloc.alloc_many(&don_keys, keys, time, &mut rng, &mut chosen);
+                    for &key in chosen.iter() {
+                        conv.push_note(beat, key as i32, Note::KIND_HEAD);
+                    }
+                    for &key in chosen.iter() {
+                        key_alloc.touch(key, end_time / 1000.);
+                        conv.push_note(end_beat, key as i32, Note::KIND_TAIL);
+                    }
+                }
+                LongConv::Hold => {
+                    //Too short to be a hold
+                    key_alloc.alloc_many(&don_keys, keys, time, &mut rng, &mut chosen);
+                    for &key in chosen.iter() {
+                        conv.push_note(beat, key as i32, Note::KIND_HIT);
+                    }
+                }
+                LongConv::Stream(unit) => {
+                    let unit = BeatPos::from(*unit).max(BeatPos::EPSILON);
+                    let mut next_beat = beat;
+                    while next_beat <= end_beat {
+                        chosen.clear();
+                        let time =
+                            (obj.time + (next_beat - beat).as_num() * conv.cur_tp.beat_len) / 1000.;
+                        key_alloc.alloc_many(&roll_keys, 1, time, &mut rng, &mut chosen);
+                        for &key in chosen.iter() {
+                            conv.push_note(next_beat, key as i32, Note::KIND_HIT);
+                        }
+                        next_beat += unit;
+                    }
+                }
+            }
+        } else if obj.ty & osufile::TYPE_SPINNER != 0 {
+            //Convert dendens to stairs
+            spinner_stairs(
+                conv,
+                bm,
+                obj,
+                conf.taiko.steps_per_spin,
+                &mut key_alloc,
+                &mut rng,
+            )?;
+        }
+    }
+
+    Ok(key_count)
+}
+
+fn process_catch(conf: &OsuLoad, bm: &Beatmap, conv: &mut ConvCtx) -> Result<i32> {
+    use crate::node::rekey::KeyAlloc;
+
+    let key_count = conf.catch.keycount;
+    if key_count == 0 {
+        //Disable the catch parser
+        return Ok(0);
+    }
+    ensure!(key_count > 0, "keycount must be positive");
+    let mut key_alloc = KeyAlloc::new(key_count as usize);
+    key_alloc.set_weight_curve(&conf.catch.weight_curve);
+    let mut rng = FastRng::seed_from_u64(fxhash::hash64(&(
+        &bm.title,
+        &bm.artist,
+        &bm.version,
+        bm.set_id,
+        bm.id,
+        "osuload-catch",
+    )));
+
+    trace!(
+        "    processing {} osu!catch hitobjects into {}K simfile",
+        bm.hit_objects.len(),
+        key_count
+    );
+
+    //Figure out which fruits require a hyperdash to be caught
+    let hyperdashes = find_hyperdashes(bm, conv)?;
+    //Get the keys that are allowed for a given x-position
+    let mut near_keys = Vec::with_capacity(key_count as usize);
+    let get_near_keys = |x: f64, near_keys: &mut Vec<usize>| {
+        let center = ((x * key_count as f64 / 512.).floor() as i32)
+            .max(0)
+            .min(key_count - 1);
+        near_keys.clear();
+        near_keys.extend(
+            ((center - conf.catch.spread).max(0)..=(center + conf.catch.spread).min(key_count - 1))
+                .map(|key| key as usize),
+        );
+    };
+    let all_keys = (0..key_count as usize).collect::<Vec<_>>();
+
+    let mut chosen = Vec::with_capacity(key_count as usize);
+    for obj in bm.hit_objects.iter() {
+        let beat = conv.get_beat(obj.time);
+        let time = obj.time / 1000.;
+        let keys = if hyperdashes.contains(&obj.time.to_bits()) {
+            conf.catch.hyperdash_keys
+        } else {
+            1
+        };
+        chosen.clear();
+        if obj.ty & osufile::TYPE_HIT != 0 {
+            //Fruit
+            get_near_keys(obj.x, &mut near_keys);
+            key_alloc.alloc_many(&near_keys, keys, time, &mut rng, &mut chosen);
+            for &key in chosen.iter() {
+                conv.push_note(beat, key as i32, Note::KIND_HIT);
+            }
+        } else if obj.ty & osufile::TYPE_SLIDER != 0 {
+            //Juice stream
+            let (curve, slides, length_pixels) = parse_slider(obj)?;
+            let duration = slider_duration(bm, conv, slides, length_pixels);
+            let path = osufile::SliderPath::parse((obj.x, obj.y), curve, length_pixels)?;
+            let end_beat = conv.get_beat(obj.time + duration);
+            match &conf.catch.juice_stream {
+                LongConv::Hold if end_beat > beat => {
+                    get_near_keys(obj.x, &mut near_keys);
+                    key_alloc.alloc_many(&near_keys, keys, time, &mut rng, &mut chosen);
+                    for &key in chosen.iter() {
+                        conv.push_note(beat, key as i32, Note::KIND_HEAD);
+                    }
+                    for &key in chosen.iter() {
+                        key_alloc.touch(key, (obj.time + duration) / 1000.);
+                        conv.push_note(end_beat, key as i32, Note::KIND_TAIL);
+                    }
+                }
+                LongConv::Hold => {
+                    //Too short to be a hold
+                    get_near_keys(obj.x, &mut near_keys);
+                    key_alloc.alloc_many(&near_keys, keys, time, &mut rng, &mut chosen);
+                    for &key in chosen.iter() {
+                        conv.push_note(beat, key as i32, Note::KIND_HIT);
+                    }
+                }
+                LongConv::Stream(unit) => {
+                    let unit = BeatPos::from(*unit).max(BeatPos::EPSILON);
+                    let mut next_beat = beat;
+                    while next_beat <= end_beat {
+                        let offset = (next_beat - beat).as_num() * conv.cur_tp.beat_len;
+                        //Follow the juice stream back and forth
+                        let progress = if duration > 0. {
+                            offset / duration * slides as f64
+                        } else {
+                            0.
+                        };
+                        let x = path.position_at_slides(progress).0;
+                        chosen.clear();
+                        get_near_keys(x, &mut near_keys);
+                        key_alloc.alloc_many(
+                            &near_keys,
+                            if next_beat == beat { keys } else { 1 },
+                            (obj.time + offset) / 1000.,
+                            &mut rng,
+                            &mut chosen,
+                        );
+                        for &key in chosen.iter() {
+                            conv.push_note(next_beat, key as i32, Note::KIND_HIT);
+                        }
+                        next_beat += unit;
+                    }
+                }
+            }
+        } else if obj.ty & osufile::TYPE_SPINNER != 0 {
+            //Banana shower
+            let end_time = parse_end_time(bm, obj, ',')?;
+            let end_beat = conv.get_beat(end_time);
+            match &conf.catch.banana_shower {
+                LongConv::Hold if end_beat > beat => {
+                    key_alloc.alloc_many(&all_keys, 1, time, &mut rng, &mut chosen);
+                    for &key in chosen.iter() {
+                        conv.push_note(beat, key as i32, Note::KIND_HEAD);
+                        key_alloc.touch(key, end_time / 1000.);
+                        conv.push_note(end_beat, key as i32, Note::KIND_TAIL);
+                    }
+                }
+                LongConv::Hold => {}
+                LongConv::Stream(unit) => {
+                    let unit = BeatPos::from(*unit).max(BeatPos::EPSILON);
+                    let mut next_beat = beat;
+                    while next_beat <= end_beat {
+                        let offset = (next_beat - beat).as_num() * conv.cur_tp.beat_len;
+                        chosen.clear();
+                        key_alloc.alloc_many(
+                            &all_keys,
+                            1,
+                            (obj.time + offset) / 1000.,
+                            &mut rng,
+                            &mut chosen,
+                        );
+                        for &key in chosen.iter() {
+                            conv.push_note(next_beat, key as i32, Note::KIND_HIT);
+                        }
+                        next_beat += unit;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(key_count)
+}
+
+/// Find the hitobjects that can only be caught with a hyperdash, identified by the bits of their
+/// start time.
+///
+/// Adapted from the osu!lazer `CatchBeatmapProcessor`, taking into account fruits, droplets
+/// (juice stream ticks) and juice stream repeats and ends.
+fn find_hyperdashes(bm: &Beatmap, conv: &ConvCtx) -> Result<HashSet<u64>> {
+    //Taken from the osu!lazer source
+    const BASE_SIZE: f64 = 106.75;
+    const ALLOWED_CATCH_RANGE: f64 = 0.8;
+    const BASE_DASH_SPEED: f64 = 1.;
+    let scale = 1. - 0.7 * (bm.circle_size - 5.) / 5.;
+    let half_catcher_width = BASE_SIZE * scale.abs() * ALLOWED_CATCH_RANGE / 2.;
+
+    //Collect the times and positions of all objects that must be caught
+    //Note that juice stream lengths are approximated with the current timing point at each
+    //stream start, which is what `slider_duration` would use.
+    let mut tmp_conv = ConvCtx {
+        cur_tp: conv.cur_tp.clone(),
+        out_bpms: Vec::new(),
+        out_stops: Vec::new(),
+        out_delays: Vec::new(),
+        out_notes: Vec::new(),
+        out_keysounds: Vec::new(),
+        out_time_signatures: Vec::new(),
+        out_scrolls: Vec::new(),
+        ..*conv
+    };
+    let mut palpable = Vec::with_capacity(bm.hit_objects.len());
+    for obj in bm.hit_objects.iter() {
+        if obj.ty & osufile::TYPE_HIT != 0 {
+            palpable.push((obj.time, obj.x, obj.time));
+        } else if obj.ty & osufile::TYPE_SLIDER != 0 {
+            tmp_conv.get_beat(obj.time);
+            let (curve, slides, length_pixels) = parse_slider(obj)?;
+            let duration = slider_duration(bm, &tmp_conv, slides, length_pixels);
+            let path = osufile::SliderPath::parse((obj.x, obj.y), curve, length_pixels)?;
+            let slide_duration = duration / slides as f64;
+            palpable.push((obj.time, obj.x, obj.time));
+            //Droplets
+            let tick_dist =
+                100. * bm.slider_multiplier / (tmp_conv.inherited_multiplier * bm.slider_tickrate);
+            //Do not place droplets within 10ms of the end of a slide, like osu!lazer
+            let min_dist_from_end = if slide_duration > 0. {
+                path.length() / slide_duration * 10.
             } else {
-                5. + 2.5 * (bm.overall_difficulty - 5.) / 5.
+                0.
             };
-            let spins = (end_time - obj.time) / 1000. * spins_per_sec;
-            let steps_approx = (spins * conf.standard.steps_per_spin).max(1.);
-            let beat_step = BeatPos::from(
-                2f64.powi(
-                    ((end_beat - beat).as_num() / steps_approx)
-                        .max(1. / 16.)
-                        .log2()
-                        .round() as i32,
-                ),
-            );
-            //Create stair steps
-            tmp_choose_vec.clear();
-            tmp_choose_vec.extend(0..key_count);
-            let mut next_key = key_alloc
-                .alloc(&tmp_choose_vec, obj.time / 1000., &mut rng)
-                .unwrap() as i32;
-            let dir = if rng.gen() { 1 } else { -1 };
-            let mut next_beat = beat;
-            while next_beat <= end_beat {
-                conv.push_note(next_beat, next_key, Note::KIND_HIT);
-                next_beat += beat_step;
-                next_key = (next_key + dir).rem_euclid(key_count as i32);
+            for (progress, (x, _y)) in path.ticks(tick_dist, slides, min_dist_from_end) {
+                let time = obj.time + progress * slide_duration;
+                palpable.push((time, x, time));
+            }
+            //Repeats and end
+            for (slide, (x, _y)) in path.repeat_positions(slides).into_iter().enumerate() {
+                let time = obj.time + (slide + 1) as f64 * slide_duration;
+                palpable.push((time, x, time));
             }
-            last_pos = None;
         }
     }
+    palpable.sort_by_key(|&(time, _, _)| SortableFloat(time));
 
-    Ok(key_count as i32)
+    let mut hyperdashes = HashSet::default();
+    let mut last_dir = 0;
+    let mut last_excess = half_catcher_width;
+    for pair in palpable.windows(2) {
+        let (cur_time, cur_x, _) = pair[0];
+        let (next_time, next_x, next_id) = pair[1];
+        let dir = if next_x > cur_x { 1 } else { -1 };
+        //A quarter of a frame of grace time
+        let time_to_next = next_time - cur_time - 1000. / 60. / 4.;
+        let dist_to_next = (next_x - cur_x).abs()
+            - if last_dir == dir {
+                last_excess
+            } else {
+                half_catcher_width
+            };
+        let dist_to_hyper = time_to_next * BASE_DASH_SPEED - dist_to_next;
+        if dist_to_hyper < 0. {
+            hyperdashes.insert(next_id.to_bits());
+            last_excess = half_catcher_width;
+        } else {
+            last_excess = dist_to_hyper.max(0.).min(half_catcher_width);
+        }
+        last_dir = dir;
+    }
+    Ok(hyperdashes)
+}
+
+/// Parse the end time of spinners and hold notes, whose extras start with the end time, followed
+/// by the given separator.
+fn parse_end_time(bm: &Beatmap, obj: &osufile::HitObject, sep: char) -> Result<f64> {
+    Ok(obj
+        .extras
+        .split(sep)
+        .next()
+        .unwrap_or_default()
+        .parse::<f64>()
+        .map_err(|_| anyhow!("invalid extras \"{}\", expected endTime", obj.extras))?
+        + bm.offset_ms)
+}
+
+/// Parse the slider-specific fields of a hitobject, returning the curve, the amount of slides and
+/// the length of a single slide in osu!pixels.
+fn parse_slider(obj: &osufile::HitObject) -> Result<(&str, usize, f64)> {
+    let mut extras = obj.extras.split(',');
+    let curve = extras.next().unwrap_or_default();
+    let slides = extras
+        .next()
+        .unwrap_or_default()
+        .parse::<i32>()
+        .map_err(|_| anyhow!("invalid slider extras \"{}\", expected slides", obj.extras))?
+        .max(1) as usize;
+    let length_pixels = extras
+        .next()
+        .unwrap_or_default()
+        .parse::<f64>()
+        .map_err(|_| anyhow!("invalid slider extras \"{}\", expected length", obj.extras))?;
+    Ok((curve, slides, length_pixels))
+}
+
+/// The length of _the entire_ slider in milliseconds, factoring in multiple slides.
+///
+/// Note that only the beat length of the starting timing point is considered, to be consistent
+/// with how osu! does it.
+/// Therefore, `conv` must be up to date with the slider start time.
+fn slider_duration(bm: &Beatmap, conv: &ConvCtx, slides: usize, length_pixels: f64) -> f64 {
+    slides as f64 * length_pixels / (100. * bm.slider_multiplier)
+        * (conv.cur_tp.beat_len * conv.inherited_multiplier)
+}
+
+/// Convert a spinner into a staircase pattern that runs through all keys.
+fn spinner_stairs(
+    conv: &mut ConvCtx,
+    bm: &Beatmap,
+    obj: &osufile::HitObject,
+    steps_per_spin: f64,
+    key_alloc: &mut crate::node::rekey::KeyAlloc,
+    rng: &mut FastRng,
+) -> Result<()> {
+    let key_count = key_alloc.key_count() as i32;
+    let beat = conv.get_beat(obj.time);
+    let end_time = parse_end_time(bm, obj, ',')?;
+    let end_beat = conv.get_beat(end_time);
+    //Taken from the osu! wiki
+    let spins_per_sec = if bm.overall_difficulty < 5. {
+        5. - 2. * (5. - bm.overall_difficulty) / 5.
+    } else {
+        5. + 2.5 * (bm.overall_difficulty - 5.) / 5.
+    };
+    let spins = (end_time - obj.time) / 1000. * spins_per_sec;
+    let steps_approx = (spins * steps_per_spin).max(1.);
+    let beat_step = BeatPos::from(
+        2f64.powi(
+            ((end_beat - beat).as_num() / steps_approx)
+                .max(1. / 16.)
+                .log2()
+                .round() as i32,
+        ),
+    );
+    //Create stair steps
+    let all_keys = (0..key_count as usize).collect::<Vec<_>>();
+    let mut next_key = key_alloc
+        .alloc(&all_keys, obj.time / 1000., rng)
+        .ok_or_else(|| anyhow!("no keys to place spinner on"))? as i32;
+    let dir = if rng.gen() { 1 } else { -1 };
+    let mut next_beat = beat;
+    while next_beat <= end_beat {
+        conv.push_note(next_beat, next_key, Note::KIND_HIT);
+        next_beat += beat_step;
+        next_key = (next_key + dir).rem_euclid(key_count);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 120 bpm beatmap whose second timing point comes 4.6 beats after the first, so that
+    /// rounding it down to 4 beats leaves a 0.3 second gap.
+    fn gap_beatmap() -> Beatmap {
+        let tp = |time| TimingPoint {
+            time,
+            beat_len: 500.,
+            meter: 4,
+            sample_set: 0,
+            sample_index: 0,
+        };
+        Beatmap {
+            timing_points: vec![tp(0.), tp(2300.)],
+            ..default()
+        }
+    }
+
+    #[test]
+    fn gap_becomes_stop() {
+        let bm = gap_beatmap();
+        let conf = OsuLoad::default();
+        let mut conv = ConvCtx::new(&conf, &bm).unwrap();
+        conv.push_note(BeatPos::from(2.), 0, Note::KIND_HIT);
+        assert_eq!(conv.get_beat(2300.), BeatPos::from(4.));
+        assert_eq!(conv.out_stops, vec![(3., 0.3)]);
+        assert!(conv.out_delays.is_empty());
+    }
+
+    #[test]
+    fn gap_becomes_delay_when_pivot_is_taken() {
+        let bm = gap_beatmap();
+        let conf = OsuLoad::default();
+        let mut conv = ConvCtx::new(&conf, &bm).unwrap();
+        conv.push_note(
+            BeatPos::from(4.) - BeatPos::EPSILON - BeatPos::EPSILON,
+            0,
+            Note::KIND_HIT,
+        );
+        assert_eq!(conv.get_beat(2300.), BeatPos::from(4.));
+        assert!(conv.out_stops.is_empty());
+        assert_eq!(conv.out_delays, vec![(4., 0.3)]);
+    }
 }
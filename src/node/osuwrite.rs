@@ -0,0 +1,213 @@
+//! Takes a bunch of simfiles as input and writes them out as osu!mania beatmaps.
+
+use crate::node::{
+    prelude::*,
+    simfilewrite::{
+        chart_display_name, copy_set_deps, or_trans, path_str, resolve_out_dir, sanitize_file_name,
+        unique_file_name, CopyMethod,
+    },
+};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OsuWrite {
+    pub from: BucketId,
+    /// The path to the output osu! song folder.
+    pub output: String,
+    /// Which methods to try for copying "dependency" files, such as `.mp3` and `.jpg` files.
+    pub copy: Vec<CopyMethod>,
+    /// The HP drain rate of the output beatmaps.
+    pub hp_drain: f64,
+    /// The overall difficulty (timing windows) of the output beatmaps.
+    pub overall_difficulty: f64,
+}
+
+impl Default for OsuWrite {
+    fn default() -> Self {
+        Self {
+            from: default(),
+            output: "".into(),
+            copy: vec![
+                CopyMethod::Hardlink,
+                CopyMethod::Copy,
+                CopyMethod::AssertIdentical,
+            ],
+            hp_drain: 8.,
+            overall_difficulty: 8.,
+        }
+    }
+}
+
+impl Node for OsuWrite {
+    fn prepare(&mut self) -> Result<()> {
+        if self.output.is_empty() {
+            eprintln!();
+            eprintln!("drag and drop your osu! song folder into this window, then press enter");
+            self.output = crate::read_path_from_stdin()?;
+        }
+        info!("outputting beatmaps in \"{}\"", self.output);
+        Ok(())
+    }
+    fn apply(&self, store: &mut SimfileStore) -> Result<()> {
+        let mut simfiles = Vec::new();
+        store.get_each(&self.from, |_, sm| {
+            simfiles.push(sm);
+            Ok(())
+        })?;
+        if simfiles.is_empty() {
+            return Ok(());
+        }
+        let out_base = resolve_out_dir(store, &self.output)?;
+        //Write one beatmap per simfile
+        let mut used_names = HashSet::default();
+        for sm in simfiles.iter() {
+            let mut bm = to_beatmap(self, sm)?;
+            bm.version = unique_file_name(&mut used_names, &bm.version);
+            let filename = sanitize_file_name(&format!(
+                "{} - {} ({}) [{}].osu",
+                bm.artist, bm.title, bm.creator, bm.version
+            ));
+            let out_path = out_base.join(filename);
+            debug!("  writing beatmap to \"{}\"", out_path.display());
+            bm.save(&out_path)
+                .with_context(|| anyhow!("write beatmap to \"{}\"", out_path.display()))?;
+        }
+        //Copy over dependencies (backgrounds, audio, etc...)
+        copy_set_deps(store, &self.copy, &out_base, &simfiles)?;
+        Ok(())
+    }
+    fn buckets_mut(&mut self) -> BucketIter<'_> {
+        Box::new(iter::once((BucketKind::Input, &mut self.from)))
+    }
+}
+
+fn to_beatmap(conf: &OsuWrite, sm: &Simfile) -> Result<Beatmap> {
+    ensure!(!sm.bpms.is_empty(), "simfile has no bpms");
+    let key_count = sm.gamemode.key_count();
+    let name = chart_display_name(sm);
+    let mut bm = Beatmap {
+        audio: path_str(&sm.music),
+        preview_start: sm.sample_start.map(|start| start * 1000.).unwrap_or(-1.),
+        mode: osufile::MODE_MANIA,
+        title_unicode: sm.title.clone(),
+        title: or_trans(&sm.title, &sm.title_trans),
+        artist_unicode: sm.artist.clone(),
+        artist: or_trans(&sm.artist, &sm.artist_trans),
+        creator: sm.credit.clone(),
+        version: if name.contains(&format!("{}K", key_count)) {
+            name.to_string()
+        } else {
+            format!("{}K {}", key_count, name)
+        },
+        id: 0,
+        tags: "osu2sm".to_string(),
+        hp_drain: conf.hp_drain,
+        circle_size: key_count as f64,
+        overall_difficulty: conf.overall_difficulty,
+        approach_rate: 5.,
+        background: path_str(&sm.background),
+        ..default()
+    };
+
+    //Add an absolute timing point at every bpm or time signature change, and after every stop
+    let change_beats = sm.timing_beats();
+    let mut to_time = sm.beat_to_time();
+    let mut cp_idx = 0;
+    for &beat in change_beats.iter() {
+        while cp_idx + 1 < sm.bpms.len() && sm.bpms[cp_idx + 1].beat <= beat {
+            cp_idx += 1;
+        }
+        let meter = sm
+            .time_signatures
+            .iter()
+            .take_while(|&&(sig_beat, _, _)| BeatPos::from(sig_beat) <= beat)
+            .last()
+            .map(|&(_, num, _)| num)
+            .unwrap_or(4);
+        bm.timing_points.push(TimingPoint {
+            time: (to_time.beat_to_time_after(beat) * 1000.).round(),
+            beat_len: sm.bpms[cp_idx].beat_len * 1000.,
+            meter,
+            sample_set: 0,
+            sample_index: 0,
+        });
+    }
+    //Add an inherited timing point at every scroll change
+    //Absolute timing points reset the slider velocity, so it must be set again after them
+    let mut to_time = sm.beat_to_time();
+    let mut scroll_idx = 0;
+    let mut inherited = Vec::new();
+    for &beat in change_beats.iter() {
+        while scroll_idx < sm.scrolls.len() && BeatPos::from(sm.scrolls[scroll_idx].0) <= beat {
+            scroll_idx += 1;
+        }
+        match sm.scrolls[..scroll_idx].last() {
+            Some(&(_, ratio)) if ratio != 1. => inherited.push((beat, ratio)),
+            _ => {}
+        }
+    }
+    inherited.extend(
+        sm.scrolls
+            .iter()
+            .map(|&(beat, ratio)| (BeatPos::from(beat), ratio)),
+    );
+    inherited.sort_by_key(|&(beat, _)| beat);
+    inherited.dedup_by_key(|&mut (beat, _)| beat);
+    for (beat, ratio) in inherited {
+        if ratio > 0. && ratio.is_finite() {
+            bm.timing_points.push(TimingPoint {
+                time: (to_time.beat_to_time_after(beat) * 1000.).round(),
+                beat_len: -100. / ratio,
+                meter: 4,
+                sample_set: 0,
+                sample_index: 0,
+            });
+        }
+    }
+    //Absolute timing points go before inherited timing points at the same time
+    bm.timing_points
+        .sort_by_key(|tp| (SortableFloat(tp.time), tp.beat_len < 0.));
+
+    //Convert notes into hit objects, dropping mines and fakes
+    let hit_sample = |note: &Note| {
+        let keysound = note
+            .keysound
+            .and_then(|idx| sm.keysounds.get(idx as usize))
+            .map(|path| path.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        format!("0:0:0:0:{}", keysound)
+    };
+    let mut to_time = sm.beat_to_time();
+    let mut pending_heads: Vec<Option<(f64, &Note)>> = vec![None; key_count as usize];
+    for note in sm.notes.iter() {
+        let time = (to_time.beat_to_time(note.beat) * 1000.).round();
+        let x = ((note.key as f64 + 0.5) * 512. / key_count as f64).floor();
+        if note.is_hit() || note.is_lift() {
+            bm.hit_objects.push(osufile::HitObject {
+                x,
+                y: 192.,
+                time,
+                ty: osufile::TYPE_HIT,
+                hitsound: 0,
+                extras: hit_sample(note),
+            });
+        } else if note.is_head() {
+            pending_heads[note.key as usize] = Some((time, note));
+        } else if note.is_tail() {
+            let (start_time, head) = pending_heads[note.key as usize]
+                .take()
+                .ok_or_else(|| anyhow!("tail at beat {} has no head", note.beat))?;
+            bm.hit_objects.push(osufile::HitObject {
+                x,
+                y: 192.,
+                time: start_time,
+                ty: osufile::TYPE_LONG,
+                hitsound: 0,
+                extras: format!("{}:{}", time, hit_sample(head)),
+            });
+        }
+    }
+    //Long notes were added at their tails
+    bm.hit_objects.sort_by_key(|obj| SortableFloat(obj.time));
+    Ok(bm)
+}
@@ -0,0 +1,269 @@
+//! Take a Quaver song folder and parse its `.qua` charts.
+//!
+//! Charts are translated into osu!mania beatmaps and converted through the same timing logic as
+//! `OsuLoad`.
+
+use crate::{
+    node::{
+        osuload::{self, OsuMania},
+        prelude::*,
+        smload::group_by_song,
+    },
+    quafile::{Qua, QuaSignature},
+};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QuaLoad {
+    /// Into what bucket to output the loaded simfiles.
+    pub into: BucketId,
+    /// The input Quaver song folder.
+    pub input: String,
+    /// The offset to apply to Quaver charts, in milliseconds.
+    pub offset: f64,
+    /// Whether to read `.mp3` files to query audio length (for proper preview audio in the song
+    /// wheel select).
+    pub query_audio_len: bool,
+    /// Which gamemodes to generate.
+    pub gamemodes: Vec<Gamemode>,
+    /// Whether to turn the custom samples of keysounded charts into StepMania keysounds.
+    pub keysounds: bool,
+    /// Same as `OsuLoad::rounding`.
+    pub rounding: Vec<f64>,
+    /// Same as `OsuLoad::gap_stops`.
+    pub gap_stops: bool,
+}
+
+impl Default for QuaLoad {
+    fn default() -> Self {
+        let osu = OsuLoad::default();
+        Self {
+            into: default(),
+            input: "".into(),
+            offset: 0.,
+            query_audio_len: true,
+            gamemodes: osu.gamemodes,
+            keysounds: true,
+            rounding: osu.rounding,
+            gap_stops: osu.gap_stops,
+        }
+    }
+}
+
+impl Node for QuaLoad {
+    fn prepare(&mut self) -> Result<()> {
+        if self.input.is_empty() {
+            eprintln!();
+            eprintln!("drag and drop your Quaver song folder into this window, then press enter");
+            self.input = crate::read_path_from_stdin()?;
+        }
+        info!("scanning for charts in \"{}\"", self.input);
+        Ok(())
+    }
+    fn apply(&self, _store: &mut SimfileStore) -> Result<()> {
+        Ok(())
+    }
+    fn buckets_mut(&mut self) -> BucketIter<'_> {
+        Box::new(iter::once((BucketKind::Output, &mut self.into)))
+    }
+    fn entry(
+        &self,
+        store: &mut SimfileStore,
+        on_bmset: &mut dyn FnMut(&mut SimfileStore) -> Result<()>,
+    ) -> Result<()> {
+        //The osu! loader settings that drive the conversion
+        let osu_conf = OsuLoad {
+            input: self.input.clone(),
+            fix_input: false,
+            offset: self.offset,
+            query_audio_len: self.query_audio_len,
+            gamemodes: self.gamemodes.clone(),
+            mania: OsuMania {
+                keysounds: self.keysounds,
+                ..default()
+            },
+            unicode: false,
+            video: false,
+            rounding: self.rounding.clone(),
+            gap_stops: self.gap_stops,
+            ..default()
+        };
+        //Group charts by song folder
+        let by_song = group_by_song(&self.input, |path| {
+            path.extension()
+                .map(|ext| ext.eq_ignore_ascii_case("qua"))
+                .unwrap_or(false)
+        });
+        for (song_path, qua_paths) in by_song {
+            if let Err(err) = process_song(self, &osu_conf, store, &song_path, &qua_paths, on_bmset)
+            {
+                error!(
+                    "  error processing song at \"{}\": {:#}",
+                    song_path.display(),
+                    err
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+fn process_song(
+    conf: &QuaLoad,
+    osu_conf: &OsuLoad,
+    store: &mut SimfileStore,
+    song_path: &Path,
+    qua_paths: &[PathBuf],
+    on_bmset: &mut dyn FnMut(&mut SimfileStore) -> Result<()>,
+) -> Result<()> {
+    info!("processing \"{}\":", song_path.display());
+    let mut simfiles = Vec::new();
+    for qua_path in qua_paths {
+        let qua_name = qua_path.file_name().unwrap_or_default().to_string_lossy();
+        let result = Qua::load(qua_path)
+            .context("parse chart file")
+            .and_then(|qua| {
+                let bm = to_beatmap(conf, &qua)?;
+                let count = simfiles.len();
+                osuload::convert_mania(osu_conf, song_path, qua_path, &bm, |mut sm| {
+                    sm.genre = qua.genre.clone();
+                    simfiles.push(sm);
+                })?;
+                Ok(simfiles.len() - count)
+            });
+        match result {
+            Ok(count) => {
+                debug!(
+                    "  loaded chart \"{}\" successfully into {} simfiles",
+                    qua_name, count
+                );
+            }
+            Err(err) => {
+                error!("  error processing chart \"{}\": {:#}", qua_name, err);
+            }
+        }
+    }
+    //Report song
+    store.reset();
+    store.global_set("root", conf.input.clone());
+    store.global_set(
+        "base",
+        song_path
+            .to_str()
+            .ok_or(anyhow!("non utf-8 song path \"{}\"", song_path.display()))?
+            .to_string(),
+    );
+    store.put(&conf.into, simfiles);
+    on_bmset(store)?;
+    Ok(())
+}
+
+/// Translate a Quaver chart into an equivalent osu!mania beatmap.
+fn to_beatmap(conf: &QuaLoad, qua: &Qua) -> Result<Beatmap> {
+    let key_count = qua.key_count();
+    let mut bm = Beatmap {
+        audio: qua.audio_file.clone(),
+        preview_start: qua.song_preview_time as f64,
+        mode: osufile::MODE_MANIA,
+        title_unicode: qua.title.clone(),
+        title: qua.title.clone(),
+        artist_unicode: qua.artist.clone(),
+        artist: qua.artist.clone(),
+        creator: qua.creator.clone(),
+        version: qua.difficulty_name.clone(),
+        source: qua.source.clone(),
+        tags: qua.tags.clone(),
+        id: qua.map_id as i64,
+        set_id: qua.map_set_id as i64,
+        circle_size: key_count as f64,
+        background: qua.background_file.clone(),
+        offset_ms: conf.offset,
+        ..default()
+    };
+
+    //Merge timing points and slider velocities into osu! timing points
+    let mut timing_points = qua
+        .timing_points
+        .iter()
+        .filter(|tp| tp.bpm > 0. && tp.bpm.is_finite())
+        .collect::<Vec<_>>();
+    timing_points.sort_by_key(|tp| SortableFloat(tp.start_time));
+    let mut svs = qua.slider_velocities.iter().collect::<Vec<_>>();
+    svs.sort_by_key(|sv| SortableFloat(sv.start_time));
+    ensure!(!timing_points.is_empty(), "chart has no timing points");
+    let inherited = |time: f64, multiplier: f64| TimingPoint {
+        time,
+        //Negative scroll speeds cannot be represented, stop scrolling instead
+        beat_len: -100. / multiplier.max(0.),
+        meter: 4,
+        sample_set: 0,
+        sample_index: 0,
+    };
+    let mut multiplier = 1.;
+    let mut svs = svs.into_iter().peekable();
+    for tp in timing_points {
+        while let Some(sv) = svs.next_if(|sv| sv.start_time < tp.start_time) {
+            multiplier = sv.multiplier;
+            bm.timing_points
+                .push(inherited(sv.start_time + conf.offset, multiplier));
+        }
+        bm.timing_points.push(TimingPoint {
+            time: tp.start_time + conf.offset,
+            beat_len: 60000. / tp.bpm,
+            meter: match tp.signature {
+                QuaSignature::Quadruple => 4,
+                QuaSignature::Triple => 3,
+            },
+            sample_set: 0,
+            sample_index: 0,
+        });
+        //Quaver slider velocities carry over timing points, but osu! ones do not
+        if multiplier != 1. {
+            bm.timing_points
+                .push(inherited(tp.start_time + conf.offset, multiplier));
+        }
+    }
+    for sv in svs {
+        bm.timing_points
+            .push(inherited(sv.start_time + conf.offset, sv.multiplier));
+    }
+
+    //Convert hit objects, keeping their first keysound as a custom sample file
+    let mut hit_objects = qua.hit_objects.iter().collect::<Vec<_>>();
+    hit_objects.sort_by_key(|obj| obj.start_time);
+    for obj in hit_objects {
+        ensure!(
+            obj.lane >= 1 && obj.lane <= key_count,
+            "invalid lane {} in a {}K chart",
+            obj.lane,
+            key_count
+        );
+        let sample = obj
+            .key_sounds
+            .first()
+            .and_then(|ks| {
+                qua.custom_audio_samples
+                    .get((ks.sample as usize).wrapping_sub(1))
+            })
+            .map(|sample| sample.path.as_str())
+            .unwrap_or_default();
+        let is_long = obj.end_time > obj.start_time;
+        bm.hit_objects.push(osufile::HitObject {
+            x: ((obj.lane - 1) as f64 + 0.5) * 512. / key_count as f64,
+            y: 192.,
+            time: obj.start_time as f64 + conf.offset,
+            ty: if is_long {
+                osufile::TYPE_LONG
+            } else {
+                osufile::TYPE_HIT
+            },
+            hitsound: 0,
+            extras: if is_long {
+                format!("{}:0:0:0:0:{}", obj.end_time, sample)
+            } else {
+                format!("0:0:0:0:{}", sample)
+            },
+        });
+    }
+    Ok(bm)
+}
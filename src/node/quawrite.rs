@@ -0,0 +1,195 @@
+//! Takes a bunch of simfiles as input and writes them out as Quaver `.qua` charts.
+
+use crate::{
+    node::{
+        prelude::*,
+        simfilewrite::{
+            chart_display_name, copy_set_deps, or_trans, path_str, resolve_out_dir,
+            sanitize_file_name, unique_file_name, CopyMethod,
+        },
+    },
+    quafile::{
+        Qua, QuaAudioSample, QuaHitObject, QuaKeySound, QuaMode, QuaSignature, QuaSliderVelocity,
+        QuaTimingPoint,
+    },
+};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QuaWrite {
+    pub from: BucketId,
+    /// The path to the output Quaver song folder.
+    pub output: String,
+    /// Which methods to try for copying "dependency" files, such as `.mp3` and `.jpg` files.
+    pub copy: Vec<CopyMethod>,
+}
+
+impl Default for QuaWrite {
+    fn default() -> Self {
+        Self {
+            from: default(),
+            output: "".into(),
+            copy: vec![
+                CopyMethod::Hardlink,
+                CopyMethod::Copy,
+                CopyMethod::AssertIdentical,
+            ],
+        }
+    }
+}
+
+impl Node for QuaWrite {
+    fn prepare(&mut self) -> Result<()> {
+        if self.output.is_empty() {
+            eprintln!();
+            eprintln!("drag and drop your Quaver song folder into this window, then press enter");
+            self.output = crate::read_path_from_stdin()?;
+        }
+        info!("outputting charts in \"{}\"", self.output);
+        Ok(())
+    }
+    fn apply(&self, store: &mut SimfileStore) -> Result<()> {
+        //Convert every chart, skipping the ones that Quaver cannot represent
+        let mut simfiles = Vec::new();
+        let mut quas = Vec::new();
+        store.get_each(&self.from, |_, sm| {
+            match to_qua(&sm) {
+                Ok(qua) => {
+                    quas.push(qua);
+                    simfiles.push(sm);
+                }
+                Err(err) => warn!("  skipping chart: {:#}", err),
+            }
+            Ok(())
+        })?;
+        if simfiles.is_empty() {
+            return Ok(());
+        }
+        let out_base = resolve_out_dir(store, &self.output)?;
+        //Write one chart per simfile
+        let mut used_names = HashSet::default();
+        for qua in quas.iter_mut() {
+            qua.difficulty_name = unique_file_name(&mut used_names, &qua.difficulty_name);
+            let filename = sanitize_file_name(&format!(
+                "{} - {} [{}].qua",
+                qua.artist, qua.title, qua.difficulty_name
+            ));
+            let out_path = out_base.join(filename);
+            debug!("  writing chart to \"{}\"", out_path.display());
+            qua.save(&out_path)
+                .with_context(|| anyhow!("write chart to \"{}\"", out_path.display()))?;
+        }
+        //Copy over dependencies (backgrounds, audio, etc...)
+        copy_set_deps(store, &self.copy, &out_base, &simfiles)?;
+        Ok(())
+    }
+    fn buckets_mut(&mut self) -> BucketIter<'_> {
+        Box::new(iter::once((BucketKind::Input, &mut self.from)))
+    }
+}
+
+fn to_qua(sm: &Simfile) -> Result<Qua> {
+    ensure!(!sm.bpms.is_empty(), "simfile has no bpms");
+    let key_count = sm.gamemode.key_count();
+    let mode = QuaMode::from_key_count(key_count).ok_or_else(|| {
+        anyhow!(
+            "cannot write {} chart \"{} - {}\": Quaver only supports 4K and 7K charts",
+            sm.gamemode.id(),
+            sm.artist,
+            sm.title,
+        )
+    })?;
+    let name = chart_display_name(sm);
+    let mut qua = Qua {
+        audio_file: path_str(&sm.music),
+        song_preview_time: sm
+            .sample_start
+            .map(|start| (start * 1000.).round() as i32)
+            .unwrap_or(0),
+        background_file: path_str(&sm.background),
+        mode,
+        title: or_trans(&sm.title, &sm.title_trans),
+        artist: or_trans(&sm.artist, &sm.artist_trans),
+        tags: "osu2sm".to_string(),
+        creator: sm.credit.clone(),
+        difficulty_name: name.to_string(),
+        genre: sm.genre.clone(),
+        custom_audio_samples: sm
+            .keysounds
+            .iter()
+            .map(|path| QuaAudioSample {
+                path: path.to_string_lossy().into_owned(),
+                unaffected_by_rate: false,
+            })
+            .collect(),
+        ..default()
+    };
+
+    //Add a timing point at every bpm or time signature change, and after every stop
+    let change_beats = sm.timing_beats();
+    let mut to_time = sm.beat_to_time();
+    let mut cp_idx = 0;
+    for &beat in change_beats.iter() {
+        while cp_idx + 1 < sm.bpms.len() && sm.bpms[cp_idx + 1].beat <= beat {
+            cp_idx += 1;
+        }
+        let meter = sm
+            .time_signatures
+            .iter()
+            .take_while(|&&(sig_beat, _, _)| BeatPos::from(sig_beat) <= beat)
+            .last()
+            .map(|&(_, num, _)| num)
+            .unwrap_or(4);
+        qua.timing_points.push(QuaTimingPoint {
+            start_time: to_time.beat_to_time_after(beat) * 1000.,
+            bpm: sm.bpms[cp_idx].bpm(),
+            signature: if meter == 3 {
+                QuaSignature::Triple
+            } else {
+                QuaSignature::Quadruple
+            },
+        });
+    }
+    //Scroll changes map directly to slider velocities
+    let mut to_time = sm.beat_to_time();
+    for &(beat, ratio) in sm.scrolls.iter() {
+        qua.slider_velocities.push(QuaSliderVelocity {
+            start_time: to_time.beat_to_time(BeatPos::from(beat)) * 1000.,
+            multiplier: ratio,
+        });
+    }
+
+    //Convert notes into hit objects, dropping mines and fakes
+    let key_sounds = |note: &Note| {
+        note.keysound
+            .filter(|&idx| (idx as usize) < sm.keysounds.len())
+            .map(|idx| QuaKeySound {
+                sample: idx + 1,
+                volume: 100,
+            })
+            .into_iter()
+            .collect::<Vec<_>>()
+    };
+    let mut to_time = sm.beat_to_time();
+    let mut pending_heads: Vec<Option<usize>> = vec![None; key_count as usize];
+    for note in sm.notes.iter() {
+        let time = (to_time.beat_to_time(note.beat) * 1000.).round() as i32;
+        if note.is_step() {
+            if note.is_head() {
+                pending_heads[note.key as usize] = Some(qua.hit_objects.len());
+            }
+            qua.hit_objects.push(QuaHitObject {
+                start_time: time,
+                lane: note.key + 1,
+                end_time: 0,
+                key_sounds: key_sounds(note),
+            });
+        } else if note.is_tail() {
+            let head_idx = pending_heads[note.key as usize]
+                .take()
+                .ok_or_else(|| anyhow!("tail at beat {} has no head", note.beat))?;
+            qua.hit_objects[head_idx].end_time = time;
+        }
+    }
+    Ok(qua)
+}
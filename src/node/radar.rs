@@ -0,0 +1,98 @@
+//! Compute the groove radar values shown in the StepMania song wheel.
+//!
+//! Follows the calculations in StepMania's
+//! [`NoteDataUtil.cpp`](https://github.com/stepmania/stepmania/blob/5_1-new/src/NoteDataUtil.cpp),
+//! except that the length of the chart is measured from the first to the last note instead of
+//! using the length of the music file.
+
+use crate::node::prelude::*;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Radar {
+    pub from: BucketId,
+    pub into: BucketId,
+}
+impl Default for Radar {
+    fn default() -> Self {
+        Self {
+            from: default(),
+            into: default(),
+        }
+    }
+}
+
+impl Node for Radar {
+    fn apply(&self, store: &mut SimfileStore) -> Result<()> {
+        store.get(&self.from, |store, list| {
+            for sm in list.iter_mut() {
+                sm.radar = compute_radar(sm);
+                trace!("    computed radar values {:?}", sm.radar);
+            }
+            store.put(&self.into, mem::replace(list, default()));
+            Ok(())
+        })
+    }
+    fn buckets_mut(&mut self) -> BucketIter<'_> {
+        Box::new(
+            iter::once((BucketKind::Input, &mut self.from))
+                .chain(iter::once((BucketKind::Output, &mut self.into))),
+        )
+    }
+}
+
+/// Stream, voltage, air, freeze and chaos, in the same order as `Simfile::radar`.
+fn compute_radar(sm: &Simfile) -> [f64; 5] {
+    let mut to_time = sm.beat_to_time();
+    let mut first = None;
+    let mut last = (BeatPos::from(0.), 0.);
+    let mut taps = 0;
+    let mut jumps = 0;
+    let mut holds = 0;
+    let mut chaos_rows = 0;
+    //Amount of taps in each 8-beat window
+    let mut windows: Vec<usize> = Vec::new();
+    for beat in sm.iter_beats() {
+        let notes = &sm.notes[beat.start_idx..beat.end_idx];
+        if !notes.iter().any(|note| note.is_step() || note.is_tail()) {
+            continue;
+        }
+        let time = to_time.beat_to_time(beat.pos);
+        first.get_or_insert(time);
+        last = (beat.pos, time);
+        let count = beat.count_heads(&sm.notes);
+        if count == 0 {
+            continue;
+        }
+        taps += count;
+        if count >= 2 {
+            jumps += 1;
+        }
+        holds += notes.iter().filter(|note| note.is_head()).count();
+        //Anything finer than 8ths is chaotic
+        if !beat.pos.is_aligned(BeatPos::from(0.5)) {
+            chaos_rows += 1;
+        }
+        let window = (beat.pos.as_num() / 8.).floor().max(0.) as usize;
+        if windows.len() <= window {
+            windows.resize(window + 1, 0);
+        }
+        windows[window] += count;
+    }
+    let secs = match first {
+        Some(first) if last.1 > first => last.1 - first,
+        _ => return [0.; 5],
+    };
+    let stream = taps as f64 / secs / 7.;
+    let avg_bps = last.0.as_num() / secs;
+    let max_density = windows.iter().copied().max().unwrap_or(0) as f64 / 8.;
+    let voltage = max_density * avg_bps / 10.;
+    let air = jumps as f64 / secs;
+    let freeze = holds as f64 / secs / 0.5;
+    let chaos = chaos_rows as f64 / secs * 0.5;
+    let mut radar = [stream, voltage, air, freeze, chaos];
+    for value in radar.iter_mut() {
+        *value = value.min(1.);
+    }
+    radar
+}
@@ -63,6 +63,24 @@ pub enum RateMethod {
     /// Outputs the "average" note density in notes / sec.
     /// Scale `x60` to obtain effective BPM.
     Gap(NoteGap),
+    /// Use the osu!mania strain model, which accounts for per-key strain, overlapping holds and
+    /// chords.
+    ///
+    /// Outputs an approximation of osu!mania star ratings.
+    /// Use a `scale` of `(0, 1, 0, 1)` to give `set_diff` thresholds in stars.
+    Strain(NoteStrain),
+    /// Rate each skillset (stream, jumpstream, handstream, jack, chordjack and technical)
+    /// separately, and store the ratings in the simfile so that `Filter` and `Select` can use
+    /// them.
+    ///
+    /// Outputs the overall rating, which is the hardest skillset, in notes / sec.
+    Skillsets(NoteSkillsets),
+    /// Use a difficulty value of the source beatmap, such as its overall difficulty or its star
+    /// rating.
+    ///
+    /// Charts without the value (eg. because they were not loaded from osu! beatmaps) are rated
+    /// `0`.
+    FromSource(SourceValue),
 }
 impl Default for RateMethod {
     fn default() -> Self {
@@ -121,7 +139,68 @@ impl Default for NoteGap {
     }
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NoteStrain {
+    /// The length of the sections in which the chart is split, in seconds.
+    /// The peak strain of each section is used to compute the final difficulty.
+    pub section_len: f64,
+    /// How much weight each section peak has relative to the previous one, after sorting them
+    /// from hardest to easiest.
+    pub decay_weight: f64,
+    /// A factor to convert the weighted strain into stars.
+    pub star_scaling: f64,
+}
+impl Default for NoteStrain {
+    fn default() -> Self {
+        Self {
+            section_len: 0.4,
+            decay_weight: 0.9,
+            star_scaling: 0.018,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NoteSkillsets {
+    /// The length of the windows in which patterns are detected, in seconds.
+    pub window: f64,
+    /// How much weight to give to short hard windows over long easy windows.
+    pub exponent: f64,
+}
+impl Default for NoteSkillsets {
+    fn default() -> Self {
+        Self {
+            window: 4.,
+            exponent: 2.,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum SourceValue {
+    OverallDifficulty,
+    HpDrain,
+    /// The osu!mania star rating, only available for osu!mania beatmaps.
+    /// Taken from `osu!.db` when possible, approximated with the `Strain` method otherwise.
+    Stars,
+}
+
 impl Node for Rate {
+    fn prepare(&mut self) -> Result<()> {
+        match &self.method {
+            RateMethod::Strain(conf) => ensure!(
+                conf.section_len > 0.,
+                "strain section length must be positive"
+            ),
+            RateMethod::Skillsets(conf) => {
+                ensure!(conf.window > 0., "skillset window length must be positive")
+            }
+            _ => {}
+        }
+        Ok(())
+    }
     fn apply(&self, store: &mut SimfileStore) -> Result<()> {
         store.get(&self.from, |store, list| {
             for sm in list.iter_mut() {
@@ -144,6 +223,13 @@ fn rate(conf: &Rate, sm: &mut Simfile) -> Result<()> {
         RateMethod::Count(conf) => get_note_count(conf, sm),
         RateMethod::Density(conf) => get_note_density(conf, sm),
         RateMethod::Gap(conf) => get_note_gap(conf, sm),
+        RateMethod::Strain(conf) => get_note_strain(conf, sm),
+        RateMethod::Skillsets(conf) => {
+            sm.skillsets = get_skillsets(conf, sm);
+            trace!("    skillset ratings: {:?}", sm.skillsets);
+            sm.skillsets.overall
+        }
+        RateMethod::FromSource(value) => get_source_value(value, sm),
     };
     let scaled = {
         let [in_min, in_max, out_min, out_max] = conf.scale;
@@ -165,7 +251,7 @@ fn rate(conf: &Rate, sm: &mut Simfile) -> Result<()> {
 fn get_note_count(conf: &NoteCount, sm: &Simfile) -> f64 {
     let mut count = 0;
     for note in sm.notes.iter() {
-        if !note.is_tail() {
+        if note.is_step() {
             count += 1;
         }
     }
@@ -282,3 +368,353 @@ fn get_note_gap(conf: &NoteGap, sm: &Simfile) -> f64 {
     }
     total_freq as f64
 }
+
+/// The osu!mania star rating of a simfile, using the default `Strain` settings.
+pub fn mania_stars(sm: &Simfile) -> f64 {
+    get_note_strain(&default(), sm)
+}
+
+/// The star rating of a chart converted from an osu!mania beatmap.
+/// Uses the rating cached in `osu!.db` if available, and approximates it with `mania_stars`
+/// otherwise.
+pub fn source_stars(sm: &Simfile) -> Option<f64> {
+    sm.source_meta.stars.or_else(|| {
+        //Star ratings only make sense for unconverted osu!mania beatmaps
+        if sm.source_meta.osu_mode == Some(osufile::MODE_MANIA) {
+            Some(mania_stars(sm))
+        } else {
+            None
+        }
+    })
+}
+
+/// A port of the osu!mania strain skill.
+fn get_note_strain(conf: &NoteStrain, sm: &Simfile) -> f64 {
+    const INDIVIDUAL_DECAY_BASE: f64 = 0.125;
+    const OVERALL_DECAY_BASE: f64 = 0.3;
+    let key_count = sm.gamemode.key_count() as usize;
+    //Gather `(start, end, key)` for every note the player has to hit, in seconds
+    let mut to_time = sm.beat_to_time();
+    let mut objects = Vec::with_capacity(sm.notes.len());
+    let mut open_heads = vec![None; key_count];
+    for note in sm.notes.iter() {
+        let key = note.key as usize;
+        if note.is_step() {
+            let time = to_time.beat_to_time(note.beat);
+            if note.is_head() {
+                open_heads[key] = Some(objects.len());
+            }
+            objects.push((time, time, key));
+        } else if note.is_tail() {
+            if let Some(head_idx) = open_heads[key].take() {
+                objects[head_idx].1 = to_time.beat_to_time(note.beat);
+            }
+        }
+    }
+    if objects.len() < 2 {
+        return 0.;
+    }
+    let decay = |value: f64, dt: f64, base: f64| value * base.powf(dt);
+    //Two times closer than a millisecond are considered equal
+    let bigger = |a: f64, b: f64| a - b > 0.001;
+    let mut hold_ends = vec![0.; key_count];
+    let mut individual_strains = vec![0.; key_count];
+    let mut individual_strain = 0.;
+    let mut overall_strain = 1.;
+    let mut peaks = Vec::new();
+    let mut section_end = (objects[1].0 / conf.section_len).ceil() * conf.section_len;
+    let mut section_peak: f64 = 0.;
+    for pair in objects.windows(2) {
+        let (prev_time, _, _) = pair[0];
+        let (time, end, key) = pair[1];
+        //Start new sections, with the strain decayed up to the section start
+        while time > section_end {
+            peaks.push(section_peak);
+            let dt = section_end - prev_time;
+            section_peak = decay(individual_strain, dt, INDIVIDUAL_DECAY_BASE)
+                + decay(overall_strain, dt, OVERALL_DECAY_BASE);
+            section_end += conf.section_len;
+        }
+        //Strain for this note
+        let dt = time - prev_time;
+        let mut hold_factor = 1.;
+        let mut hold_addition = 0.;
+        for (hold_end, strain) in hold_ends.iter().zip(individual_strains.iter_mut()) {
+            //Releasing in the middle of another hold is awkward, unless both are released together
+            if bigger(*hold_end, time) && bigger(end, *hold_end) {
+                hold_addition = 1.;
+            }
+            if (end - *hold_end).abs() <= 0.001 {
+                hold_addition = 0.;
+            }
+            //Everything is harder while holding
+            if bigger(*hold_end, end) {
+                hold_factor = 1.25;
+            }
+            *strain = decay(*strain, dt, INDIVIDUAL_DECAY_BASE);
+        }
+        hold_ends[key] = end;
+        individual_strains[key] += 2. * hold_factor;
+        individual_strain = individual_strains[key];
+        overall_strain =
+            decay(overall_strain, dt, OVERALL_DECAY_BASE) + (1. + hold_addition) * hold_factor;
+        section_peak = section_peak.max(individual_strain + overall_strain);
+    }
+    peaks.push(section_peak);
+    //Weigh the hardest sections the most
+    peaks.sort_unstable_by_key(|&peak| cmp::Reverse(SortableFloat(peak)));
+    let mut difficulty = 0.;
+    let mut weight = 1.;
+    for peak in peaks {
+        difficulty += peak * weight;
+        weight *= conf.decay_weight;
+    }
+    difficulty * conf.star_scaling
+}
+
+fn get_skillsets(conf: &NoteSkillsets, sm: &Simfile) -> Skillsets {
+    //Amount of notes of each pattern, indexed by `Skillset as usize`
+    type Counts = [f64; 7];
+    let mut to_time = sm.beat_to_time();
+    let mut windows: Vec<Counts> = Vec::new();
+    let mut first_time = None;
+    let mut last_keys = 0u64;
+    let mut last_times = (f64::NAN, f64::NAN);
+    for beat in sm.iter_beats() {
+        let count = beat.count_heads(&sm.notes);
+        if count == 0 {
+            continue;
+        }
+        let keys = sm.notes[beat.start_idx..beat.end_idx]
+            .iter()
+            .filter(|note| note.is_step())
+            .fold(0u64, |keys, note| keys | 1 << (note.key as u64 % 64));
+        let time = to_time.beat_to_time(beat.pos);
+        let first_time = *first_time.get_or_insert(time);
+        //Classify this row
+        let jack = keys & last_keys != 0;
+        let pattern = match (count, jack) {
+            (1, false) => Skillset::Stream,
+            (2, false) => Skillset::Jumpstream,
+            (_, false) => Skillset::Handstream,
+            (1, true) | (2, true) => Skillset::Jack,
+            (_, true) => Skillset::Chordjack,
+        };
+        //A row is technical if the rhythm changes
+        let gap = time - last_times.1;
+        let last_gap = last_times.1 - last_times.0;
+        let technical = (gap - last_gap).abs() > 0.1 * gap.min(last_gap);
+        //Accumulate into the current window
+        let window = ((time - first_time) / conf.window).floor().max(0.) as usize;
+        if windows.len() <= window {
+            windows.resize(window + 1, [0.; 7]);
+        }
+        windows[window][pattern as usize] += count as f64;
+        if technical {
+            windows[window][Skillset::Technical as usize] += count as f64;
+        }
+        last_keys = keys;
+        last_times = (last_times.1, time);
+    }
+    //Breaks in the chart are not easy sections, so leave windows without rows out of the average
+    windows.retain(|counts| counts.iter().any(|&count| count > 0.));
+    if windows.is_empty() {
+        return default();
+    }
+    //Average out the densities of each pattern, weighing hard windows more
+    let mut ratings = [0.; 7];
+    for (idx, rating) in ratings.iter_mut().enumerate() {
+        let total = windows
+            .iter()
+            .map(|counts| (counts[idx] / conf.window).powf(conf.exponent))
+            .sum::<f64>();
+        *rating = (total / windows.len() as f64).powf(1. / conf.exponent);
+    }
+    let mut skillsets = Skillsets {
+        overall: 0.,
+        stream: ratings[Skillset::Stream as usize],
+        jumpstream: ratings[Skillset::Jumpstream as usize],
+        handstream: ratings[Skillset::Handstream as usize],
+        jack: ratings[Skillset::Jack as usize],
+        chordjack: ratings[Skillset::Chordjack as usize],
+        technical: ratings[Skillset::Technical as usize],
+    };
+    skillsets.overall = ratings.iter().copied().fold(0., f64::max);
+    skillsets
+}
+
+fn get_source_value(value: &SourceValue, sm: &Simfile) -> f64 {
+    let meta = &sm.source_meta;
+    let val = match value {
+        SourceValue::OverallDifficulty => meta.overall_difficulty,
+        SourceValue::HpDrain => meta.hp_drain,
+        SourceValue::Stars => source_stars(sm),
+    };
+    val.unwrap_or_else(|| {
+        debug!(
+            "    chart \"{}\" has no source {:?}, rating it 0",
+            sm.desc, value
+        );
+        0.
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 4K chart at 120 BPM, so that every beat is half a second long.
+    fn chart(notes: &[(char, f64, i32)]) -> Simfile {
+        Simfile {
+            gamemode: Gamemode::DanceSingle,
+            bpms: vec![ControlPoint {
+                beat: BeatPos::from(0.),
+                beat_len: 0.5,
+            }],
+            notes: notes
+                .iter()
+                .map(|&(kind, beat, key)| Note {
+                    kind,
+                    beat: BeatPos::from(beat),
+                    key,
+                    keysound: None,
+                    player: 0,
+                })
+                .collect(),
+            ..default()
+        }
+    }
+
+    /// A single section with no star scaling, so that the result is the peak strain.
+    fn peak_strain(sm: &Simfile) -> f64 {
+        let conf = NoteStrain {
+            section_len: 100.,
+            decay_weight: 0.9,
+            star_scaling: 1.,
+        };
+        get_note_strain(&conf, sm)
+    }
+
+    //How much individual and overall strain are left after half a second
+    fn individual_decay() -> f64 {
+        0.125f64.powf(0.5)
+    }
+    fn overall_decay() -> f64 {
+        0.3f64.powf(0.5)
+    }
+
+    #[test]
+    fn jack_strain() {
+        use Note as N;
+        let sm = chart(&[
+            (N::KIND_HIT, 0., 0),
+            (N::KIND_HIT, 1., 0),
+            (N::KIND_HIT, 2., 0),
+            (N::KIND_HIT, 3., 0),
+        ]);
+        //The first note adds no strain, every other note adds 2 to the column and 1 overall
+        let (i, o) = (individual_decay(), overall_decay());
+        let individual = 2. + 2. * i + 2. * i * i;
+        let overall = o * o * o + o * o + o + 1.;
+        assert!((peak_strain(&sm) - (individual + overall)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn chord_stream_strain() {
+        use Note as N;
+        //Start after time 0, otherwise the first chord would get a zero-length section of its own
+        let sm = chart(&[
+            (N::KIND_HIT, 1., 0),
+            (N::KIND_HIT, 1., 1),
+            (N::KIND_HIT, 2., 2),
+            (N::KIND_HIT, 2., 3),
+            (N::KIND_HIT, 3., 0),
+            (N::KIND_HIT, 3., 1),
+        ]);
+        //The last note hits column 1 for the second time, one second after the first time
+        let (i, o) = (individual_decay(), overall_decay());
+        let individual = 2. * i * i + 2.;
+        //Overall strain does not decay between the notes of a chord
+        let overall = ((1. + 1.) * o + 1. + 1.) * o + 1. + 1.;
+        assert!((peak_strain(&sm) - (individual + overall)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn overlapping_hold_strain() {
+        use Note as N;
+        let sm = chart(&[
+            (N::KIND_HIT, 0., 3),
+            (N::KIND_HEAD, 1., 0),
+            (N::KIND_HIT, 2., 1),
+            (N::KIND_HEAD, 3., 2),
+            (N::KIND_TAIL, 5., 0),
+            (N::KIND_TAIL, 6., 2),
+        ]);
+        //Like osu!, the first note is not rated, so it is a tap that comes before the holds
+        let o = overall_decay();
+        let hold_strain = 2. + (o + 1.);
+        //The tap is inside the first hold, so its strain is multiplied by 1.25
+        let tap_strain = 2. * 1.25 + ((o + 1.) * o + 1.25);
+        //The second hold starts inside the first one and is released after it, adding 1 to the
+        //overall strain
+        let overlap_strain = 2. + (((o + 1.) * o + 1.25) * o + 1. + 1.);
+        assert!(hold_strain < tap_strain && tap_strain < overlap_strain);
+        assert!((peak_strain(&sm) - overlap_strain).abs() < 1e-9);
+    }
+
+    #[test]
+    fn strain_sections() {
+        use Note as N;
+        let sm = chart(&[
+            (N::KIND_HIT, 0., 0),
+            (N::KIND_HIT, 1., 1),
+            (N::KIND_HIT, 2., 2),
+        ]);
+        //With the default 0.4s sections, each note is in its own section, and the hardest
+        //section is weighted fully while the next one is weighted by 0.9
+        let o = overall_decay();
+        let first = 2. + o + 1.;
+        let second = 2. + (o + 1.) * o + 1.;
+        let expected = (second + 0.9 * first) * 0.018;
+        assert!((mania_stars(&sm) - expected).abs() < 1e-9);
+    }
+
+    /// Rows of 16th notes at 120 BPM, cycling through the given columns.
+    fn rows(start: f64, count: usize, keys: &[i32]) -> Vec<(char, f64, i32)> {
+        (0..count)
+            .map(|i| {
+                (
+                    Note::KIND_HIT,
+                    start + i as f64 * 0.25,
+                    keys[i % keys.len()],
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn jack_skillset() {
+        let sm = chart(&rows(0., 32, &[0]));
+        let skillsets = get_skillsets(&default(), &sm);
+        assert!(skillsets.jack > skillsets.stream);
+        assert_eq!(skillsets.overall, skillsets.jack);
+    }
+
+    #[test]
+    fn stream_skillset() {
+        let sm = chart(&rows(0., 32, &[0, 1, 2, 3]));
+        let skillsets = get_skillsets(&default(), &sm);
+        assert!(skillsets.stream > skillsets.jack);
+        assert_eq!(skillsets.overall, skillsets.stream);
+    }
+
+    #[test]
+    fn skillsets_skip_breaks() {
+        //Two bursts of stream, with a break several windows long between them
+        let mut notes = rows(0., 32, &[0, 1, 2, 3]);
+        notes.extend(rows(64., 32, &[0, 1, 2, 3]));
+        let with_break = get_skillsets(&default(), &chart(&notes));
+        let without_break = get_skillsets(&default(), &chart(&rows(0., 64, &[0, 1, 2, 3])));
+        assert!((with_break.stream - without_break.stream).abs() < 1e-9);
+    }
+}
@@ -35,6 +35,14 @@ impl Node for Rekey {
     fn apply(&self, store: &mut SimfileStore) -> Result<()> {
         store.get(&self.from, |store, list| {
             for sm in list.iter_mut() {
+                //Routine charts have one set of notes per player sharing the same keys
+                if sm.gamemode.is_routine() {
+                    warn!(
+                        "    skipping {} chart, routine charts are not supported",
+                        sm.gamemode.id()
+                    );
+                    continue;
+                }
                 rekey(sm, self)?;
                 sm.gamemode = self.gamemode;
             }
@@ -54,6 +62,7 @@ pub struct KeyAlloc {
     weight_points: Vec<(f32, f32, f32)>,
     default_weight: f32,
     last_active: Vec<f64>,
+    tmp_keys: Vec<usize>,
 }
 impl KeyAlloc {
     pub fn new(key_count: usize) -> KeyAlloc {
@@ -61,9 +70,14 @@ impl KeyAlloc {
             weight_points: Vec::new(),
             default_weight: 1.,
             last_active: vec![f64::NEG_INFINITY; key_count],
+            tmp_keys: Vec::with_capacity(key_count),
         }
     }
 
+    pub fn key_count(&self) -> usize {
+        self.last_active.len()
+    }
+
     pub fn set_weight_curve(&mut self, weight_curve: &[(f32, f32)]) {
         self.weight_points.clear();
         self.weight_points
@@ -116,6 +130,35 @@ impl KeyAlloc {
         self.alloc(keys, time, rng)
             .map(|key| (keys.iter().position(|&k| k == key).unwrap(), key))
     }
+
+    /// Allocate up to `count` distinct keys out of `keys`, and append them to `out`.
+    /// Returns the amount of keys allocated, which might be less than `count` if there are not
+    /// enough keys.
+    pub fn alloc_many(
+        &mut self,
+        keys: &[usize],
+        count: usize,
+        time: f64,
+        rng: &mut FastRng,
+        out: &mut Vec<usize>,
+    ) -> usize {
+        let mut tmp_keys = mem::take(&mut self.tmp_keys);
+        tmp_keys.clear();
+        tmp_keys.extend_from_slice(keys);
+        let mut allocated = 0;
+        while allocated < count {
+            match self.alloc_idx(&tmp_keys, time, rng) {
+                Some((pos, key)) => {
+                    tmp_keys.swap_remove(pos);
+                    out.push(key);
+                    allocated += 1;
+                }
+                None => break,
+            }
+        }
+        self.tmp_keys = tmp_keys;
+        allocated
+    }
 }
 
 /// Shuffle keys around, changing keycount in the way.
@@ -143,6 +186,9 @@ fn rekey(sm: &mut Simfile, conf: &Rekey) -> Result<()> {
 
     //Detach note buffer for lifetiming purposes
     let mut notes = mem::replace(&mut sm.notes, Vec::new());
+    //Place the steps of each row before its mines and fakes, so that mines and fakes only take
+    //the keys that steps left free
+    notes.sort_by_key(|note| (note.beat, !(note.is_step() || note.is_tail())));
     //To randomize key mappings
     let mut rng = simfile_rng(sm, "rekey");
     //Beat -> time
@@ -172,6 +218,23 @@ fn rekey(sm: &mut Simfile, conf: &Rekey) -> Result<()> {
             locked_outkeys[out_key] = None;
             key_alloc.touch(out_key, note_time);
             out_key as i32
+        } else if !note.is_step() {
+            //Mines and fakes go on any free key, without counting as key activity
+            choose_tmp_buf.clear();
+            choose_tmp_buf.extend(
+                locked_outkeys
+                    .iter()
+                    .enumerate()
+                    .filter(|(_i, locked)| locked.is_none())
+                    .map(|(i, _locked)| i),
+            );
+            match choose_tmp_buf.choose(&mut rng) {
+                Some(&out_key) => {
+                    locked_outkeys[out_key] = Some(Some(note.beat));
+                    out_key as i32
+                }
+                None => -1,
+            }
         } else {
             //Choose an outkey using randomness and weights
             choose_tmp_buf.clear();
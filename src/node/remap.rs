@@ -210,6 +210,8 @@ fn remap(sm: &mut Simfile, conf: &Remap) -> Result<Vec<Note>> {
                         beat,
                         key: key as i32,
                         kind: Note::KIND_HIT,
+                        keysound: None,
+                        player: 0,
                     });
                 }
                 last_beat += BeatPos::from(unit);
@@ -27,6 +27,11 @@ pub struct Select {
     ///
     /// Defaults to the entire range of difficulties (`Beginner` - `Challenge`, `Edit`).
     pub diff_names: Vec<Difficulty>,
+    /// Rank charts by a skillset rating (computed by `RateMethod::Skillsets`) instead of by their
+    /// numerical difficulty.
+    /// For example, `skillset: Some(Jack)` with `max: 1` and `prefer: Harder` keeps only the
+    /// hardest jack chart.
+    pub skillset: Option<Skillset>,
 }
 impl Default for Select {
     fn default() -> Self {
@@ -40,6 +45,7 @@ impl Default for Select {
             prefer: default(),
             dedup_dist: 0.,
             dedup_bias: 0.5,
+            skillset: None,
         }
     }
 }
@@ -202,17 +208,22 @@ pub fn trim_difficulties(conf: &Select, simfiles: &mut Vec<Box<Simfile>>) -> Res
         return Ok(());
     }
 
+    let rating = |sm: &Simfile| match conf.skillset {
+        Some(skillset) => sm.skillsets.get(skillset),
+        None => sm.difficulty_num,
+    };
+
     //Make sure some rating system was used
     ensure!(
-        simfiles.iter().all(|sm| sm.difficulty_num.is_finite()),
+        simfiles.iter().all(|sm| rating(sm).is_finite()),
         "cannot fix simfiles without a difficulty rating (use the `Rate` node before `Select`): difficulties are {:?}",
-        simfiles.iter().map(|sm| sm.difficulty_num).collect::<Vec<_>>()
+        simfiles.iter().map(|sm| rating(sm)).collect::<Vec<_>>()
     );
 
     //Create an auxiliary vec holding chart indices and difficulties
     let mut order = simfiles
         .iter()
-        .map(|sm| sm.difficulty_num)
+        .map(|sm| rating(sm))
         .enumerate()
         .collect::<Vec<_>>();
     trace!("    raw difficulties: {:?}", order);
@@ -258,19 +269,18 @@ pub fn trim_difficulties(conf: &Select, simfiles: &mut Vec<Box<Simfile>>) -> Res
     trace!("    with conflicts resolved: {:?}", order);
 
     //Reorder charts
-    for chart in simfiles.iter_mut() {
-        chart.difficulty_num = f64::NAN;
-    }
-    for (idx, diff) in order.iter() {
-        simfiles[*idx].difficulty_num = *diff;
+    let mut keep = vec![false; simfiles.len()];
+    for (idx, _diff) in order.iter() {
+        keep[*idx] = true;
     }
-    simfiles.retain(|chart| !chart.difficulty_num.is_nan());
-    simfiles.sort_by_key(|chart| SortableFloat(chart.difficulty_num));
+    let mut keep = keep.into_iter();
+    simfiles.retain(|_| keep.next().unwrap_or(false));
+    simfiles.sort_by_key(|chart| SortableFloat(rating(chart)));
     trace!(
         "    final chart difficulties: {:?}",
         simfiles
             .iter()
-            .map(|chart| chart.difficulty_num)
+            .map(|chart| rating(chart))
             .collect::<Vec<_>>()
     );
 
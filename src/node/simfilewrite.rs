@@ -19,14 +19,24 @@ pub struct SimfileWrite {
     /// Cannot be set from the config, it is only used as an internal cache.
     #[serde(skip)]
     pub in_place_from: RefCell<Option<PathBuf>>,
-    /// Remove all files in the output directory or subdirectories matching the `osu2sm-*.sm`
-    /// filename, where `*` stands for anything.
+    /// Remove all files in the output directory or subdirectories matching the `osu2sm-*.sm` or
+    /// `osu2sm-*.ssc` filenames, where `*` stands for anything.
     pub cleanup: bool,
     /// Whether to automatically correct output paths if they point somewhere within a StepMania
     /// installation.
     pub fix_output: bool,
     /// The path to the output directory (a StepMania song group).
     pub output: String,
+    /// Write simfiles that belong to osu! collections into one song group per collection, next to
+    /// the `output` song group.
+    /// Simfiles in several collections are written once per collection, and simfiles that are not
+    /// in any collection are written into `output` as usual.
+    ///
+    /// Requires `OsuLoad::collections` to be enabled.
+    pub collection_groups: bool,
+    /// Which simfile formats to write.
+    /// If both are written StepMania prefers the `.ssc` file.
+    pub formats: Vec<SimfileFormat>,
 }
 
 impl Default for SimfileWrite {
@@ -60,6 +70,25 @@ impl Default for SimfileWrite {
                 }
             },
             cleanup: false,
+            collection_groups: false,
+            formats: vec![SimfileFormat::Sm],
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Hash)]
+pub enum SimfileFormat {
+    /// The legacy `.sm` format, where all charts share the timing data of the first chart.
+    Sm,
+    /// The newer `.ssc` format, with per-chart timing data, time signatures, scroll segments and
+    /// chart names.
+    Ssc,
+}
+impl SimfileFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SimfileFormat::Sm => "sm",
+            SimfileFormat::Ssc => "ssc",
         }
     }
 }
@@ -132,7 +161,7 @@ impl Node for SimfileWrite {
         //Cleanup output
         if self.cleanup {
             info!(
-                "cleanup enabled, removing all `osu2sm-*.sm` files under \"{}\"",
+                "cleanup enabled, removing all `osu2sm-*.sm` and `osu2sm-*.ssc` files under \"{}\"",
                 self.output
             );
             let mut files_removed = 0;
@@ -146,7 +175,9 @@ impl Node for SimfileWrite {
                 };
                 if file.file_type().is_file() {
                     let filename = file.file_name().to_string_lossy();
-                    if filename.starts_with("osu2sm-") && filename.ends_with(".sm") {
+                    if filename.starts_with("osu2sm-")
+                        && (filename.ends_with(".sm") || filename.ends_with(".ssc"))
+                    {
                         match fs::remove_file(file.path()) {
                             Ok(()) => {
                                 files_removed += 1;
@@ -168,26 +199,32 @@ impl Node for SimfileWrite {
         Ok(())
     }
     fn apply(&self, store: &mut SimfileStore) -> Result<()> {
-        //Organize output simfiles
-        let mut by_music: HashMap<PathBuf, Vec<Box<Simfile>>> = HashMap::default();
+        //Organize output simfiles, by collection group and music file
+        let mut by_music: HashMap<(Option<String>, PathBuf), Vec<Box<Simfile>>> =
+            HashMap::default();
         store.get_each(&self.from, |_, mut sm| {
             //Fix some `.sm` quirks
             sm.fix_tails()?;
-            //Append to the appropiate list
-            let list = by_music
-                .entry(
-                    AsRef::<Path>::as_ref(
-                        sm.music.as_ref().map(|p| p.as_os_str()).unwrap_or_default(),
-                    )
-                    .to_path_buf(),
-                )
-                .or_default();
-            list.push(sm);
+            //Append to the appropiate lists
+            let music =
+                AsRef::<Path>::as_ref(sm.music.as_ref().map(|p| p.as_os_str()).unwrap_or_default())
+                    .to_path_buf();
+            if self.collection_groups && !sm.collections.is_empty() {
+                for collection in sm.collections.iter() {
+                    by_music
+                        .entry((Some(collection.clone()), music.clone()))
+                        .or_default()
+                        .push(sm.clone());
+                }
+            } else {
+                by_music.entry((None, music)).or_default().push(sm);
+            }
             Ok(())
         })?;
         //Get globals
         let root_path = store.global_get_expect("root")?;
         let set_path = store.global_get_expect("base")?;
+        let from_archive = store.global_get("archive").is_some();
         //Handle in-place-ness lazily on the first simfile
         if self.in_place {
             let mut in_place_from = self.in_place_from.borrow_mut();
@@ -221,10 +258,20 @@ impl Node for SimfileWrite {
                 );
             }
         }
+        if from_archive && in_place_enabled(self) {
+            info!("  in-place conversion is not possible for beatmapsets inside archives, extracting files instead");
+        }
         //Write output simfiles
-        for (_music_path, simfiles) in by_music {
+        for ((group, _music_path), simfiles) in by_music {
             //Write a single `.sm` for these simfiles
-            write_sm(self, root_path.as_ref(), set_path.as_ref(), &simfiles)?;
+            write_sm(
+                self,
+                root_path.as_ref(),
+                set_path.as_ref(),
+                from_archive,
+                group.as_deref(),
+                &simfiles,
+            )?;
         }
         Ok(())
     }
@@ -245,83 +292,238 @@ fn write_sm(
     conf: &SimfileWrite,
     root_path: &Path,
     set_path: &Path,
+    from_archive: bool,
+    group: Option<&str>,
     sms: &[Box<Simfile>],
 ) -> Result<()> {
     if sms.is_empty() {
         //Skip empty beatmapsets
         return Ok(());
     }
+    //Files cannot be written into an archive, so archives are never converted in-place
+    //Collection groups are never in-place either, they are separate from the input folder
+    let in_place = in_place_enabled(conf) && !from_archive && group.is_none();
     //Resolve output folder
-    let out_base = if in_place_enabled(conf) {
+    let out_base = if in_place {
         set_path.to_path_buf()
     } else {
-        let rel = set_path
+        let mut rel = set_path
             .strip_prefix(root_path)
-            .context("find path relative to base")?;
-        Path::new(&conf.output).join(rel)
+            .context("find path relative to base")?
+            .to_path_buf();
+        if from_archive {
+            //Output into a folder named after the archive
+            rel.set_extension("");
+        }
+        match group {
+            Some(group) => {
+                //Place the beatmapset folder directly inside the collection song group
+                Path::new(&conf.output)
+                    .parent()
+                    .unwrap_or_else(|| Path::new(""))
+                    .join(sanitize_file_name(group))
+                    .join(rel.file_name().unwrap_or_default())
+            }
+            None => Path::new(&conf.output).join(rel),
+        }
+    };
+    //Dependencies are extracted from the archive, if any
+    let mut archive = if from_archive {
+        Some(osufile::OszArchive::open(set_path).context("open beatmapset archive")?)
+    } else {
+        None
     };
     //Create base output folder
-    if !in_place_enabled(conf) {
+    if !in_place {
         fs::create_dir_all(&out_base)
             .with_context(|| anyhow!("create output dir at \"{}\"", out_base.display()))?;
     }
-    //Do not copy files twice
-    let mut already_copied: HashSet<PathBuf> = HashSet::default();
-    //Decide the output filename
-    let filename = format!(
-        "osu2sm-{}.sm",
-        sms[0]
-            .music
-            .as_ref()
-            .map(|m| m.file_stem().unwrap_or_default().to_string_lossy())
-            .unwrap_or_default()
-    );
-    let out_path: PathBuf = out_base.join(&filename);
-    //Write simfile
-    debug!("  writing simfile to \"{}\"", out_path.display());
-    Simfile::save(&out_path, sms.iter().map(|sm| &**sm))
+    //Write simfile, once per format
+    for &format in conf.formats.iter() {
+        //Decide the output filename
+        let filename = format!(
+            "osu2sm-{}.{}",
+            sms[0]
+                .music
+                .as_ref()
+                .map(|m| m.file_stem().unwrap_or_default().to_string_lossy())
+                .unwrap_or_default(),
+            format.extension(),
+        );
+        let out_path: PathBuf = out_base.join(&filename);
+        debug!("  writing simfile to \"{}\"", out_path.display());
+        let sms = sms.iter().map(|sm| &**sm);
+        match format {
+            SimfileFormat::Sm => Simfile::save(&out_path, sms),
+            SimfileFormat::Ssc => Simfile::save_ssc(&out_path, sms),
+        }
         .with_context(|| anyhow!("write simfile to \"{}\"", out_path.display()))?;
+    }
     //Copy over dependencies (backgrounds, audio, etc...)
-    if !in_place_enabled(conf) {
-        for sm in sms.iter() {
-            for dep_name in sm.file_deps() {
-                if already_copied.contains(dep_name) {
-                    continue;
-                }
-                already_copied.insert(dep_name.to_path_buf());
-                //Make sure no rogue '..' or 'C:\System32' appear
-                for comp in dep_name.components() {
-                    use std::path::Component;
-                    match comp {
-                        Component::Normal(_) | Component::CurDir => {}
-                        _ => bail!("invalid simfile dependency \"{}\"", dep_name.display()),
-                    }
+    if !in_place {
+        copy_deps(&conf.copy, set_path, &out_base, archive.as_mut(), sms)?;
+    }
+    Ok(())
+}
+
+/// Copy the files that the given simfiles depend on from the input beatmapset folder (or
+/// archive) into the output folder, skipping files that are copied more than once.
+pub fn copy_deps(
+    methods: &[CopyMethod],
+    set_path: &Path,
+    out_base: &Path,
+    mut archive: Option<&mut osufile::OszArchive>,
+    sms: &[Box<Simfile>],
+) -> Result<()> {
+    //Do not copy files twice
+    let mut already_copied: HashSet<PathBuf> = HashSet::default();
+    for sm in sms.iter() {
+        for dep_name in sm.file_deps() {
+            if already_copied.contains(dep_name) {
+                continue;
+            }
+            already_copied.insert(dep_name.to_path_buf());
+            //Make sure no rogue '..' or 'C:\System32' appear
+            for comp in dep_name.components() {
+                use std::path::Component;
+                match comp {
+                    Component::Normal(_) | Component::CurDir => {}
+                    _ => bail!("invalid simfile dependency \"{}\"", dep_name.display()),
                 }
-                //Copy the dependency over to the destination folder
-                let dep_src = set_path.join(dep_name);
-                let dep_dst = out_base.join(dep_name);
-                match copy_with_methods(&conf.copy, &dep_src, &dep_dst) {
-                    Ok(method) => {
-                        info!(
-                            "  copied dependency \"{}\" using {:?}",
-                            dep_name.display(),
-                            method
-                        );
+            }
+            //Copy the dependency over to the destination folder
+            let dep_src = set_path.join(dep_name);
+            let dep_dst = out_base.join(dep_name);
+            if let Some(archive) = archive.as_deref_mut() {
+                match extract_from_archive(archive, dep_name, &dep_dst) {
+                    Ok(()) => {
+                        info!("  extracted dependency \"{}\"", dep_name.display());
                     }
                     Err(err) => {
                         error!(
-                            "  failed to copy dependency \"{}\": {:#}",
+                            "  failed to extract dependency \"{}\": {:#}",
                             dep_name.display(),
                             err
                         );
                     }
                 }
+                continue;
+            }
+            match copy_with_methods(methods, &dep_src, &dep_dst) {
+                Ok(method) => {
+                    info!(
+                        "  copied dependency \"{}\" using {:?}",
+                        dep_name.display(),
+                        method
+                    );
+                }
+                Err(err) => {
+                    error!(
+                        "  failed to copy dependency \"{}\": {:#}",
+                        dep_name.display(),
+                        err
+                    );
+                }
             }
         }
     }
     Ok(())
 }
 
+/// Convert an arbitrary name (such as an osu! collection name) into a valid file or folder name.
+pub fn sanitize_file_name(name: &str) -> String {
+    let name = name
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>();
+    //Windows does not like trailing dots or spaces
+    let name = name.trim_end_matches(['.', ' ']);
+    if name.is_empty() {
+        "_".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+/// Resolve and create the output folder of the current beatmapset, for writers that mirror the
+/// input folder structure into `output`.
+/// Beatmapsets inside archives are output into a folder named after the archive.
+pub fn resolve_out_dir(store: &SimfileStore, output: &str) -> Result<PathBuf> {
+    let root_path = Path::new(store.global_get_expect("root")?);
+    let set_path = Path::new(store.global_get_expect("base")?);
+    let mut rel = set_path
+        .strip_prefix(root_path)
+        .context("find path relative to base")?
+        .to_path_buf();
+    if store.global_get("archive").is_some() {
+        rel.set_extension("");
+    }
+    let out_base = Path::new(output).join(rel);
+    fs::create_dir_all(&out_base)
+        .with_context(|| anyhow!("create output dir at \"{}\"", out_base.display()))?;
+    Ok(out_base)
+}
+
+/// Copy the dependencies of the given simfiles from the current beatmapset into `out_base`,
+/// extracting them if the beatmapset is an archive.
+pub fn copy_set_deps(
+    store: &SimfileStore,
+    methods: &[CopyMethod],
+    out_base: &Path,
+    sms: &[Box<Simfile>],
+) -> Result<()> {
+    let set_path = Path::new(store.global_get_expect("base")?);
+    let mut archive = if store.global_get("archive").is_some() {
+        Some(osufile::OszArchive::open(set_path).context("open beatmapset archive")?)
+    } else {
+        None
+    };
+    copy_deps(methods, set_path, out_base, archive.as_mut(), sms)
+}
+
+/// Make `name` unique among `used` by appending a number to it, and mark it as used.
+pub fn unique_file_name(used: &mut HashSet<String>, name: &str) -> String {
+    let mut unique = name.to_string();
+    let mut dup_idx = 1;
+    while !used.insert(unique.clone()) {
+        dup_idx += 1;
+        unique = format!("{} ({})", name, dup_idx);
+    }
+    unique
+}
+
+/// The name to show for a chart in formats that name each chart, falling back to the
+/// description and then to the difficulty.
+pub fn chart_display_name(sm: &Simfile) -> &str {
+    if !sm.chart_name.is_empty() {
+        &sm.chart_name
+    } else if !sm.desc.is_empty() {
+        &sm.desc
+    } else {
+        sm.difficulty.name()
+    }
+}
+
+/// Get a simfile path as a string, or an empty string if there is no path.
+pub fn path_str(path: &Option<PathBuf>) -> String {
+    path.as_deref()
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Get the transliterated version of a text, or the text itself if there is no transliteration.
+pub fn or_trans(text: &str, trans: &str) -> String {
+    if trans.is_empty() {
+        text.to_string()
+    } else {
+        trans.to_string()
+    }
+}
+
 fn copy_with_methods<'a>(
     methods: &'a [CopyMethod],
     src: &Path,
@@ -373,6 +575,28 @@ fn copy_with_methods<'a>(
     bail!(errstr)
 }
 
+fn extract_from_archive(archive: &mut osufile::OszArchive, name: &Path, dst: &Path) -> Result<()> {
+    debug!(
+        "  extracting \"{}\" to \"{}\"",
+        name.display(),
+        dst.display()
+    );
+    let mut src = archive.open_file(name)?;
+    //Skip files that were already extracted on a previous run
+    if let Ok(dst_meta) = fs::metadata(dst) {
+        if dst_meta.len() == src.size() {
+            debug!("    file already exists, skipping");
+            return Ok(());
+        }
+    }
+    if let Some(parent) = dst.parent() {
+        fs::create_dir_all(parent).context("create parent directory")?;
+    }
+    let mut dst = File::create(dst).context("create destination file")?;
+    io::copy(&mut src, &mut dst).context("extract file")?;
+    Ok(())
+}
+
 fn assert_identical(src: &Path, dst: &Path) -> Result<()> {
     let mut src = File::open(src).context("failed to open source file")?;
     let mut dst = File::open(dst).context("failed to open destination file")?;
@@ -22,6 +22,13 @@ impl Node for Simultaneous {
     fn apply(&self, store: &mut SimfileStore) -> Result<()> {
         store.get(&self.from, |store, list| {
             for sm in list.iter_mut() {
+                if sm.gamemode.is_routine() {
+                    warn!(
+                        "    skipping {} chart, routine charts are not supported",
+                        sm.gamemode.id()
+                    );
+                    continue;
+                }
                 limit_simultaneous_keys(sm, self)?;
             }
             store.put(&self.into, mem::replace(list, default()));
@@ -62,7 +69,7 @@ fn limit_simultaneous_keys(sm: &mut Simfile, conf: &Simultaneous) -> Result<()>
                 } else {
                     note.key = -1;
                 }
-            } else {
+            } else if note.is_step() {
                 beat_notes.push(note_idx);
                 if note.is_head() {
                     active_notes[note.key as usize] = true;
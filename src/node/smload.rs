@@ -0,0 +1,165 @@
+//! Take a StepMania song folder and parse its `.sm` and `.ssc` simfiles.
+
+use crate::{node::prelude::*, smfile};
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SmLoad {
+    /// Into what bucket to output the loaded simfiles.
+    pub into: BucketId,
+    /// The input StepMania song folder (or song group, or whole `Songs` folder).
+    pub input: String,
+    /// Only load charts in these gamemodes.
+    /// If empty, all gamemodes are loaded.
+    pub gamemodes: Vec<Gamemode>,
+    /// Whether to skip simfiles written by `osu2sm` (`osu2sm-*.sm` and `osu2sm-*.ssc`).
+    /// Useful when the output folder is the same as the input folder.
+    pub ignore_generated: bool,
+}
+
+impl Default for SmLoad {
+    fn default() -> Self {
+        Self {
+            into: default(),
+            input: "".into(),
+            gamemodes: vec![],
+            ignore_generated: true,
+        }
+    }
+}
+
+impl Node for SmLoad {
+    fn prepare(&mut self) -> Result<()> {
+        if self.input.is_empty() {
+            eprintln!();
+            eprintln!(
+                "drag and drop your stepmania song folder into this window, then press enter"
+            );
+            self.input = crate::read_path_from_stdin()?;
+        }
+        info!("scanning for simfiles in \"{}\"", self.input);
+        Ok(())
+    }
+    fn apply(&self, _store: &mut SimfileStore) -> Result<()> {
+        Ok(())
+    }
+    fn buckets_mut(&mut self) -> BucketIter<'_> {
+        Box::new(iter::once((BucketKind::Output, &mut self.into)))
+    }
+    fn entry(
+        &self,
+        store: &mut SimfileStore,
+        on_bmset: &mut dyn FnMut(&mut SimfileStore) -> Result<()>,
+    ) -> Result<()> {
+        //Group simfiles by song folder
+        let by_song = group_by_song(&self.input, |path| {
+            let is_simfile = path
+                .extension()
+                .map(|ext| ext.eq_ignore_ascii_case("sm") || ext.eq_ignore_ascii_case("ssc"))
+                .unwrap_or(false);
+            let is_generated = path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .starts_with("osu2sm-");
+            is_simfile && !(self.ignore_generated && is_generated)
+        });
+        for (song_path, sm_paths) in by_song {
+            if let Err(err) = process_song(self, store, &song_path, &sm_paths, on_bmset) {
+                error!(
+                    "  error processing song at \"{}\": {:#}",
+                    song_path.display(),
+                    err
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Find the chart files under `input` that pass `is_chart`, grouped by the song folder that
+/// contains them.
+pub fn group_by_song(
+    input: &str,
+    mut is_chart: impl FnMut(&Path) -> bool,
+) -> BTreeMap<PathBuf, Vec<PathBuf>> {
+    let mut by_song: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    for entry in WalkDir::new(input) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                warn!("failed to scan input directory: {:#}", err);
+                continue;
+            }
+        };
+        if !entry.file_type().is_file() || !is_chart(entry.path()) {
+            continue;
+        }
+        let song = entry
+            .path()
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .to_path_buf();
+        by_song.entry(song).or_default().push(entry.into_path());
+    }
+    by_song
+}
+
+fn process_song(
+    conf: &SmLoad,
+    store: &mut SimfileStore,
+    song_path: &Path,
+    sm_paths: &[PathBuf],
+    on_bmset: &mut dyn FnMut(&mut SimfileStore) -> Result<()>,
+) -> Result<()> {
+    info!("processing \"{}\":", song_path.display());
+    let mut simfiles = Vec::new();
+    for sm_path in sm_paths {
+        //StepMania prefers `.ssc` files over `.sm` files with the same name
+        if sm_path.extension() == Some("sm".as_ref())
+            && sm_paths.contains(&sm_path.with_extension("ssc"))
+        {
+            debug!(
+                "  skipping \"{}\" in favor of its .ssc counterpart",
+                sm_path.display()
+            );
+            continue;
+        }
+        let sm_name = sm_path.file_name().unwrap_or_default().to_string_lossy();
+        match smfile::load(sm_path) {
+            Ok(charts) => {
+                let count = simfiles.len();
+                simfiles.extend(
+                    charts
+                        .into_iter()
+                        .filter(|sm| {
+                            conf.gamemodes.is_empty() || conf.gamemodes.contains(&sm.gamemode)
+                        })
+                        .map(Box::new),
+                );
+                debug!(
+                    "  loaded simfile \"{}\" successfully into {} charts",
+                    sm_name,
+                    simfiles.len() - count
+                );
+            }
+            Err(err) => {
+                error!("  error processing simfile \"{}\": {:#}", sm_name, err);
+            }
+        }
+    }
+    //Report song
+    store.reset();
+    store.global_set("root", conf.input.clone());
+    store.global_set(
+        "base",
+        song_path
+            .to_str()
+            .ok_or(anyhow!("non utf-8 song path \"{}\"", song_path.display()))?
+            .to_string(),
+    );
+    store.put(&conf.into, simfiles);
+    on_bmset(store)?;
+    Ok(())
+}
@@ -34,6 +34,13 @@ impl Node for Space {
     fn apply(&self, store: &mut SimfileStore) -> Result<()> {
         store.get(&self.from, |store, list| {
             for sm in list.iter_mut() {
+                if sm.gamemode.is_routine() {
+                    warn!(
+                        "    skipping {} chart, routine charts are not supported",
+                        sm.gamemode.id()
+                    );
+                    continue;
+                }
                 make_space(sm, self)?;
             }
             store.put(&self.into, mem::replace(list, default()));
@@ -90,7 +97,7 @@ fn make_space(sm: &mut Simfile, conf: &Space) -> Result<()> {
     };
     //Create an array of references to notes, sorted from most removable to least removable
     let mut note_refs = (0..sm.notes.len())
-        .filter(|&idx| !sm.notes[idx].is_tail())
+        .filter(|&idx| sm.notes[idx].is_step())
         .collect::<Vec<_>>();
     note_refs.sort_by_cached_key(|&idx| {
         ((64 - sm.notes[idx].beat.denominator() as u32) << (32 - 6))
@@ -104,7 +111,7 @@ fn make_space(sm: &mut Simfile, conf: &Space) -> Result<()> {
         //Check forward gap
         if let Some(indices_to_next_note) = sm.notes[note_idx + 1..]
             .iter()
-            .position(|note| !note.is_tail() && note.key >= 0 && note.beat > this_beat)
+            .position(|note| note.is_step() && note.key >= 0 && note.beat > this_beat)
         {
             let next_note = note_idx + 1 + indices_to_next_note;
             keep = are_far_enough(&sm.notes, note_idx, next_note);
@@ -115,7 +122,7 @@ fn make_space(sm: &mut Simfile, conf: &Space) -> Result<()> {
             if let Some(indices_to_prev_note) = sm.notes[..note_idx]
                 .iter()
                 .rev()
-                .position(|note| !note.is_tail() && note.key >= 0 && note.beat < this_beat)
+                .position(|note| note.is_step() && note.key >= 0 && note.beat < this_beat)
             {
                 let prev_note = note_idx - 1 - indices_to_prev_note;
                 keep = are_far_enough(&sm.notes, prev_note, note_idx);
@@ -147,7 +154,7 @@ fn make_space(sm: &mut Simfile, conf: &Space) -> Result<()> {
     let notes_without_tails = sm
         .notes
         .iter()
-        .filter(|note| !note.is_tail())
+        .filter(|note| note.is_step())
         .cloned()
         .collect::<Vec<_>>();
     for (idx, note) in notes_without_tails.iter().enumerate() {
@@ -0,0 +1,104 @@
+//! Split a chart between two players, for couple and routine gamemodes.
+
+use crate::node::prelude::*;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Split {
+    pub from: BucketId,
+    pub into: BucketId,
+    /// Into what two-player gamemode to convert.
+    /// Only charts with the same keycount as this gamemode are split, the rest are passed through
+    /// unchanged.
+    pub gamemode: Gamemode,
+    /// How to choose which player hits each note.
+    /// Couple gamemodes can only be split by side, since each player has their own half of the
+    /// keys.
+    pub by: SplitBy,
+}
+impl Default for Split {
+    fn default() -> Self {
+        Self {
+            from: default(),
+            into: default(),
+            gamemode: Gamemode::DanceRoutine,
+            by: SplitBy::Side,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SplitBy {
+    /// Notes on the left half of the keys go to the first player, and notes on the right half go
+    /// to the second player.
+    Side,
+    /// Players take turns, switching every given amount of beats.
+    /// Hold notes are always hit by the player that started them.
+    Turns(f64),
+}
+
+impl Node for Split {
+    fn prepare(&mut self) -> Result<()> {
+        ensure!(
+            self.gamemode.is_couple() || self.gamemode.is_routine(),
+            "cannot split charts into gamemode {}, which is not a couple or routine gamemode",
+            self.gamemode.id()
+        );
+        match self.by {
+            SplitBy::Side => {}
+            SplitBy::Turns(beats) => {
+                ensure!(
+                    !self.gamemode.is_couple(),
+                    "couple gamemodes can only be split by side"
+                );
+                ensure!(
+                    beats.is_finite() && beats > 0.,
+                    "turn length must be a positive amount of beats"
+                );
+            }
+        }
+        Ok(())
+    }
+    fn apply(&self, store: &mut SimfileStore) -> Result<()> {
+        store.get(&self.from, |store, list| {
+            for sm in list.iter_mut() {
+                if sm.gamemode.key_count() == self.gamemode.key_count() {
+                    split(sm, self);
+                }
+            }
+            store.put(&self.into, mem::replace(list, default()));
+            Ok(())
+        })
+    }
+    fn buckets_mut(&mut self) -> BucketIter<'_> {
+        Box::new(
+            iter::once((BucketKind::Input, &mut self.from))
+                .chain(iter::once((BucketKind::Output, &mut self.into))),
+        )
+    }
+}
+
+fn split(sm: &mut Simfile, conf: &Split) {
+    let key_count = conf.gamemode.key_count();
+    trace!(
+        "    splitting {}K chart into {}",
+        key_count,
+        conf.gamemode.id()
+    );
+    //Remember the player of each hold head, so that tails are hit by the same player
+    let mut head_player = vec![0; key_count as usize];
+    for note in sm.notes.iter_mut() {
+        note.player = if note.is_tail() {
+            head_player[note.key as usize]
+        } else {
+            match conf.by {
+                SplitBy::Side => (note.key >= key_count / 2) as u8,
+                SplitBy::Turns(beats) => (note.beat.as_num() / beats).floor().rem_euclid(2.) as u8,
+            }
+        };
+        if note.is_head() {
+            head_player[note.key as usize] = note.player;
+        }
+    }
+    sm.gamemode = conf.gamemode;
+}
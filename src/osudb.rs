@@ -0,0 +1,556 @@
+//! Parse the osu! database files found at the root of an osu! installation.
+//!
+//! See https://github.com/ppy/osu/wiki/Legacy-database-file-structure for the format.
+
+use crate::prelude::*;
+
+/// Beatmaps in `osu!.db` older than this version have a beatmap size field.
+const VERSION_BEATMAP_SIZE: i32 = 20191106;
+/// Beatmaps in `osu!.db` older than this version store difficulty settings as bytes and have no
+/// star ratings.
+const VERSION_FLOAT_DIFFICULTY: i32 = 20140609;
+/// Beatmaps in `osu!.db` starting from this version store star ratings as floats.
+const VERSION_FLOAT_STARS: i32 = 20250107;
+
+/// The amount of seconds between the Windows tick epoch (0001-01-01) and the unix epoch.
+const TICKS_EPOCH_OFFSET_SECS: f64 = 62_135_596_800.;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RankedStatus {
+    Unknown,
+    Unsubmitted,
+    /// Pending, WIP or graveyard.
+    Pending,
+    Ranked,
+    Approved,
+    Qualified,
+    Loved,
+}
+impl RankedStatus {
+    fn from_raw(raw: u8) -> RankedStatus {
+        use RankedStatus::*;
+        match raw {
+            1 => Unsubmitted,
+            2 => Pending,
+            4 => Ranked,
+            5 => Approved,
+            6 => Qualified,
+            7 => Loved,
+            _ => Unknown,
+        }
+    }
+}
+
+/// A beatmap entry in `osu!.db`.
+#[derive(Debug, Clone)]
+pub struct DbBeatmap {
+    pub artist: String,
+    pub title: String,
+    pub version: String,
+    /// The MD5 hash of the `.osu` file, as a lowercase hex string.
+    pub md5: String,
+    /// The name of the `.osu` file, relative to `folder`.
+    pub file: String,
+    /// The name of the beatmapset folder, relative to the songs folder.
+    pub folder: String,
+    pub ranked: RankedStatus,
+    pub mode: i32,
+    pub circle_size: f64,
+    /// The cached no-mod star rating of this beatmap, in its own gamemode.
+    /// `NaN` if osu! has not computed it yet.
+    pub stars: f64,
+    /// The last time this beatmap was played, as seconds since the unix epoch.
+    /// `None` if it was never played.
+    pub last_played: Option<f64>,
+}
+
+/// The parsed contents of an `osu!.db` file.
+#[derive(Debug, Clone)]
+pub struct OsuDb {
+    pub version: i32,
+    pub beatmaps: Vec<DbBeatmap>,
+}
+impl OsuDb {
+    pub fn load(path: &Path) -> Result<OsuDb> {
+        Self::read(File::open(path).context("open file")?)
+    }
+
+    fn read(file: impl Read) -> Result<OsuDb> {
+        let mut r = DbReader::new(file);
+        let version = r.int()?;
+        let _folder_count = r.int()?;
+        let _account_unlocked = r.bool()?;
+        let _unlock_date = r.long()?;
+        let _player_name = r.string()?;
+        let beatmap_count = r.int()?;
+        ensure!(
+            beatmap_count >= 0,
+            "invalid beatmap count {}",
+            beatmap_count
+        );
+        let mut beatmaps = Vec::with_capacity(beatmap_count as usize);
+        for idx in 0..beatmap_count {
+            let bm = Self::read_beatmap(&mut r, version)
+                .with_context(|| anyhow!("read beatmap entry {}", idx))?;
+            beatmaps.push(bm);
+        }
+        Ok(OsuDb { version, beatmaps })
+    }
+
+    fn read_beatmap(r: &mut DbReader<impl Read>, version: i32) -> Result<DbBeatmap> {
+        if version < VERSION_BEATMAP_SIZE {
+            let _size = r.int()?;
+        }
+        let artist = r.string()?;
+        let _artist_unicode = r.string()?;
+        let title = r.string()?;
+        let _title_unicode = r.string()?;
+        let _creator = r.string()?;
+        let version_name = r.string()?;
+        let _audio = r.string()?;
+        let md5 = r.string()?;
+        let file = r.string()?;
+        let ranked = RankedStatus::from_raw(r.byte()?);
+        let _circles = r.short()?;
+        let _sliders = r.short()?;
+        let _spinners = r.short()?;
+        let _modified = r.long()?;
+        let mut difficulty = || -> Result<f64> {
+            Ok(if version < VERSION_FLOAT_DIFFICULTY {
+                r.byte()? as f64
+            } else {
+                r.single()? as f64
+            })
+        };
+        let _approach_rate = difficulty()?;
+        let circle_size = difficulty()?;
+        let _hp_drain = difficulty()?;
+        let _overall_difficulty = difficulty()?;
+        let _slider_velocity = r.double()?;
+        //Star ratings, per gamemode, per mod combination
+        let mut nomod_stars = [f64::NAN; 4];
+        if version >= VERSION_FLOAT_DIFFICULTY {
+            for stars in nomod_stars.iter_mut() {
+                let count = r.int()?;
+                for _ in 0..count {
+                    r.expect_byte(0x08)?;
+                    let mods = r.int()?;
+                    let rating = if version >= VERSION_FLOAT_STARS {
+                        r.expect_byte(0x0c)?;
+                        r.single()? as f64
+                    } else {
+                        r.expect_byte(0x0d)?;
+                        r.double()?
+                    };
+                    if mods == 0 {
+                        *stars = rating;
+                    }
+                }
+            }
+        }
+        let _drain_time = r.int()?;
+        let _total_time = r.int()?;
+        let _preview_time = r.int()?;
+        let timing_point_count = r.int()?;
+        for _ in 0..timing_point_count {
+            let _bpm = r.double()?;
+            let _offset = r.double()?;
+            let _inherited = r.bool()?;
+        }
+        let _difficulty_id = r.int()?;
+        let _beatmap_id = r.int()?;
+        let _thread_id = r.int()?;
+        for _ in 0..4 {
+            let _grade = r.byte()?;
+        }
+        let _local_offset = r.short()?;
+        let _stack_leniency = r.single()?;
+        let mode = r.byte()? as i32;
+        let _source = r.string()?;
+        let _tags = r.string()?;
+        let _online_offset = r.short()?;
+        let _title_font = r.string()?;
+        let _unplayed = r.bool()?;
+        let last_played = r.long()?;
+        let _is_osz2 = r.bool()?;
+        let folder = r.string()?;
+        let _last_checked = r.long()?;
+        let _ignore_sound = r.bool()?;
+        let _ignore_skin = r.bool()?;
+        let _disable_storyboard = r.bool()?;
+        let _disable_video = r.bool()?;
+        let _visual_override = r.bool()?;
+        if version < VERSION_FLOAT_DIFFICULTY {
+            let _unknown = r.short()?;
+        }
+        let _last_modified = r.int()?;
+        let _mania_scroll_speed = r.byte()?;
+        Ok(DbBeatmap {
+            artist,
+            title,
+            version: version_name,
+            md5,
+            file,
+            folder,
+            ranked,
+            mode,
+            circle_size,
+            stars: nomod_stars.get(mode as usize).copied().unwrap_or(f64::NAN),
+            last_played: if last_played > 0 {
+                Some(last_played as f64 / 1e7 - TICKS_EPOCH_OFFSET_SECS)
+            } else {
+                None
+            },
+        })
+    }
+}
+
+/// An osu! collection, as stored in `collection.db`.
+#[derive(Debug, Clone)]
+pub struct Collection {
+    pub name: String,
+    /// The MD5 hashes of the `.osu` files in this collection, as lowercase hex strings.
+    pub md5s: Vec<String>,
+}
+
+/// The parsed contents of a `collection.db` file.
+#[derive(Debug, Clone)]
+pub struct CollectionDb {
+    pub version: i32,
+    pub collections: Vec<Collection>,
+}
+impl CollectionDb {
+    pub fn load(path: &Path) -> Result<CollectionDb> {
+        Self::read(File::open(path).context("open file")?)
+    }
+
+    fn read(file: impl Read) -> Result<CollectionDb> {
+        let mut r = DbReader::new(file);
+        let version = r.int()?;
+        let collection_count = r.int()?;
+        let mut collections = Vec::new();
+        for idx in 0..collection_count {
+            let mut read_collection = || -> Result<Collection> {
+                let name = r.string()?;
+                let count = r.int()?;
+                let md5s = (0..count).map(|_| r.string()).collect::<Result<_>>()?;
+                Ok(Collection { name, md5s })
+            };
+            collections
+                .push(read_collection().with_context(|| anyhow!("read collection {}", idx))?);
+        }
+        Ok(CollectionDb {
+            version,
+            collections,
+        })
+    }
+}
+
+/// Reads the primitive types used in osu! database files.
+struct DbReader<R> {
+    inner: BufReader<R>,
+}
+impl<R: Read> DbReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner: BufReader::new(inner),
+        }
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut buf = [0; N];
+        self.inner
+            .read_exact(&mut buf)
+            .context("unexpected end of file")?;
+        Ok(buf)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.bytes::<1>()?[0])
+    }
+
+    fn expect_byte(&mut self, expected: u8) -> Result<()> {
+        let byte = self.byte()?;
+        ensure!(
+            byte == expected,
+            "expected byte 0x{:02x}, found 0x{:02x}",
+            expected,
+            byte
+        );
+        Ok(())
+    }
+
+    fn bool(&mut self) -> Result<bool> {
+        Ok(self.byte()? != 0)
+    }
+
+    fn short(&mut self) -> Result<i16> {
+        Ok(i16::from_le_bytes(self.bytes()?))
+    }
+
+    fn int(&mut self) -> Result<i32> {
+        Ok(i32::from_le_bytes(self.bytes()?))
+    }
+
+    fn long(&mut self) -> Result<i64> {
+        Ok(i64::from_le_bytes(self.bytes()?))
+    }
+
+    fn single(&mut self) -> Result<f32> {
+        Ok(f32::from_le_bytes(self.bytes()?))
+    }
+
+    fn double(&mut self) -> Result<f64> {
+        Ok(f64::from_le_bytes(self.bytes()?))
+    }
+
+    fn uleb128(&mut self) -> Result<u64> {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.byte()?;
+            ensure!(shift < 64, "ULEB128 integer too large");
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                break Ok(value);
+            }
+            shift += 7;
+        }
+    }
+
+    fn string(&mut self) -> Result<String> {
+        match self.byte()? {
+            0x00 => Ok(String::new()),
+            0x0b => {
+                let len = self.uleb128()?;
+                let mut buf = Vec::new();
+                (&mut self.inner)
+                    .take(len)
+                    .read_to_end(&mut buf)
+                    .context("unexpected end of file")?;
+                ensure!(buf.len() as u64 == len, "unexpected end of file");
+                String::from_utf8(buf).context("invalid utf-8 string")
+            }
+            other => bail!("invalid string marker 0x{:02x}", other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes the primitive types read by `DbReader`.
+    #[derive(Default)]
+    struct DbWriter {
+        buf: Vec<u8>,
+    }
+    impl DbWriter {
+        fn byte(&mut self, value: u8) -> &mut Self {
+            self.buf.push(value);
+            self
+        }
+        fn short(&mut self, value: i16) -> &mut Self {
+            self.buf.extend_from_slice(&value.to_le_bytes());
+            self
+        }
+        fn int(&mut self, value: i32) -> &mut Self {
+            self.buf.extend_from_slice(&value.to_le_bytes());
+            self
+        }
+        fn long(&mut self, value: i64) -> &mut Self {
+            self.buf.extend_from_slice(&value.to_le_bytes());
+            self
+        }
+        fn single(&mut self, value: f32) -> &mut Self {
+            self.buf.extend_from_slice(&value.to_le_bytes());
+            self
+        }
+        fn double(&mut self, value: f64) -> &mut Self {
+            self.buf.extend_from_slice(&value.to_le_bytes());
+            self
+        }
+        fn string(&mut self, value: &str) -> &mut Self {
+            if value.is_empty() {
+                return self.byte(0x00);
+            }
+            self.byte(0x0b);
+            let mut len = value.len();
+            while len >= 0x80 {
+                self.byte(len as u8 | 0x80);
+                len >>= 7;
+            }
+            self.byte(len as u8);
+            self.buf.extend_from_slice(value.as_bytes());
+            self
+        }
+    }
+
+    /// A beatmap entry in the format of the given `osu!.db` version.
+    /// osu!mania stars are 4.5 with no mods and 6 with a mod, and other gamemodes have none.
+    fn write_beatmap(w: &mut DbWriter, version: i32, mode: u8, last_played: i64) {
+        if version < VERSION_BEATMAP_SIZE {
+            w.int(0);
+        }
+        w.string("Artist")
+            .string("")
+            .string("Title")
+            .string("")
+            .string("Mapper")
+            .string("Hard")
+            .string("audio.mp3")
+            .string("0123456789abcdef0123456789abcdef")
+            .string("Artist - Title (Mapper) [Hard].osu")
+            .byte(4)
+            .short(100)
+            .short(10)
+            .short(0)
+            .long(0);
+        for value in [8, 7, 6, 5] {
+            if version < VERSION_FLOAT_DIFFICULTY {
+                w.byte(value);
+            } else {
+                w.single(value as f32);
+            }
+        }
+        w.double(1.4);
+        if version >= VERSION_FLOAT_DIFFICULTY {
+            for gamemode in 0..4 {
+                let ratings: &[(i32, f64)] = if gamemode == osufile::MODE_MANIA {
+                    &[(64, 6.), (0, 4.5)]
+                } else {
+                    &[]
+                };
+                w.int(ratings.len() as i32);
+                for &(mods, stars) in ratings {
+                    w.byte(0x08).int(mods);
+                    if version >= VERSION_FLOAT_STARS {
+                        w.byte(0x0c).single(stars as f32);
+                    } else {
+                        w.byte(0x0d).double(stars);
+                    }
+                }
+            }
+        }
+        w.int(60).int(62000).int(30000);
+        w.int(1).double(500.).double(20.).byte(1);
+        w.int(100).int(10).int(0);
+        w.byte(9).byte(9).byte(9).byte(9);
+        w.short(0).single(0.7).byte(mode);
+        w.string("Source").string("tag").short(0).string("");
+        w.byte((last_played == 0) as u8).long(last_played).byte(0);
+        w.string("10 Artist - Title").long(0);
+        w.byte(0).byte(0).byte(0).byte(0).byte(0);
+        if version < VERSION_FLOAT_DIFFICULTY {
+            w.short(0);
+        }
+        w.int(0).byte(0);
+    }
+
+    fn osu_db(version: i32, beatmaps: &[(u8, i64)]) -> OsuDb {
+        let mut w = DbWriter::default();
+        w.int(version).int(1).byte(1).long(0).string("Player");
+        w.int(beatmaps.len() as i32);
+        for &(mode, last_played) in beatmaps {
+            write_beatmap(&mut w, version, mode, last_played);
+        }
+        OsuDb::read(&w.buf[..]).unwrap()
+    }
+
+    fn check_beatmap(bm: &DbBeatmap) {
+        assert_eq!(bm.artist, "Artist");
+        assert_eq!(bm.title, "Title");
+        assert_eq!(bm.version, "Hard");
+        assert_eq!(bm.md5, "0123456789abcdef0123456789abcdef");
+        assert_eq!(bm.file, "Artist - Title (Mapper) [Hard].osu");
+        assert_eq!(bm.folder, "10 Artist - Title");
+        assert_eq!(bm.ranked, RankedStatus::Ranked);
+        assert_eq!(bm.circle_size, 7.);
+    }
+
+    #[test]
+    fn float_stars() {
+        let db = osu_db(VERSION_FLOAT_STARS, &[(3, 0), (0, 0)]);
+        assert_eq!(db.beatmaps.len(), 2);
+        check_beatmap(&db.beatmaps[0]);
+        assert_eq!(db.beatmaps[0].mode, osufile::MODE_MANIA);
+        assert_eq!(db.beatmaps[0].stars, 4.5);
+        check_beatmap(&db.beatmaps[1]);
+        assert!(db.beatmaps[1].stars.is_nan());
+    }
+
+    #[test]
+    fn double_stars() {
+        for version in [VERSION_BEATMAP_SIZE - 1, VERSION_FLOAT_STARS - 1] {
+            let db = osu_db(version, &[(3, 0), (3, 0)]);
+            assert_eq!(db.beatmaps.len(), 2);
+            for bm in db.beatmaps.iter() {
+                check_beatmap(bm);
+                assert_eq!(bm.stars, 4.5);
+            }
+        }
+    }
+
+    #[test]
+    fn byte_difficulty() {
+        let db = osu_db(VERSION_FLOAT_DIFFICULTY - 1, &[(3, 0)]);
+        check_beatmap(&db.beatmaps[0]);
+        assert!(db.beatmaps[0].stars.is_nan());
+    }
+
+    #[test]
+    fn last_played() {
+        //2020-01-01 in ticks since 0001-01-01
+        let ticks = 637_134_336_000_000_000;
+        let db = osu_db(VERSION_FLOAT_STARS, &[(3, ticks), (3, 0)]);
+        assert_eq!(db.beatmaps[0].last_played, Some(1_577_836_800.));
+        assert_eq!(db.beatmaps[1].last_played, None);
+    }
+
+    #[test]
+    fn truncated_db() {
+        let mut w = DbWriter::default();
+        w.int(VERSION_FLOAT_STARS)
+            .int(1)
+            .byte(1)
+            .long(0)
+            .string("Player");
+        w.int(1);
+        write_beatmap(&mut w, VERSION_FLOAT_STARS, 3, 0);
+        w.buf.pop();
+        assert!(OsuDb::read(&w.buf[..]).is_err());
+    }
+
+    #[test]
+    fn collections() {
+        let long_name = "x".repeat(200);
+        let mut w = DbWriter::default();
+        w.int(20250107).int(2);
+        w.string("Favourites").int(2);
+        w.string("0123456789abcdef0123456789abcdef")
+            .string("fedcba9876543210fedcba9876543210");
+        w.string(&long_name).int(0);
+        let db = CollectionDb::read(&w.buf[..]).unwrap();
+        assert_eq!(db.version, 20250107);
+        assert_eq!(db.collections.len(), 2);
+        assert_eq!(db.collections[0].name, "Favourites");
+        assert_eq!(
+            db.collections[0].md5s,
+            vec![
+                "0123456789abcdef0123456789abcdef".to_string(),
+                "fedcba9876543210fedcba9876543210".to_string(),
+            ]
+        );
+        //Names longer than 127 bytes need a multi-byte length
+        assert_eq!(db.collections[1].name, long_name);
+        assert!(db.collections[1].md5s.is_empty());
+    }
+
+    #[test]
+    fn truncated_collections() {
+        let mut w = DbWriter::default();
+        w.int(20250107).int(2).string("Favourites").int(0);
+        let err = CollectionDb::read(&w.buf[..]).unwrap_err();
+        assert!(format!("{:#}", err).contains("read collection 1"));
+    }
+}
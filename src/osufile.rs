@@ -8,6 +8,8 @@ pub struct Beatmap {
     pub preview_start: f64,
     pub mode: i32,
     pub mania_special: bool,
+    /// The default sample set of the beatmap, used when timing points do not specify one.
+    pub sample_set: u32,
     pub title_unicode: String,
     pub title: String,
     pub artist_unicode: String,
@@ -37,6 +39,7 @@ impl Default for Beatmap {
             preview_start: 0.,
             mode: 0,
             mania_special: false,
+            sample_set: SAMPLESET_NORMAL,
             title_unicode: default(),
             title: default(),
             artist_unicode: default(),
@@ -63,6 +66,12 @@ impl Default for Beatmap {
 }
 impl Beatmap {
     pub fn parse(offset_ms: f64, path: &Path) -> Result<Beatmap> {
+        Self::parse_from(offset_ms, path, File::open(path).context("open file")?)
+    }
+
+    /// Parse a beatmap from an arbitrary reader.
+    /// The path is only used for diagnostics.
+    pub fn parse_from(offset_ms: f64, path: &Path, file: impl Read) -> Result<Beatmap> {
         use Category::*;
 
         #[derive(Copy, Clone, Debug)]
@@ -109,7 +118,7 @@ impl Beatmap {
 
         let mut category = Category::Unknown;
         let mut bm = Beatmap::default();
-        let mut lines = BufReader::new(File::open(path).context("open file")?).lines();
+        let mut lines = BufReader::new(file).lines();
         let mut line_num = 0;
 
         //Find osu header
@@ -168,6 +177,13 @@ impl Beatmap {
                                     "SpecialStyle" => {
                                         bm.mania_special = parse_as::<i32>(v, "ManiaSpecial")? != 0
                                     }
+                                    "SampleSet" => {
+                                        bm.sample_set = match v {
+                                            "Soft" => SAMPLESET_SOFT,
+                                            "Drum" => SAMPLESET_DRUM,
+                                            _ => SAMPLESET_NORMAL,
+                                        }
+                                    }
 
                                     _ => {}
                                 }
@@ -233,10 +249,22 @@ impl Beatmap {
                                 .trim()
                                 .parse::<i32>()
                                 .unwrap_or(4);
+                            let mut next_u32 = || {
+                                comps
+                                    .next()
+                                    .unwrap_or_default()
+                                    .trim()
+                                    .parse::<u32>()
+                                    .unwrap_or(0)
+                            };
+                            let sample_set = next_u32();
+                            let sample_index = next_u32();
                             bm.timing_points.push(TimingPoint {
                                 time,
                                 beat_len,
                                 meter,
+                                sample_set,
+                                sample_index,
                             });
                         }
                         HitObjects => {
@@ -245,13 +273,16 @@ impl Beatmap {
                             let y = get_component(&mut comps, "y")?;
                             let time = get_component::<f64, _>(&mut comps, "time")? + bm.offset_ms;
                             let ty = get_component(&mut comps, "type")?;
-                            let _hitsound: String = get_component(&mut comps, "hitsound")?;
+                            let hitsound = get_component::<String, _>(&mut comps, "hitsound")?
+                                .parse::<u32>()
+                                .unwrap_or(0);
                             let extras = comps.next().unwrap_or_default().trim().to_string();
                             bm.hit_objects.push(HitObject {
                                 x,
                                 y,
                                 time,
                                 ty,
+                                hitsound,
                                 extras,
                             });
                             if time < last_time {
@@ -281,6 +312,476 @@ impl Beatmap {
         }
         Ok(bm)
     }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut file = BufWriter::new(File::create(path).context("create file")?);
+        self.write_to(&mut file)?;
+        file.flush().context("write file")?;
+        Ok(())
+    }
+
+    /// Write the beatmap in the `.osu` format to an arbitrary writer.
+    /// The inverse of `parse_from`, including the removal of `offset_ms` from all times.
+    pub fn write_to(&self, mut file: impl Write) -> Result<()> {
+        let sample_set = match self.sample_set {
+            SAMPLESET_SOFT => "Soft",
+            SAMPLESET_DRUM => "Drum",
+            _ => "Normal",
+        };
+        write!(
+            file,
+            r#"osu file format v14
+
+[General]
+AudioFilename: {audio}
+AudioLeadIn: 0
+PreviewTime: {preview}
+Countdown: 0
+SampleSet: {sample_set}
+StackLeniency: 0.7
+Mode: {mode}
+LetterboxInBreaks: 0
+SpecialStyle: {special}
+WidescreenStoryboard: 0
+
+[Metadata]
+Title:{title}
+TitleUnicode:{title_unicode}
+Artist:{artist}
+ArtistUnicode:{artist_unicode}
+Creator:{creator}
+Version:{version}
+Source:{source}
+Tags:{tags}
+BeatmapID:{id}
+BeatmapSetID:{set_id}
+
+[Difficulty]
+HPDrainRate:{hp}
+CircleSize:{cs}
+OverallDifficulty:{od}
+ApproachRate:{ar}
+SliderMultiplier:{sv}
+SliderTickRate:{tickrate}
+
+[Events]
+"#,
+            audio = self.audio,
+            preview = self.preview_start,
+            sample_set = sample_set,
+            mode = self.mode,
+            special = self.mania_special as i32,
+            title = self.title,
+            title_unicode = self.title_unicode,
+            artist = self.artist,
+            artist_unicode = self.artist_unicode,
+            creator = self.creator,
+            version = self.version,
+            source = self.source,
+            tags = self.tags,
+            id = self.id,
+            set_id = self.set_id,
+            hp = self.hp_drain,
+            cs = self.circle_size,
+            od = self.overall_difficulty,
+            ar = self.approach_rate,
+            sv = self.slider_multiplier,
+            tickrate = self.slider_tickrate,
+        )?;
+        if !self.background.is_empty() {
+            writeln!(file, "0,0,\"{}\",0,0", self.background)?;
+        }
+        if !self.video.is_empty() {
+            writeln!(file, "Video,0,\"{}\"", self.video)?;
+        }
+        write!(file, "\n[TimingPoints]\n")?;
+        for tp in self.timing_points.iter() {
+            writeln!(
+                file,
+                "{},{},{},{},{},100,{},0",
+                tp.time - self.offset_ms,
+                tp.beat_len,
+                tp.meter,
+                tp.sample_set,
+                tp.sample_index,
+                (tp.beat_len > 0.) as i32,
+            )?;
+        }
+        write!(file, "\n[HitObjects]\n")?;
+        for obj in self.hit_objects.iter() {
+            writeln!(
+                file,
+                "{},{},{},{},{},{}",
+                obj.x,
+                obj.y,
+                obj.time - self.offset_ms,
+                obj.ty,
+                obj.hitsound,
+                obj.extras
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// An `.osz` beatmapset archive, which is just a renamed zip file.
+pub struct OszArchive {
+    zip: zip::ZipArchive<File>,
+    /// Maps normalized (lowercase, forward-slash) file names to their actual archive names.
+    /// osu! matches file names case-insensitively, so beatmaps do not always get the case right.
+    by_name: HashMap<String, String>,
+}
+impl OszArchive {
+    pub fn open(path: &Path) -> Result<OszArchive> {
+        let zip = zip::ZipArchive::new(File::open(path).context("open file")?)
+            .context("read zip archive")?;
+        let by_name = zip
+            .file_names()
+            .map(|name| (Self::normalize(name), name.to_string()))
+            .collect();
+        Ok(OszArchive { zip, by_name })
+    }
+
+    fn normalize(name: &str) -> String {
+        name.replace('\\', "/").to_lowercase()
+    }
+
+    /// Get the names of the `.osu` files in this archive.
+    pub fn beatmap_names(&self) -> Vec<PathBuf> {
+        let mut names = self
+            .zip
+            .file_names()
+            .filter(|name| Self::normalize(name).ends_with(".osu"))
+            .map(PathBuf::from)
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// Check whether a file exists within the archive, given its path relative to the archive root.
+    pub fn contains(&self, name: &Path) -> bool {
+        self.by_name
+            .contains_key(&Self::normalize(&name.to_string_lossy()))
+    }
+
+    /// Open a file within the archive, given its path relative to the archive root.
+    pub fn open_file(&mut self, name: &Path) -> Result<zip::read::ZipFile<'_>> {
+        let name = self
+            .by_name
+            .get(&Self::normalize(&name.to_string_lossy()))
+            .ok_or_else(|| anyhow!("file not found in archive"))?;
+        self.zip.by_name(name).context("read file from archive")
+    }
+}
+
+/// The approximated geometry of an osu! slider, as a polyline.
+///
+/// Curves are evaluated roughly the same way osu!lazer does, and the path is trimmed or extended
+/// to the slider length given in the beatmap.
+#[derive(Debug, Clone)]
+pub struct SliderPath {
+    points: Vec<(f64, f64)>,
+    /// The distance along the path at each point.
+    cumulative: Vec<f64>,
+}
+impl SliderPath {
+    /// How many points to sample per catmull segment.
+    const CATMULL_DETAIL: usize = 50;
+    /// The approximate distance between sampled points on bezier and circular curves, in osu!
+    /// pixels.
+    const CURVE_DETAIL: f64 = 2.;
+
+    /// Build a slider path from the slider start position, the curve field of the slider
+    /// (eg. `B|100:200|150:250`) and the slider length in osu! pixels.
+    pub fn parse(start: (f64, f64), curve: &str, length: f64) -> Result<SliderPath> {
+        let mut parts = curve.split('|');
+        let curve_ty = parts.next().unwrap_or_default();
+        let mut control = vec![start];
+        for point in parts {
+            let mut coords = point.split(':');
+            let mut coord = |name: &str| -> Result<f64> {
+                coords
+                    .next()
+                    .unwrap_or_default()
+                    .parse::<f64>()
+                    .map_err(|_| anyhow!("invalid slider point \"{}\", expected {}", point, name))
+            };
+            let x = coord("x")?;
+            let y = coord("y")?;
+            control.push((x, y));
+        }
+        let mut points = Vec::new();
+        match curve_ty {
+            "L" => points.extend_from_slice(&control),
+            "P" if control.len() == 3 => {
+                if !Self::circle_arc(&control, &mut points) {
+                    //Degenerate circle, fall back to a straight line
+                    points.clear();
+                    points.extend_from_slice(&control);
+                }
+            }
+            "C" => Self::catmull(&control, &mut points),
+            //Bezier, and perfect circles with the wrong amount of points
+            "B" | "P" => {
+                //Repeated points split the curve into several bezier segments
+                let mut seg_start = 0;
+                for idx in 1..=control.len() {
+                    if idx == control.len() || control[idx] == control[idx - 1] {
+                        Self::bezier(&control[seg_start..idx], &mut points);
+                        seg_start = idx;
+                    }
+                }
+            }
+            _ => bail!("unknown slider curve type \"{}\"", curve_ty),
+        }
+        if points.is_empty() {
+            points.push(start);
+        }
+        let mut path = SliderPath {
+            cumulative: Vec::with_capacity(points.len()),
+            points,
+        };
+        path.fit_length(length);
+        Ok(path)
+    }
+
+    fn bezier(control: &[(f64, f64)], out: &mut Vec<(f64, f64)>) {
+        if control.len() < 2 {
+            out.extend_from_slice(control);
+            return;
+        }
+        let polygon_len = control
+            .windows(2)
+            .map(|pair| dist(pair[0], pair[1]))
+            .sum::<f64>();
+        let steps = ((polygon_len / Self::CURVE_DETAIL).ceil() as usize).clamp(1, 1000);
+        let mut tmp = Vec::with_capacity(control.len());
+        for step in 0..=steps {
+            //De Casteljau's algorithm
+            let t = step as f64 / steps as f64;
+            tmp.clear();
+            tmp.extend_from_slice(control);
+            for level in (1..tmp.len()).rev() {
+                for i in 0..level {
+                    tmp[i] = lerp(tmp[i], tmp[i + 1], t);
+                }
+            }
+            out.push(tmp[0]);
+        }
+    }
+
+    /// Returns `false` if the three points do not define a proper circle.
+    fn circle_arc(control: &[(f64, f64)], out: &mut Vec<(f64, f64)>) -> bool {
+        let (a, b, c) = (control[0], control[1], control[2]);
+        let det = 2. * (a.0 * (b.1 - c.1) + b.0 * (c.1 - a.1) + c.0 * (a.1 - b.1));
+        if det.abs() < 1e-3 {
+            return false;
+        }
+        let sq = |p: (f64, f64)| p.0 * p.0 + p.1 * p.1;
+        let center = (
+            (sq(a) * (b.1 - c.1) + sq(b) * (c.1 - a.1) + sq(c) * (a.1 - b.1)) / det,
+            (sq(a) * (c.0 - b.0) + sq(b) * (a.0 - c.0) + sq(c) * (b.0 - a.0)) / det,
+        );
+        let radius = dist(a, center);
+        let angle = |p: (f64, f64)| (p.1 - center.1).atan2(p.0 - center.0);
+        let start = angle(a);
+        let mut end = angle(c);
+        //Go around the circle in the direction that passes through the middle point
+        let clockwise = (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0) < 0.;
+        let tau = 2. * std::f64::consts::PI;
+        if clockwise {
+            while end > start {
+                end -= tau;
+            }
+        } else {
+            while end < start {
+                end += tau;
+            }
+        }
+        let arc_len = (end - start).abs() * radius;
+        let steps = ((arc_len / Self::CURVE_DETAIL).ceil() as usize).clamp(2, 1000);
+        for step in 0..=steps {
+            let theta = start + (end - start) * step as f64 / steps as f64;
+            out.push((
+                center.0 + radius * theta.cos(),
+                center.1 + radius * theta.sin(),
+            ));
+        }
+        true
+    }
+
+    fn catmull(control: &[(f64, f64)], out: &mut Vec<(f64, f64)>) {
+        let n = control.len();
+        for i in 0..n.saturating_sub(1) {
+            let v2 = control[i];
+            let v1 = if i > 0 { control[i - 1] } else { v2 };
+            let v3 = control[i + 1];
+            let v4 = if i + 2 < n {
+                control[i + 2]
+            } else {
+                (2. * v3.0 - v2.0, 2. * v3.1 - v2.1)
+            };
+            for step in 0..Self::CATMULL_DETAIL {
+                let t = step as f64 / Self::CATMULL_DETAIL as f64;
+                let (t2, t3) = (t * t, t * t * t);
+                let coord = |p1: f64, p2: f64, p3: f64, p4: f64| {
+                    0.5 * (2. * p2
+                        + (-p1 + p3) * t
+                        + (2. * p1 - 5. * p2 + 4. * p3 - p4) * t2
+                        + (-p1 + 3. * p2 - 3. * p3 + p4) * t3)
+                };
+                out.push((coord(v1.0, v2.0, v3.0, v4.0), coord(v1.1, v2.1, v3.1, v4.1)));
+            }
+        }
+        if let Some(&last) = control.last() {
+            out.push(last);
+        }
+    }
+
+    /// Trim or extend the path so that its length matches the given length.
+    fn fit_length(&mut self, length: f64) {
+        self.cumulative.clear();
+        let mut total = 0.;
+        self.cumulative.push(0.);
+        for pair in self.points.windows(2) {
+            total += dist(pair[0], pair[1]);
+            self.cumulative.push(total);
+        }
+        if !length.is_finite() || length <= 0. {
+            return;
+        }
+        if total > length {
+            //Trim the path
+            let end = self
+                .cumulative
+                .iter()
+                .position(|&d| d >= length)
+                .unwrap_or(self.points.len() - 1);
+            let seg_len = self.cumulative[end] - self.cumulative[end - 1];
+            let t = if seg_len > 0. {
+                (length - self.cumulative[end - 1]) / seg_len
+            } else {
+                0.
+            };
+            self.points[end] = lerp(self.points[end - 1], self.points[end], t);
+            self.cumulative[end] = length;
+            self.points.truncate(end + 1);
+            self.cumulative.truncate(end + 1);
+        } else if total < length {
+            //Extend the last segment in its direction
+            let n = self.points.len();
+            let last = self.points[n - 1];
+            let dir = if n >= 2 {
+                let prev = self.points[n - 2];
+                let len = dist(prev, last);
+                if len > 0. {
+                    ((last.0 - prev.0) / len, (last.1 - prev.1) / len)
+                } else {
+                    (0., 0.)
+                }
+            } else {
+                (0., 0.)
+            };
+            let extra = length - total;
+            self.points
+                .push((last.0 + dir.0 * extra, last.1 + dir.1 * extra));
+            self.cumulative.push(length);
+        }
+    }
+
+    /// The length of a single slide of the slider, in osu! pixels.
+    pub fn length(&self) -> f64 {
+        self.cumulative.last().copied().unwrap_or(0.)
+    }
+
+    /// Get the position along a single slide, where `0` is the slider head and `1` is the slider
+    /// end.
+    pub fn position_at(&self, progress: f64) -> (f64, f64) {
+        let d = progress.clamp(0., 1.) * self.length();
+        let idx = self
+            .cumulative
+            .iter()
+            .position(|&cum| cum >= d)
+            .unwrap_or(self.points.len() - 1);
+        if idx == 0 {
+            return self.points[0];
+        }
+        let seg_len = self.cumulative[idx] - self.cumulative[idx - 1];
+        let t = if seg_len > 0. {
+            (d - self.cumulative[idx - 1]) / seg_len
+        } else {
+            1.
+        };
+        lerp(self.points[idx - 1], self.points[idx], t)
+    }
+
+    /// Get the position after the given amount of slides, which might be fractional.
+    /// Takes into account that the slider goes back and forth on repeats.
+    pub fn position_at_slides(&self, progress: f64) -> (f64, f64) {
+        let span = progress.floor();
+        let frac = progress - span;
+        if span as i64 % 2 == 0 {
+            self.position_at(frac)
+        } else {
+            self.position_at(1. - frac)
+        }
+    }
+
+    /// The final position of a slider with the given amount of slides.
+    pub fn end_position(&self, slides: usize) -> (f64, f64) {
+        self.position_at_slides(slides as f64)
+    }
+
+    /// The positions at the end of each slide, the last one being the slider end.
+    pub fn repeat_positions(&self, slides: usize) -> Vec<(f64, f64)> {
+        (1..=slides)
+            .map(|slide| self.position_at_slides(slide as f64))
+            .collect()
+    }
+
+    /// The slider ticks, as pairs of progress (measured in slides) and position.
+    ///
+    /// Ticks are placed every `tick_dist` pixels along each slide, skipping any ticks that are
+    /// closer than `min_dist_from_end` to the end of their slide.
+    pub fn ticks(
+        &self,
+        tick_dist: f64,
+        slides: usize,
+        min_dist_from_end: f64,
+    ) -> Vec<(f64, (f64, f64))> {
+        let mut ticks = Vec::new();
+        let length = self.length();
+        if tick_dist.is_nan() || tick_dist <= 0. || length <= 0. {
+            return ticks;
+        }
+        let mut span_ticks = Vec::new();
+        let mut d = tick_dist;
+        while d < length - min_dist_from_end {
+            span_ticks.push(d / length);
+            d += tick_dist;
+        }
+        for span in 0..slides {
+            let reversed = span % 2 == 1;
+            let ordered: Box<dyn Iterator<Item = &f64>> = if reversed {
+                Box::new(span_ticks.iter().rev())
+            } else {
+                Box::new(span_ticks.iter())
+            };
+            for &p in ordered {
+                let progress = if reversed { 1. - p } else { p };
+                ticks.push((span as f64 + progress, self.position_at(p)));
+            }
+        }
+        ticks
+    }
+}
+
+fn dist(a: (f64, f64), b: (f64, f64)) -> f64 {
+    ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt()
+}
+
+fn lerp(a: (f64, f64), b: (f64, f64), t: f64) -> (f64, f64) {
+    (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t)
 }
 
 #[derive(Debug, Clone)]
@@ -288,6 +789,10 @@ pub struct TimingPoint {
     pub time: f64,
     pub beat_len: f64,
     pub meter: i32,
+    /// The sample set of this timing point, or `0` to use the beatmap default.
+    pub sample_set: u32,
+    /// The custom sample index, or `0` to use the skin samples.
+    pub sample_index: u32,
 }
 
 #[derive(Debug, Clone)]
@@ -296,8 +801,117 @@ pub struct HitObject {
     pub y: f64,
     pub time: f64,
     pub ty: u32,
+    pub hitsound: u32,
     pub extras: String,
 }
+impl HitObject {
+    /// Parse the `hitSample` extras of this hit object.
+    /// Missing or invalid fields are left as zero, as osu! does.
+    pub fn hit_sample(&self) -> HitSample {
+        let raw = if self.ty & TYPE_LONG != 0 {
+            self.extras
+                .split_once(':')
+                .map(|(_end_time, sample)| sample)
+        } else if self.ty & TYPE_SLIDER != 0 {
+            self.extras.split(',').nth(5)
+        } else if self.ty & TYPE_SPINNER != 0 {
+            self.extras.split(',').nth(1)
+        } else {
+            Some(&self.extras[..])
+        };
+        let mut comps = raw.unwrap_or_default().splitn(5, ':');
+        let mut next_u32 = || {
+            comps
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .parse::<u32>()
+                .unwrap_or(0)
+        };
+        let normal_set = next_u32();
+        let addition_set = next_u32();
+        let index = next_u32();
+        let _volume = next_u32();
+        HitSample {
+            normal_set,
+            addition_set,
+            index,
+            filename: comps.next().unwrap_or_default().trim().to_string(),
+        }
+    }
+}
+
+/// The `hitSample` extras of a hit object, which override the timing point sample settings.
+#[derive(Debug, Clone, Default)]
+pub struct HitSample {
+    /// The sample set of the normal sound, or `0` to use the timing point sample set.
+    pub normal_set: u32,
+    /// The sample set of the whistle, finish and clap sounds, or `0` to use the normal set.
+    pub addition_set: u32,
+    /// The custom sample index, or `0` to use the timing point sample index.
+    pub index: u32,
+    /// A custom sample file that replaces the normal sound entirely, or empty if none.
+    pub filename: String,
+}
+impl HitSample {
+    /// Get the names of the sample files that this hit object may play, taking into account the
+    /// given timing point and beatmap defaults.
+    /// Names are listed in order of preference, with additions (whistle, finish, clap) first.
+    /// Since the extension is not known, each sample is listed with all extensions osu! accepts.
+    /// Skin samples are never listed, since they are not part of the beatmapset.
+    pub fn file_names(&self, bm: &Beatmap, tp: &TimingPoint, hitsound: u32) -> Vec<String> {
+        if !self.filename.is_empty() {
+            return vec![self.filename.clone()];
+        }
+        let index = if self.index != 0 {
+            self.index
+        } else {
+            tp.sample_index
+        };
+        if index == 0 {
+            return vec![];
+        }
+        let normal_set = [self.normal_set, tp.sample_set, bm.sample_set]
+            .iter()
+            .copied()
+            .find(|&set| set != 0)
+            .unwrap_or(SAMPLESET_NORMAL);
+        let addition_set = if self.addition_set != 0 {
+            self.addition_set
+        } else {
+            normal_set
+        };
+        let set_name = |set: u32| match set {
+            SAMPLESET_SOFT => "soft",
+            SAMPLESET_DRUM => "drum",
+            _ => "normal",
+        };
+        let suffix = if index == 1 {
+            String::new()
+        } else {
+            index.to_string()
+        };
+        let mut names = Vec::new();
+        let mut add = |set: u32, sound: &str| {
+            for ext in ["wav", "ogg", "mp3"].iter() {
+                names.push(format!("{}-hit{}{}.{}", set_name(set), sound, suffix, ext));
+            }
+        };
+        for &(flag, sound) in [
+            (HITSOUND_WHISTLE, "whistle"),
+            (HITSOUND_FINISH, "finish"),
+            (HITSOUND_CLAP, "clap"),
+        ]
+        .iter()
+        {
+            if hitsound & flag != 0 {
+                add(addition_set, sound);
+            }
+        }
+        add(normal_set, "normal");
+        names
+    }
+}
 
 pub const MODE_STD: i32 = 0;
 pub const MODE_TAIKO: i32 = 1;
@@ -308,3 +922,164 @@ pub const TYPE_HIT: u32 = 1 << 0;
 pub const TYPE_SLIDER: u32 = 1 << 1;
 pub const TYPE_SPINNER: u32 = 1 << 3;
 pub const TYPE_LONG: u32 = 1 << 7;
+
+pub const HITSOUND_NORMAL: u32 = 1 << 0;
+pub const HITSOUND_WHISTLE: u32 = 1 << 1;
+pub const HITSOUND_FINISH: u32 = 1 << 2;
+pub const HITSOUND_CLAP: u32 = 1 << 3;
+
+pub const SAMPLESET_NORMAL: u32 = 1;
+pub const SAMPLESET_SOFT: u32 = 2;
+pub const SAMPLESET_DRUM: u32 = 3;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(actual: (f64, f64), expected: (f64, f64), tolerance: f64) {
+        assert!(
+            dist(actual, expected) <= tolerance,
+            "expected {:?}, got {:?}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn beatmap_roundtrip() {
+        let tp = |time, beat_len| TimingPoint {
+            time,
+            beat_len,
+            meter: 4,
+            sample_set: 0,
+            sample_index: 0,
+        };
+        let obj = |time, ty, extras: &str| HitObject {
+            x: 64.,
+            y: 192.,
+            time,
+            ty,
+            hitsound: 0,
+            extras: extras.to_string(),
+        };
+        let bm = Beatmap {
+            mode: MODE_MANIA,
+            circle_size: 4.,
+            title: "Song".to_string(),
+            version: "4K Hard".to_string(),
+            timing_points: vec![tp(0., 500.), tp(1000., 250.), tp(1500., -50.)],
+            hit_objects: vec![
+                obj(0., TYPE_HIT, "0:0:0:0:"),
+                obj(500., TYPE_LONG, "1000:0:0:0:0:"),
+                obj(1500., TYPE_HIT, "0:0:0:0:"),
+            ],
+            ..default()
+        };
+        let mut raw = Vec::new();
+        bm.write_to(&mut raw).unwrap();
+        let loaded = Beatmap::parse_from(0., Path::new("test.osu"), &raw[..]).unwrap();
+        assert_eq!(loaded.mode, bm.mode);
+        assert_eq!(loaded.circle_size, bm.circle_size);
+        assert_eq!(loaded.title, bm.title);
+        assert_eq!(loaded.version, bm.version);
+        let simplify_tps = |bm: &Beatmap| {
+            bm.timing_points
+                .iter()
+                .map(|tp| (tp.time, tp.beat_len, tp.meter))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(simplify_tps(&loaded), simplify_tps(&bm));
+        let simplify_objs = |bm: &Beatmap| {
+            bm.hit_objects
+                .iter()
+                .map(|obj| (obj.x, obj.time, obj.ty, obj.extras.clone()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(simplify_objs(&loaded), simplify_objs(&bm));
+    }
+
+    fn end(curve: &str, length: f64) -> (f64, f64) {
+        SliderPath::parse((0., 0.), curve, length)
+            .unwrap()
+            .end_position(1)
+    }
+
+    #[test]
+    fn linear_end() {
+        assert_near(end("L|100:0", 100.), (100., 0.), 1e-9);
+        assert_near(end("L|100:0|100:100", 200.), (100., 100.), 1e-9);
+    }
+
+    #[test]
+    fn perfect_circle_end() {
+        let half_circle = std::f64::consts::PI * 50.;
+        assert_near(end("P|50:50|100:0", half_circle), (100., 0.), 0.1);
+        //Trimmed to a quarter circle
+        assert_near(end("P|50:50|100:0", half_circle / 2.), (50., 50.), 0.1);
+        //Going around the other way
+        assert_near(end("P|50:-50|100:0", half_circle / 2.), (50., -50.), 0.1);
+    }
+
+    #[test]
+    fn degenerate_circle_is_linear() {
+        assert_near(end("P|50:0|100:0", 100.), (100., 0.), 1e-9);
+    }
+
+    #[test]
+    fn bezier_end() {
+        assert_near(end("B|50:100|100:0", 0.), (100., 0.), 1e-9);
+        let path = SliderPath::parse((0., 0.), "B|50:100|100:0", 0.).unwrap();
+        //The middle of a symmetric quadratic curve is halfway to its control point
+        assert_near(path.position_at(0.5), (50., 50.), 0.5);
+        //Repeated points start a new segment
+        assert_near(end("B|50:0|50:0|50:50", 100.), (50., 50.), 1e-9);
+    }
+
+    #[test]
+    fn catmull_end() {
+        assert_near(end("C|50:0|100:0", 100.), (100., 0.), 1e-9);
+    }
+
+    #[test]
+    fn trim_longer_path() {
+        let path = SliderPath::parse((0., 0.), "L|200:0", 100.).unwrap();
+        assert_eq!(path.length(), 100.);
+        assert_near(path.end_position(1), (100., 0.), 1e-9);
+    }
+
+    #[test]
+    fn extend_shorter_path() {
+        let path = SliderPath::parse((0., 0.), "L|0:50", 100.).unwrap();
+        assert_eq!(path.length(), 100.);
+        assert_near(path.end_position(1), (0., 100.), 1e-9);
+    }
+
+    #[test]
+    fn repeats_and_ticks() {
+        //Two repeats make three slides
+        let path = SliderPath::parse((0., 0.), "L|100:0", 100.).unwrap();
+        let repeats = path.repeat_positions(3);
+        assert_eq!(repeats.len(), 3);
+        assert_near(repeats[0], (100., 0.), 1e-9);
+        assert_near(repeats[1], (0., 0.), 1e-9);
+        assert_near(repeats[2], (100., 0.), 1e-9);
+        assert_near(path.end_position(3), (100., 0.), 1e-9);
+        let ticks = path.ticks(25., 3, 10.);
+        let expected = [
+            (0.25, 25.),
+            (0.5, 50.),
+            (0.75, 75.),
+            (1.25, 75.),
+            (1.5, 50.),
+            (1.75, 25.),
+            (2.25, 25.),
+            (2.5, 50.),
+            (2.75, 75.),
+        ];
+        assert_eq!(ticks.len(), expected.len());
+        for (&(progress, pos), &(exp_progress, exp_x)) in ticks.iter().zip(expected.iter()) {
+            assert!((progress - exp_progress).abs() < 1e-9);
+            assert_near(pos, (exp_x, 0.), 1e-9);
+        }
+    }
+}
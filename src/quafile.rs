@@ -0,0 +1,168 @@
+//! Read and write Quaver `.qua` charts, which are plain YAML documents.
+//!
+//! All times are in milliseconds.
+//! Quaver omits fields that have their default value, so most fields are optional.
+
+use crate::prelude::*;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, rename_all = "PascalCase")]
+pub struct Qua {
+    pub audio_file: String,
+    pub song_preview_time: i32,
+    pub background_file: String,
+    pub map_id: i32,
+    pub map_set_id: i32,
+    pub mode: QuaMode,
+    /// Whether there is an extra scratch lane after the regular lanes.
+    #[serde(skip_serializing_if = "is_false")]
+    pub has_scratch_key: bool,
+    pub title: String,
+    pub artist: String,
+    pub source: String,
+    pub tags: String,
+    pub creator: String,
+    pub difficulty_name: String,
+    pub description: String,
+    pub genre: String,
+    pub custom_audio_samples: Vec<QuaAudioSample>,
+    pub timing_points: Vec<QuaTimingPoint>,
+    pub slider_velocities: Vec<QuaSliderVelocity>,
+    pub hit_objects: Vec<QuaHitObject>,
+}
+impl Default for Qua {
+    fn default() -> Self {
+        Self {
+            audio_file: default(),
+            song_preview_time: 0,
+            background_file: default(),
+            map_id: -1,
+            map_set_id: -1,
+            mode: QuaMode::Keys4,
+            has_scratch_key: false,
+            title: default(),
+            artist: default(),
+            source: default(),
+            tags: default(),
+            creator: default(),
+            difficulty_name: default(),
+            description: default(),
+            genre: default(),
+            custom_audio_samples: default(),
+            timing_points: default(),
+            slider_velocities: default(),
+            hit_objects: default(),
+        }
+    }
+}
+impl Qua {
+    pub fn load(path: &Path) -> Result<Qua> {
+        let file = BufReader::new(File::open(path).context("open file")?);
+        serde_yaml::from_reader(file).context("parse yaml")
+    }
+
+    /// The total amount of lanes, including the scratch lane.
+    pub fn key_count(&self) -> i32 {
+        self.mode.key_count() + self.has_scratch_key as i32
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file = BufWriter::new(File::create(path).context("create file")?);
+        serde_yaml::to_writer(file, self).context("write yaml")?;
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum QuaMode {
+    Keys4,
+    Keys7,
+}
+impl QuaMode {
+    pub fn from_key_count(key_count: i32) -> Option<QuaMode> {
+        match key_count {
+            4 => Some(QuaMode::Keys4),
+            7 => Some(QuaMode::Keys7),
+            _ => None,
+        }
+    }
+
+    pub fn key_count(self) -> i32 {
+        match self {
+            QuaMode::Keys4 => 4,
+            QuaMode::Keys7 => 7,
+        }
+    }
+}
+
+/// Quaver only knows about 4/4 and 3/4 time signatures.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum QuaSignature {
+    #[default]
+    Quadruple,
+    Triple,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct QuaAudioSample {
+    /// Path to the sample file, relative to the chart.
+    pub path: String,
+    #[serde(default)]
+    pub unaffected_by_rate: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct QuaTimingPoint {
+    #[serde(default)]
+    pub start_time: f64,
+    #[serde(default)]
+    pub bpm: f64,
+    #[serde(default, skip_serializing_if = "is_quadruple")]
+    pub signature: QuaSignature,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct QuaSliderVelocity {
+    #[serde(default)]
+    pub start_time: f64,
+    #[serde(default)]
+    pub multiplier: f64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct QuaHitObject {
+    #[serde(default)]
+    pub start_time: i32,
+    /// 1-based lane.
+    pub lane: i32,
+    /// If nonzero, this hit object is a long note ending at this time.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub end_time: i32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub key_sounds: Vec<QuaKeySound>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct QuaKeySound {
+    /// 1-based index into `custom_audio_samples`.
+    pub sample: u32,
+    #[serde(default)]
+    pub volume: i32,
+}
+
+fn is_quadruple(sig: &QuaSignature) -> bool {
+    *sig == QuaSignature::Quadruple
+}
+
+fn is_zero(num: &i32) -> bool {
+    *num == 0
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
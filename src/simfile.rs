@@ -22,94 +22,112 @@ pub struct Simfile {
     pub music: Option<PathBuf>,
     pub offset: f64,
     pub bpms: Vec<ControlPoint>,
+    /// Stops, as `(beat, seconds)`.
+    /// Notes on the beat of a stop are hit before the stop.
     pub stops: Vec<(f64, f64)>,
+    /// Delays, as `(beat, seconds)`.
+    /// Unlike stops, notes on the beat of a delay are hit after the delay.
+    /// Written as stops to `.sm` files.
+    pub delays: Vec<(f64, f64)>,
+    /// Warps, as `(beat, length in beats)`.
+    /// The beats within a warp are skipped instantly.
+    /// Written as negative stops to `.sm` files.
+    pub warps: Vec<(f64, f64)>,
     pub sample_start: Option<f64>,
     pub sample_len: Option<f64>,
     pub display_bpm: DisplayBpm,
+    /// Time signature changes, as `(beat, numerator, denominator)`.
+    /// Only written to `.ssc` files.
+    pub time_signatures: Vec<(f64, i32, i32)>,
+    /// Scroll speed changes, as `(beat, speed ratio)`.
+    /// Only written to `.ssc` files.
+    pub scrolls: Vec<(f64, f64)>,
     pub gamemode: Gamemode,
+    /// The name of this chart, only written to `.ssc` files.
+    pub chart_name: String,
     pub desc: String,
     pub difficulty: Difficulty,
     pub difficulty_num: f64,
     pub radar: [f64; 5],
+    /// Per-skillset difficulty ratings, set by `RateMethod::Skillsets`.
+    pub skillsets: Skillsets,
     pub notes: Vec<Note>,
+    /// The names of the osu! collections that the source beatmap belongs to.
+    pub collections: Vec<String>,
+    /// Sound files that notes can play when hit, referenced by `Note::keysound`.
+    pub keysounds: Vec<PathBuf>,
+    /// Information about the chart this simfile was converted from.
+    pub source_meta: SourceMeta,
+}
+impl Default for Simfile {
+    fn default() -> Self {
+        Simfile {
+            title: default(),
+            subtitle: default(),
+            artist: default(),
+            title_trans: default(),
+            subtitle_trans: default(),
+            artist_trans: default(),
+            genre: default(),
+            credit: default(),
+            banner: None,
+            background: None,
+            lyrics: None,
+            cdtitle: None,
+            music: None,
+            offset: 0.,
+            bpms: vec![],
+            stops: vec![],
+            delays: vec![],
+            warps: vec![],
+            sample_start: None,
+            sample_len: None,
+            display_bpm: DisplayBpm::Random,
+            time_signatures: vec![],
+            scrolls: vec![],
+            gamemode: Gamemode::DanceSingle,
+            chart_name: default(),
+            desc: default(),
+            difficulty: Difficulty::Edit,
+            difficulty_num: f64::NAN,
+            radar: [0., 0., 0., 0., 0.],
+            skillsets: default(),
+            notes: vec![],
+            collections: vec![],
+            keysounds: vec![],
+            source_meta: default(),
+        }
+    }
 }
 impl Simfile {
+    /// Write the given simfiles as the charts of a single `.sm` file.
+    /// Song-wide data, including timing data, is taken from the first simfile.
     pub fn save<'a>(path: &Path, simfiles: impl IntoIterator<Item = &'a Simfile>) -> Result<()> {
-        let mut simfiles = simfiles.into_iter();
-        let main_sm = simfiles.next().ok_or(anyhow!("zero simfiles supplied"))?;
+        let simfiles = simfiles.into_iter().collect::<Vec<_>>();
+        let main_sm = *simfiles.first().ok_or(anyhow!("zero simfiles supplied"))?;
+        let (keysounds, keysound_maps) = merge_keysounds(&simfiles);
         let mut file = BufWriter::new(File::create(path).context("create file")?);
-        fn as_utf8<'a>(path: &'a Option<PathBuf>, name: &str) -> Result<&'a str> {
-            path.as_deref()
-                .unwrap_or_else(|| "".as_ref())
-                .to_str()
-                .ok_or_else(|| anyhow!("non-utf8 {}", name))
-        }
         write!(
             file,
             r#"
 // Simfile converted from osu! automatically using `osu2sm` by negamartin
-#TITLE:{title};
-#SUBTITLE:{subtitle};
-#ARTIST:{artist};
-#TITLETRANSLIT:{title_t};
-#SUBTITLETRANSLIT:{subtitle_t};
-#ARTISTTRANSLIT:{artist_t};
-#GENRE:{genre};
-#CREDIT:{credit};
-#BANNER:{banner};
-#BACKGROUND:{bg};
-#LYRICSPATH:{lyrics};
-#CDTITLE:{cdtitle};
-#MUSIC:{music};
-#OFFSET:{offset};
-#SAMPLESTART:{sample_start};
-#SAMPLELENGTH:{sample_len};
-#DISPLAYBPM:{display_bpm};
-#SELECTABLE:YES;
+"#
+        )?;
+        write_song_header(&mut file, main_sm, &keysounds)?;
+        write!(
+            file,
+            r#"#OFFSET:{offset};
 #BPMS:{bpms};
-#STOPS:;
+#STOPS:{stops};
 #BGCHANGES:;
-#KEYSOUNDS:;
 #ATTACKS:;
 "#,
-            title = main_sm.title,
-            subtitle = main_sm.subtitle,
-            artist = main_sm.artist,
-            title_t = main_sm.title_trans,
-            subtitle_t = main_sm.subtitle_trans,
-            artist_t = main_sm.artist_trans,
-            genre = main_sm.genre,
-            credit = main_sm.credit,
-            banner = as_utf8(&main_sm.banner, "BANNER")?,
-            bg = as_utf8(&main_sm.background, "BACKGROUND")?,
-            lyrics = as_utf8(&main_sm.lyrics, "LYRICSPATH")?,
-            cdtitle = as_utf8(&main_sm.cdtitle, "CDTITLE")?,
-            music = as_utf8(&main_sm.music, "MUSIC")?,
             offset = main_sm.offset,
-            sample_start = main_sm
-                .sample_start
-                .map(|s| format!("{}", s))
-                .unwrap_or_else(String::new),
-            sample_len = main_sm
-                .sample_len
-                .map(|l| format!("{}", l))
-                .unwrap_or_else(String::new),
-            display_bpm = main_sm.display_bpm.to_string(),
-            bpms = {
-                let mut bpms = String::new();
-                let mut first = true;
-                for point in main_sm.bpms.iter() {
-                    if first {
-                        first = false;
-                    } else {
-                        bpms.push(',');
-                    }
-                    write!(bpms, "{}={}", point.beat.as_num(), point.bpm()).unwrap();
-                }
-                bpms
-            },
+            bpms = fmt_bpms(&main_sm.bpms),
+            stops = fmt_sm_stops(main_sm),
         )?;
-        for sm in iter::once(main_sm).chain(simfiles) {
+        for (sm, keysound_map) in simfiles.iter().zip(keysound_maps.iter()) {
+            write!(file, "{}", fmt_source_comment(sm))?;
             write!(
                 file,
                 r#"
@@ -129,12 +147,120 @@ impl Simfile {
                 radar3 = sm.radar[3],
                 radar4 = sm.radar[4],
             )?;
-            write_notedata(&mut file, &sm)?;
+            write_notedata(&mut file, sm, keysound_map)?;
             write!(file, ";")?;
         }
         Ok(())
     }
 
+    /// Write the given simfiles as the charts of a single `.ssc` file.
+    /// Unlike `.sm` files, each chart keeps its own timing data and name.
+    pub fn save_ssc<'a>(
+        path: &Path,
+        simfiles: impl IntoIterator<Item = &'a Simfile>,
+    ) -> Result<()> {
+        let simfiles = simfiles.into_iter().collect::<Vec<_>>();
+        let main_sm = *simfiles.first().ok_or(anyhow!("zero simfiles supplied"))?;
+        let (keysounds, keysound_maps) = merge_keysounds(&simfiles);
+        let mut file = BufWriter::new(File::create(path).context("create file")?);
+        write!(
+            file,
+            r#"
+// Simfile converted from osu! automatically using `osu2sm` by negamartin
+#VERSION:0.83;
+"#
+        )?;
+        write_song_header(&mut file, main_sm, &keysounds)?;
+        write_ssc_timing(&mut file, main_sm)?;
+        write!(
+            file,
+            r#"#BGCHANGES:;
+#ATTACKS:;
+"#
+        )?;
+        for (sm, keysound_map) in simfiles.iter().zip(keysound_maps.iter()) {
+            write!(
+                file,
+                r#"
+//---------------{gamemode} - {chart_name}----------------{source_comment}
+#NOTEDATA:;
+#CHARTNAME:{chart_name};
+#STEPSTYPE:{gamemode};
+#DESCRIPTION:{desc};
+#CHARTSTYLE:;
+#DIFFICULTY:{diff_name};
+#METER:{diff_num};
+#RADARVALUES:{radar0},{radar1},{radar2},{radar3},{radar4};
+#CREDIT:{credit};
+#DISPLAYBPM:{display_bpm};
+"#,
+                gamemode = sm.gamemode.id(),
+                chart_name = sm.chart_name,
+                desc = sm.desc,
+                diff_name = sm.difficulty.name(),
+                diff_num = sm.difficulty_num.round(),
+                radar0 = sm.radar[0],
+                radar1 = sm.radar[1],
+                radar2 = sm.radar[2],
+                radar3 = sm.radar[3],
+                radar4 = sm.radar[4],
+                credit = sm.credit,
+                display_bpm = sm.display_bpm.to_string(),
+                source_comment = fmt_source_comment(sm),
+            )?;
+            write_ssc_timing(&mut file, sm)?;
+            write!(file, "#NOTES:")?;
+            write_notedata(&mut file, sm, keysound_map)?;
+            write!(file, "\n;\n")?;
+        }
+        Ok(())
+    }
+
+    /// The beats at which a new timing section starts: bpm and time signature changes, and the
+    /// ends of stops, delays and warps.
+    /// Use `ToTime::beat_to_time_after` to get the time at which each section starts.
+    pub fn timing_beats(&self) -> Vec<BeatPos> {
+        let mut beats = self
+            .bpms
+            .iter()
+            .map(|cp| cp.beat)
+            .chain(
+                self.time_signatures
+                    .iter()
+                    .map(|&(beat, _, _)| BeatPos::from(beat)),
+            )
+            .chain(self.stops.iter().map(|&(beat, _)| BeatPos::from(beat)))
+            .chain(self.delays.iter().map(|&(beat, _)| BeatPos::from(beat)))
+            .chain(
+                self.warps
+                    .iter()
+                    .map(|&(beat, len)| BeatPos::from(beat + len)),
+            )
+            .collect::<Vec<_>>();
+        beats.sort();
+        beats.dedup();
+        beats
+    }
+
+    /// Stops, delays and warps expressed as stops only, for formats that only have stops.
+    /// Delays become stops right before their beat and warps become negative stops.
+    pub fn folded_stops(&self) -> Vec<(f64, f64)> {
+        let mut stops = self.stops.clone();
+        stops.extend(
+            self.delays
+                .iter()
+                .map(|&(beat, secs)| ((BeatPos::from(beat) - BeatPos::EPSILON).as_num(), secs)),
+        );
+        let mut to_time = ToTime::from_raw(&self.bpms, 0.);
+        for &(beat, len) in self.warps.iter() {
+            let start = to_time.beat_to_time(BeatPos::from(beat));
+            let end = to_time.beat_to_time(BeatPos::from(beat + len));
+            stops.push((beat, start - end));
+        }
+        stops.sort_by_key(|&(beat, _)| SortableFloat(beat));
+        stops
+    }
+
     /// Get the files that this simfile references.
     pub fn file_deps(&self) -> impl Iterator<Item = &Path> {
         self.banner
@@ -144,6 +270,7 @@ impl Simfile {
             .chain(self.lyrics.as_deref().into_iter())
             .chain(self.cdtitle.as_deref().into_iter())
             .chain(self.music.as_deref().into_iter())
+            .chain(self.keysounds.iter().map(PathBuf::as_path))
     }
 
     /// Iterate over the populated beats in a simfile.
@@ -185,7 +312,7 @@ impl Simfile {
                 if self.notes[i + 1..]
                     .iter()
                     .take_while(|next_n| next_n.beat == cur_beat)
-                    .any(|next_n| next_n.key == note.key)
+                    .any(|next_n| next_n.key == note.key && next_n.player == note.player)
                 {
                     //Move back by the smallest beat unit, and to the previous beat
                     self.notes[i].beat -= BeatPos::EPSILON;
@@ -225,8 +352,9 @@ impl Simfile {
             last_beat = cp.beat;
         }
         //Check a single beat
-        let mut beat_notes = vec![false; key_count];
-        let mut beat_tails = vec![false; key_count];
+        //Routine charts have separate note data for each player, so keys are tracked per player
+        let mut beat_notes = vec![false; key_count * 2];
+        let mut beat_tails = vec![false; key_count * 2];
         let mut check_beat = |beat, start_idx: usize, end_idx: usize| -> Result<()> {
             for n in beat_notes.iter_mut() {
                 *n = false;
@@ -236,24 +364,25 @@ impl Simfile {
             }
             for idx in start_idx..end_idx {
                 let key = self.notes[idx].key as usize;
+                let slot = self.notes[idx].player as usize * key_count + key;
                 if self.notes[idx].is_tail() {
                     ensure!(
-                        !beat_tails[key],
+                        !beat_tails[slot],
                         "two tails on beat {}, key {} (beat {:?})",
                         beat,
                         key,
                         &self.notes[start_idx..end_idx]
                     );
-                    beat_tails[key] = true;
+                    beat_tails[slot] = true;
                 } else {
                     ensure!(
-                        !beat_notes[key],
+                        !beat_notes[slot],
                         "two hit/head notes on beat {}, key {} (beat {:?})",
                         beat,
                         key,
                         &self.notes[start_idx..end_idx]
                     );
-                    beat_notes[key] = true;
+                    beat_notes[slot] = true;
                 }
             }
             Ok(())
@@ -270,11 +399,16 @@ impl Simfile {
                 last_beat
             );
             ensure!(
-                note.is_hit() || note.is_head() || note.is_tail(),
+                note.is_step() || note.is_tail() || note.is_mine() || note.is_fake(),
                 "unknown note kind '{}'",
                 note.kind
             );
             ensure!(note.key >= 0, "note key ({}) is negative", note.key);
+            ensure!(
+                note.player < 2,
+                "note player ({}) is not 0 or 1",
+                note.player
+            );
             ensure!(
                 note.key < key_count as i32,
                 "note key is not less than key-count-for-gamemode-{:?}: {} >= {}",
@@ -294,7 +428,7 @@ impl Simfile {
                 let mut found = false;
                 for j in idx + 1..self.notes.len() {
                     let next_note = &self.notes[j];
-                    if next_note.key == note.key {
+                    if next_note.key == note.key && next_note.player == note.player {
                         ensure!(next_note.is_tail(), "hold head at beat {}, key {} is followed by non-tail (kind '{}') at beat {}", note.beat, note.key, next_note.kind, next_note.beat);
                         ensure!(
                             next_note.beat != note.beat,
@@ -318,7 +452,7 @@ impl Simfile {
                 let mut found = false;
                 for j in (0..idx).rev() {
                     let prev_note = &self.notes[j];
-                    if prev_note.key == note.key {
+                    if prev_note.key == note.key && prev_note.player == note.player {
                         ensure!(prev_note.is_head(), "hold tail at beat {}, key {} is preceded by non-head (kind '{}') at beat {}", note.beat, note.key, prev_note.kind, prev_note.beat);
                         found = true;
                         break;
@@ -339,12 +473,198 @@ impl Simfile {
     }
 }
 
+fn as_utf8<'a>(path: &'a Option<PathBuf>, name: &str) -> Result<&'a str> {
+    path.as_deref()
+        .unwrap_or_else(|| "".as_ref())
+        .to_str()
+        .ok_or_else(|| anyhow!("non-utf8 {}", name))
+}
+
+/// All charts in a file share a single keysound list, so merge the keysounds of all simfiles and
+/// map the indices of each simfile into the merged list.
+fn merge_keysounds<'a>(simfiles: &[&'a Simfile]) -> (Vec<&'a Path>, Vec<Vec<u32>>) {
+    let mut keysounds: Vec<&Path> = Vec::new();
+    let keysound_maps = simfiles
+        .iter()
+        .map(|sm| {
+            sm.keysounds
+                .iter()
+                .map(
+                    |ks| match keysounds.iter().position(|&merged| merged == ks) {
+                        Some(idx) => idx as u32,
+                        None => {
+                            keysounds.push(ks);
+                            keysounds.len() as u32 - 1
+                        }
+                    },
+                )
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    (keysounds, keysound_maps)
+}
+
+/// A comment recording which file a chart was converted from, or an empty string if unknown.
+fn fmt_source_comment(sm: &Simfile) -> String {
+    let meta = &sm.source_meta;
+    let path = match &meta.path {
+        Some(path) => path,
+        None => return String::new(),
+    };
+    let file_name = path
+        .file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy();
+    let mut comment = format!("\n// Converted from \"{}\"", file_name);
+    if let Some(id) = meta.beatmap_id {
+        comment += &match meta.set_id {
+            Some(set_id) => format!(" (beatmap {}, set {})", id, set_id),
+            None => format!(" (beatmap {})", id),
+        };
+    }
+    comment
+}
+
+/// Write the song metadata tags shared by the `.sm` and `.ssc` formats.
+fn write_song_header(file: &mut impl Write, main_sm: &Simfile, keysounds: &[&Path]) -> Result<()> {
+    write!(
+        file,
+        r#"#TITLE:{title};
+#SUBTITLE:{subtitle};
+#ARTIST:{artist};
+#TITLETRANSLIT:{title_t};
+#SUBTITLETRANSLIT:{subtitle_t};
+#ARTISTTRANSLIT:{artist_t};
+#GENRE:{genre};
+#CREDIT:{credit};
+#BANNER:{banner};
+#BACKGROUND:{bg};
+#LYRICSPATH:{lyrics};
+#CDTITLE:{cdtitle};
+#MUSIC:{music};
+#SAMPLESTART:{sample_start};
+#SAMPLELENGTH:{sample_len};
+#DISPLAYBPM:{display_bpm};
+#SELECTABLE:YES;
+#KEYSOUNDS:{keysounds};
+"#,
+        title = main_sm.title,
+        subtitle = main_sm.subtitle,
+        artist = main_sm.artist,
+        title_t = main_sm.title_trans,
+        subtitle_t = main_sm.subtitle_trans,
+        artist_t = main_sm.artist_trans,
+        genre = main_sm.genre,
+        credit = main_sm.credit,
+        banner = as_utf8(&main_sm.banner, "BANNER")?,
+        bg = as_utf8(&main_sm.background, "BACKGROUND")?,
+        lyrics = as_utf8(&main_sm.lyrics, "LYRICSPATH")?,
+        cdtitle = as_utf8(&main_sm.cdtitle, "CDTITLE")?,
+        music = as_utf8(&main_sm.music, "MUSIC")?,
+        sample_start = main_sm
+            .sample_start
+            .map(|s| format!("{}", s))
+            .unwrap_or_default(),
+        sample_len = main_sm
+            .sample_len
+            .map(|l| format!("{}", l))
+            .unwrap_or_default(),
+        display_bpm = main_sm.display_bpm.to_string(),
+        keysounds = {
+            let mut names = String::new();
+            for (idx, ks) in keysounds.iter().enumerate() {
+                if idx > 0 {
+                    names.push(',');
+                }
+                names.push_str(ks.to_str().ok_or_else(|| anyhow!("non-utf8 KEYSOUNDS"))?);
+            }
+            names
+        },
+    )?;
+    Ok(())
+}
+
+/// Write the timing tags of an `.ssc` file, either for the whole song or for a single chart.
+fn write_ssc_timing(file: &mut impl Write, sm: &Simfile) -> Result<()> {
+    let join = |items: &mut dyn Iterator<Item = String>| items.collect::<Vec<_>>().join(",");
+    write!(
+        file,
+        r#"#OFFSET:{offset};
+#BPMS:{bpms};
+#STOPS:{stops};
+#DELAYS:{delays};
+#WARPS:{warps};
+#TIMESIGNATURES:{time_signatures};
+#SCROLLS:{scrolls};
+"#,
+        offset = sm.offset,
+        bpms = fmt_bpms(&sm.bpms),
+        stops = join(
+            &mut sm
+                .stops
+                .iter()
+                .map(|(beat, secs)| format!("{}={}", beat, secs))
+        ),
+        delays = join(
+            &mut sm
+                .delays
+                .iter()
+                .map(|(beat, secs)| format!("{}={}", beat, secs))
+        ),
+        warps = join(
+            &mut sm
+                .warps
+                .iter()
+                .map(|(beat, len)| format!("{}={}", beat, len))
+        ),
+        time_signatures = join(
+            &mut sm
+                .time_signatures
+                .iter()
+                .map(|(beat, num, den)| format!("{}={}={}", beat, num, den))
+        ),
+        scrolls = join(
+            &mut sm
+                .scrolls
+                .iter()
+                .map(|(beat, ratio)| format!("{}={}", beat, ratio))
+        ),
+    )?;
+    Ok(())
+}
+
+fn fmt_bpms(bpms: &[ControlPoint]) -> String {
+    let mut out = String::new();
+    let mut first = true;
+    for point in bpms.iter() {
+        if first {
+            first = false;
+        } else {
+            out.push(',');
+        }
+        write!(out, "{}={}", point.beat.as_num(), point.bpm()).unwrap();
+    }
+    out
+}
+
+fn fmt_sm_stops(sm: &Simfile) -> String {
+    let mut out = String::new();
+    for (idx, (beat, secs)) in sm.folded_stops().iter().enumerate() {
+        if idx > 0 {
+            out.push(',');
+        }
+        write!(out, "{}={}", beat, secs).unwrap();
+    }
+    out
+}
+
 fn write_measure(
     file: &mut impl Write,
     key_count: i32,
     measure_idx: usize,
     measure_start: BeatPos,
     notes: &[Note],
+    keysound_map: &[u32],
 ) -> Result<()> {
     //Extract largest simplified denominator, in prime-factorized form.
     //To obtain the actual number from prime-factorized form, use 2^pf[0] * 3^pf[1]
@@ -383,6 +703,7 @@ fn write_measure(
     //Output 4x this amount of rows (if 4 beats in measure)
     let mut out_measure =
         vec![b'0'; (BEATS_IN_MEASURE * rows_per_beat) as usize * key_count as usize];
+    let mut out_keysounds = vec![None; out_measure.len()];
     for note in notes {
         let rel_pos = note.beat - measure_start;
         let idx = (rel_pos.frac / simplify_by) as usize;
@@ -405,7 +726,15 @@ fn write_measure(
             note.key,
             key_count
         );
-        out_measure[idx * key_count as usize + note.key as usize] = note.kind as u8;
+        let cell = idx * key_count as usize + note.key as usize;
+        out_measure[cell] = note.kind as u8;
+        if let Some(keysound) = note.keysound {
+            out_keysounds[cell] = Some(
+                *keysound_map
+                    .get(keysound as usize)
+                    .ok_or_else(|| anyhow!("note references unknown keysound {}", keysound))?,
+            );
+        }
     }
     //Convert map into a string
     if measure_idx > 0 {
@@ -416,25 +745,56 @@ fn write_measure(
     for row in 0..(BEATS_IN_MEASURE * rows_per_beat) as usize {
         write!(file, "\n")?;
         for key in 0..key_count as usize {
-            file.write_all(&[out_measure[row * key_count as usize + key]])?;
+            let cell = row * key_count as usize + key;
+            file.write_all(&[out_measure[cell]])?;
+            if let Some(keysound) = out_keysounds[cell] {
+                write!(file, "[{}]", keysound)?;
+            }
         }
     }
     Ok(())
 }
 
-fn write_notedata(file: &mut impl Write, sm: &Simfile) -> Result<()> {
+/// Write the note data of a simfile.
+/// Routine charts have one section of note data per player, separated by `&`.
+fn write_notedata(file: &mut impl Write, sm: &Simfile, keysound_map: &[u32]) -> Result<()> {
+    let key_count = sm.gamemode.key_count();
+    if sm.gamemode.is_routine() {
+        for player in 0..2 {
+            if player > 0 {
+                write!(file, "\n&")?;
+            }
+            let notes = sm
+                .notes
+                .iter()
+                .filter(|note| note.player == player)
+                .cloned()
+                .collect::<Vec<_>>();
+            write_notedata_section(file, key_count, &notes, keysound_map)?;
+        }
+        Ok(())
+    } else {
+        write_notedata_section(file, key_count, &sm.notes, keysound_map)
+    }
+}
+
+fn write_notedata_section(
+    file: &mut impl Write,
+    key_count: i32,
+    notes: &[Note],
+    keysound_map: &[u32],
+) -> Result<()> {
     struct CurMeasure {
         first_note: usize,
         start_beat: BeatPos,
     }
 
-    let key_count = sm.gamemode.key_count();
     let mut measure_counter = 0;
     let mut cur_measure = CurMeasure {
         first_note: 0,
         start_beat: BeatPos::from(0.),
     };
-    for (note_idx, note) in sm.notes.iter().enumerate() {
+    for (note_idx, note) in notes.iter().enumerate() {
         //Finish any pending measures
         while (note.beat - cur_measure.start_beat) >= BeatPos::from(BEATS_IN_MEASURE as f64) {
             write_measure(
@@ -442,7 +802,8 @@ fn write_notedata(file: &mut impl Write, sm: &Simfile) -> Result<()> {
                 key_count,
                 measure_counter,
                 cur_measure.start_beat,
-                &sm.notes[cur_measure.first_note..note_idx],
+                &notes[cur_measure.first_note..note_idx],
+                keysound_map,
             )?;
             measure_counter += 1;
             cur_measure.first_note = note_idx;
@@ -456,7 +817,8 @@ fn write_notedata(file: &mut impl Write, sm: &Simfile) -> Result<()> {
         key_count,
         measure_counter,
         cur_measure.start_beat,
-        &sm.notes[cur_measure.first_note..sm.notes.len()],
+        &notes[cur_measure.first_note..notes.len()],
+        keysound_map,
     )?;
     Ok(())
 }
@@ -506,10 +868,11 @@ pub struct Beat {
     pub end_idx: usize,
 }
 impl Beat {
+    /// Count the notes that the player has to hit on this beat.
     pub fn count_heads(&self, notes: &[Note]) -> usize {
         notes[self.start_idx..self.end_idx]
             .iter()
-            .filter(|note| !note.is_tail())
+            .filter(|note| note.is_step())
             .count()
     }
 }
@@ -698,6 +1061,62 @@ impl Gamemode {
             KickboxArachnid => "kickbox-arachnid",
         }
     }
+
+    /// Get a gamemode from its StepMania id, such as `dance-single`.
+    pub fn from_id(id: &str) -> Option<Gamemode> {
+        use Gamemode::*;
+        Some(match id {
+            "dance-single" => DanceSingle,
+            "dance-double" => DanceDouble,
+            "dance-couple" => DanceCouple,
+            "dance-solo" => DanceSolo,
+            "dance-threepanel" => DanceThreepanel,
+            "dance-routine" => DanceRoutine,
+            "pump-single" => PumpSingle,
+            "pump-halfdouble" => PumpHalfdouble,
+            "pump-double" => PumpDouble,
+            "pump-couple" => PumpCouple,
+            "pump-routine" => PumpRoutine,
+            "kb7-single" => Kb7Single,
+            "ez2-single" => Ez2Single,
+            "ez2-double" => Ez2Double,
+            "ez2-real" => Ez2Real,
+            "para-single" => ParaSingle,
+            "ds3ddx-single" => Ds3ddxSingle,
+            "bm-single5" => BmSingle5,
+            "bm-versus5" => BmVersus5,
+            "bm-double5" => BmDouble5,
+            "bm-single7" => BmSingle7,
+            "bm-versus7" => BmVersus7,
+            "bm-double7" => BmDouble7,
+            "maniax-single" => ManiaxSingle,
+            "maniax-double" => ManiaxDouble,
+            "techno-single4" => TechnoSingle4,
+            "techno-single5" => TechnoSingle5,
+            "techno-single8" => TechnoSingle8,
+            "techno-double4" => TechnoDouble4,
+            "techno-double5" => TechnoDouble5,
+            "techno-double8" => TechnoDouble8,
+            "pnm-five" => PnmFive,
+            "pnm-nine" => PnmNine,
+            "kickbox-human" => KickboxHuman,
+            "kickbox-quadarm" => KickboxQuadarm,
+            "kickbox-insect" => KickboxInsect,
+            "kickbox-arachnid" => KickboxArachnid,
+            _ => return None,
+        })
+    }
+
+    /// Whether this gamemode has two players side by side, each on their half of the keys.
+    pub fn is_couple(&self) -> bool {
+        matches!(self, Gamemode::DanceCouple | Gamemode::PumpCouple)
+    }
+
+    /// Whether this gamemode has two players sharing all of the keys, with separate note data for
+    /// each player.
+    pub fn is_routine(&self) -> bool {
+        matches!(self, Gamemode::DanceRoutine | Gamemode::PumpRoutine)
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -710,7 +1129,7 @@ pub enum Difficulty {
     Edit,
 }
 impl Difficulty {
-    fn name(&self) -> &'static str {
+    pub fn name(&self) -> &'static str {
         use Difficulty::*;
         match self {
             Beginner => "Beginner",
@@ -721,6 +1140,109 @@ impl Difficulty {
             Edit => "Edit",
         }
     }
+
+    /// Parse a difficulty name, including the legacy names used by old simfiles.
+    pub fn from_name(name: &str) -> Option<Difficulty> {
+        use Difficulty::*;
+        Some(match &name.to_ascii_lowercase()[..] {
+            "beginner" => Beginner,
+            "easy" | "basic" | "light" => Easy,
+            "medium" | "another" | "trick" | "standard" | "difficult" => Medium,
+            "hard" | "ssr" | "maniac" | "heavy" => Hard,
+            "challenge" | "smaniac" | "expert" | "oni" => Challenge,
+            "edit" => Edit,
+            _ => return None,
+        })
+    }
+}
+
+/// The kinds of patterns that charts are rated on.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Skillset {
+    /// The hardest of all other skillsets.
+    Overall,
+    /// Single notes.
+    Stream,
+    /// Jumps mixed in with streams.
+    Jumpstream,
+    /// Hands and quads mixed in with streams.
+    Handstream,
+    /// Single notes and jumps repeated on the same keys.
+    Jack,
+    /// Hands and quads repeated on the same keys.
+    Chordjack,
+    /// Changes in rhythm.
+    Technical,
+}
+
+/// A rating for each skillset, in notes per second of each pattern.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Skillsets {
+    pub overall: f64,
+    pub stream: f64,
+    pub jumpstream: f64,
+    pub handstream: f64,
+    pub jack: f64,
+    pub chordjack: f64,
+    pub technical: f64,
+}
+impl Skillsets {
+    pub fn get(&self, skillset: Skillset) -> f64 {
+        use Skillset::*;
+        match skillset {
+            Overall => self.overall,
+            Stream => self.stream,
+            Jumpstream => self.jumpstream,
+            Handstream => self.handstream,
+            Jack => self.jack,
+            Chordjack => self.chordjack,
+            Technical => self.technical,
+        }
+    }
+}
+
+/// Information about the chart that a simfile was converted from.
+/// Fields are empty or `None` if the source format does not have them.
+#[derive(Clone, Debug, Default)]
+pub struct SourceMeta {
+    /// The path to the source chart file, such as an `.osu` file.
+    pub path: Option<PathBuf>,
+    pub beatmap_id: Option<i64>,
+    pub set_id: Option<i64>,
+    /// The name of the difficulty.
+    pub version: String,
+    /// The creator of the beatmapset.
+    pub creator: String,
+    /// The guest mapper of this difficulty, if its name looks like "Someone's Insane".
+    pub guest: Option<String>,
+    /// Where the song comes from (eg. a game or an anime).
+    pub source: String,
+    pub tags: String,
+    pub hp_drain: Option<f64>,
+    pub circle_size: Option<f64>,
+    pub overall_difficulty: Option<f64>,
+    /// The osu!mania star rating cached in `osu!.db`, if the beatmap was listed there.
+    /// Use `rate::source_stars` to fall back to an approximation.
+    pub stars: Option<f64>,
+    /// The osu! gamemode of the source beatmap, for charts converted from osu! beatmaps.
+    pub osu_mode: Option<i32>,
+}
+impl SourceMeta {
+    /// Guess the guest mapper from a difficulty name like "Someone's Insane".
+    pub fn guest_from_version(version: &str, creator: &str) -> Option<String> {
+        let idx = version.find("'s ")?;
+        let guest = &version[..idx];
+        if guest.is_empty() || guest.eq_ignore_ascii_case(creator) {
+            None
+        } else {
+            Some(guest.to_string())
+        }
+    }
+
+    /// Who mapped this difficulty, either the guest mapper or the creator.
+    pub fn mapper(&self) -> &str {
+        self.guest.as_deref().unwrap_or(&self.creator)
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -854,23 +1376,59 @@ pub struct Note {
     pub kind: char,
     pub beat: BeatPos,
     pub key: i32,
+    /// An index into `Simfile::keysounds`, if this note plays a sound when hit.
+    pub keysound: Option<u32>,
+    /// Which player should hit this note, either `0` or `1`.
+    /// Only meaningful in routine gamemodes, where each player has their own note data.
+    pub player: u8,
 }
 impl Note {
     pub const KIND_HIT: char = '1';
     pub const KIND_HEAD: char = '2';
     pub const KIND_TAIL: char = '3';
+    /// The head of a roll, a hold that has to be tapped repeatedly instead of held.
+    /// Ends with a regular `KIND_TAIL`.
+    pub const KIND_ROLL: char = '4';
+    /// A note that must be avoided instead of hit.
+    pub const KIND_MINE: char = 'M';
+    /// A note that is hit by releasing the key instead of pressing it.
+    pub const KIND_LIFT: char = 'L';
+    /// A note that is displayed, but not judged.
+    pub const KIND_FAKE: char = 'F';
 
     pub fn is_hit(&self) -> bool {
         self.kind == Self::KIND_HIT
     }
 
+    /// Whether this note is the head of a hold or a roll, which is always followed by a tail.
     pub fn is_head(&self) -> bool {
-        self.kind == Self::KIND_HEAD
+        self.kind == Self::KIND_HEAD || self.kind == Self::KIND_ROLL
     }
 
     pub fn is_tail(&self) -> bool {
         self.kind == Self::KIND_TAIL
     }
+
+    pub fn is_roll(&self) -> bool {
+        self.kind == Self::KIND_ROLL
+    }
+
+    pub fn is_mine(&self) -> bool {
+        self.kind == Self::KIND_MINE
+    }
+
+    pub fn is_lift(&self) -> bool {
+        self.kind == Self::KIND_LIFT
+    }
+
+    pub fn is_fake(&self) -> bool {
+        self.kind == Self::KIND_FAKE
+    }
+
+    /// Whether the player has to hit this note: hits, lifts and hold or roll heads.
+    pub fn is_step(&self) -> bool {
+        self.is_hit() || self.is_lift() || self.is_head()
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -886,32 +1444,110 @@ impl ControlPoint {
     }
 }
 
+/// Converts beats into times, taking into account bpm changes, stops, delays and warps.
 #[derive(Debug, Clone)]
 pub struct ToTime<'a> {
-    bpms: &'a [ControlPoint],
-    cur_idx: usize,
-    cur_time: f64,
+    clock: BpmClock<'a>,
+    warp_clock: BpmClock<'a>,
+    stops: &'a [(f64, f64)],
+    delays: &'a [(f64, f64)],
+    warps: &'a [(f64, f64)],
+    /// Time added by the stops and delays that have already passed, minus the time skipped by
+    /// the warps that have already passed.
+    extra_time: f64,
 }
 impl ToTime<'_> {
     pub fn new(sm: &Simfile) -> ToTime {
         ToTime {
-            bpms: &sm.bpms,
-            cur_idx: 0,
-            cur_time: -sm.offset,
+            clock: BpmClock::new(&sm.bpms, sm.offset),
+            warp_clock: BpmClock::new(&sm.bpms, sm.offset),
+            stops: &sm.stops,
+            delays: &sm.delays,
+            warps: &sm.warps,
+            extra_time: 0.,
         }
     }
 
+    /// Only takes bpm changes into account.
     pub fn from_raw(bpms: &[ControlPoint], offset: f64) -> ToTime {
         ToTime {
-            bpms,
-            cur_idx: 0,
-            cur_time: -offset,
+            clock: BpmClock::new(bpms, offset),
+            warp_clock: BpmClock::new(bpms, offset),
+            stops: &[],
+            delays: &[],
+            warps: &[],
+            extra_time: 0.,
         }
     }
 
     /// Returns incorrect results if called with non-monotonic beat positions.
     /// If needing to seek back in time, create a new `ToTime` or make "checkpoints" with `Clone`.
     pub fn beat_to_time(&mut self, beat: BeatPos) -> f64 {
+        //Notes on the beat of a stop are hit before the stop
+        while let Some(&(stop_beat, secs)) = self.stops.first() {
+            if BeatPos::from(stop_beat) < beat {
+                self.extra_time += secs;
+                self.stops = &self.stops[1..];
+            } else {
+                break;
+            }
+        }
+        //Notes on the beat of a delay are hit after the delay
+        while let Some(&(delay_beat, secs)) = self.delays.first() {
+            if BeatPos::from(delay_beat) <= beat {
+                self.extra_time += secs;
+                self.delays = &self.delays[1..];
+            } else {
+                break;
+            }
+        }
+        //Skip over warps
+        while let Some(&(warp_beat, len)) = self.warps.first() {
+            let start = BeatPos::from(warp_beat);
+            let end = BeatPos::from(warp_beat + len);
+            if beat >= end {
+                let start_time = self.warp_clock.beat_to_time(start);
+                self.extra_time -= self.warp_clock.beat_to_time(end) - start_time;
+                self.warps = &self.warps[1..];
+            } else if beat > start {
+                //Within a warp, time stands still
+                return self.warp_clock.beat_to_time(start) + self.extra_time;
+            } else {
+                break;
+            }
+        }
+        self.clock.beat_to_time(beat) + self.extra_time
+    }
+
+    /// Like `beat_to_time`, but if there is a stop on this beat, get the time at which it ends.
+    pub fn beat_to_time_after(&mut self, beat: BeatPos) -> f64 {
+        let time = self.beat_to_time(beat);
+        time + self
+            .stops
+            .iter()
+            .take_while(|&&(stop_beat, _)| BeatPos::from(stop_beat) == beat)
+            .map(|&(_, secs)| secs)
+            .sum::<f64>()
+    }
+}
+
+/// Converts beats into times, taking into account bpm changes only.
+#[derive(Debug, Clone)]
+struct BpmClock<'a> {
+    bpms: &'a [ControlPoint],
+    cur_idx: usize,
+    cur_time: f64,
+}
+impl BpmClock<'_> {
+    fn new(bpms: &[ControlPoint], offset: f64) -> BpmClock<'_> {
+        BpmClock {
+            bpms,
+            cur_idx: 0,
+            cur_time: -offset,
+        }
+    }
+
+    fn beat_to_time(&mut self, beat: BeatPos) -> f64 {
         //Advance control points
         while self.cur_idx + 1 < self.bpms.len() {
             let cur_bpm = &self.bpms[self.cur_idx];
@@ -931,3 +1567,273 @@ impl ToTime<'_> {
         self.cur_time + (beat - cur_bpm.beat).as_num() * cur_bpm.beat_len
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(kind: char, beat: f64, key: i32) -> Note {
+        Note {
+            kind,
+            beat: BeatPos::from(beat),
+            key,
+            keysound: None,
+            player: 0,
+        }
+    }
+
+    /// Two charts of the same song, with different timing data and names.
+    fn make_charts() -> Vec<Simfile> {
+        let base = Simfile {
+            title: "Song".to_string(),
+            artist: "Artist".to_string(),
+            music: Some("audio.mp3".into()),
+            offset: -0.5,
+            ..default()
+        };
+        let easy = Simfile {
+            chart_name: "Easy One".to_string(),
+            desc: "Easy One".to_string(),
+            difficulty: Difficulty::Easy,
+            difficulty_num: 3.,
+            bpms: vec![ControlPoint {
+                beat: BeatPos::from(0.),
+                beat_len: 0.5,
+            }],
+            time_signatures: vec![(0., 4, 4)],
+            notes: vec![
+                note(Note::KIND_HIT, 0., 0),
+                note(Note::KIND_HEAD, 1., 1),
+                note(Note::KIND_TAIL, 2., 1),
+                note(Note::KIND_HIT, 3.5, 3),
+            ],
+            ..base.clone()
+        };
+        let hard = Simfile {
+            chart_name: "Hard One".to_string(),
+            desc: "Hard One".to_string(),
+            difficulty: Difficulty::Hard,
+            difficulty_num: 9.,
+            bpms: vec![
+                ControlPoint {
+                    beat: BeatPos::from(0.),
+                    beat_len: 0.5,
+                },
+                ControlPoint {
+                    beat: BeatPos::from(4.),
+                    beat_len: 0.25,
+                },
+            ],
+            time_signatures: vec![(0., 4, 4), (4., 3, 4)],
+            scrolls: vec![(2., 0.5), (4., 1.)],
+            notes: vec![
+                note(Note::KIND_HIT, 0., 0),
+                note(Note::KIND_HIT, 0., 2),
+                note(Note::KIND_HIT, 0.25, 1),
+                note(Note::KIND_HIT, 4.75, 3),
+            ],
+            ..base
+        };
+        vec![easy, hard]
+    }
+
+    /// A 120 bpm chart with the given stops, delays and warps.
+    fn timed(stops: Vec<(f64, f64)>, delays: Vec<(f64, f64)>, warps: Vec<(f64, f64)>) -> Simfile {
+        Simfile {
+            bpms: vec![ControlPoint {
+                beat: BeatPos::from(0.),
+                beat_len: 0.5,
+            }],
+            stops,
+            delays,
+            warps,
+            ..default()
+        }
+    }
+
+    #[test]
+    fn stop_is_hit_before() {
+        let sm = timed(vec![(2., 1.)], vec![], vec![]);
+        let mut to_time = sm.beat_to_time();
+        assert_eq!(to_time.beat_to_time(BeatPos::from(1.)), 0.5);
+        assert_eq!(to_time.beat_to_time(BeatPos::from(2.)), 1.);
+        assert_eq!(to_time.beat_to_time(BeatPos::from(3.)), 2.5);
+        let mut to_time = sm.beat_to_time();
+        assert_eq!(to_time.beat_to_time_after(BeatPos::from(2.)), 2.);
+        assert_eq!(to_time.beat_to_time_after(BeatPos::from(3.)), 2.5);
+    }
+
+    #[test]
+    fn delay_is_hit_after() {
+        let sm = timed(vec![], vec![(2., 1.)], vec![]);
+        let mut to_time = sm.beat_to_time();
+        assert_eq!(to_time.beat_to_time(BeatPos::from(1.)), 0.5);
+        assert_eq!(to_time.beat_to_time(BeatPos::from(2.)), 2.);
+        assert_eq!(to_time.beat_to_time(BeatPos::from(3.)), 2.5);
+    }
+
+    #[test]
+    fn warp_skips_time() {
+        let sm = timed(vec![], vec![], vec![(2., 2.)]);
+        let mut to_time = sm.beat_to_time();
+        assert_eq!(to_time.beat_to_time(BeatPos::from(1.)), 0.5);
+        assert_eq!(to_time.beat_to_time(BeatPos::from(3.)), 1.);
+        assert_eq!(to_time.beat_to_time(BeatPos::from(4.)), 1.);
+        assert_eq!(to_time.beat_to_time(BeatPos::from(5.)), 1.5);
+    }
+
+    fn write_and_load(
+        name: &str,
+        save: impl FnOnce(&Path) -> Result<()>,
+    ) -> (String, Vec<Simfile>) {
+        let path =
+            std::env::temp_dir().join(format!("osu2sm-test-{}-{}", std::process::id(), name));
+        save(&path).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        let loaded = crate::smfile::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        (text, loaded)
+    }
+
+    fn assert_same_notes(a: &Simfile, b: &Simfile) {
+        let simplify = |sm: &Simfile| {
+            sm.notes
+                .iter()
+                .map(|note| (note.kind, note.beat, note.key))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(simplify(a), simplify(b));
+    }
+
+    #[test]
+    fn ssc_keeps_per_chart_data() {
+        let charts = make_charts();
+        let (text, loaded) =
+            write_and_load("charts.ssc", |path| Simfile::save_ssc(path, charts.iter()));
+        assert!(text.contains("#CHARTNAME:Easy One;"));
+        assert!(text.contains("#CHARTNAME:Hard One;"));
+        assert!(text.contains("#BPMS:0=120;"));
+        assert!(text.contains("#BPMS:0=120,4=240;"));
+        assert!(text.contains("#TIMESIGNATURES:0=4=4,4=3=4;"));
+        assert!(text.contains("#SCROLLS:2=0.5,4=1;"));
+        assert_eq!(loaded.len(), 2);
+        for (orig, loaded) in charts.iter().zip(loaded.iter()) {
+            assert_eq!(orig.chart_name, loaded.chart_name);
+            assert_eq!(orig.difficulty, loaded.difficulty);
+            assert_eq!(orig.bpms, loaded.bpms);
+            assert_eq!(orig.offset, loaded.offset);
+            assert_eq!(orig.time_signatures, loaded.time_signatures);
+            assert_eq!(orig.scrolls, loaded.scrolls);
+            assert_same_notes(orig, loaded);
+        }
+    }
+
+    #[test]
+    fn sm_shares_first_chart_timing() {
+        let charts = make_charts();
+        let (text, loaded) = write_and_load("charts.sm", |path| Simfile::save(path, charts.iter()));
+        assert_eq!(text.matches("#BPMS:").count(), 1);
+        assert!(text.contains("#BPMS:0=120;"));
+        assert!(!text.contains("#CHARTNAME"));
+        assert!(!text.contains("#TIMESIGNATURES"));
+        assert!(!text.contains("#SCROLLS"));
+        assert_eq!(loaded.len(), 2);
+        for (orig, loaded) in charts.iter().zip(loaded.iter()) {
+            assert_eq!(orig.desc, loaded.desc);
+            assert_eq!(orig.difficulty, loaded.difficulty);
+            assert_eq!(loaded.bpms, charts[0].bpms);
+            assert_same_notes(orig, loaded);
+        }
+    }
+
+    /// `make_charts`, plus keysounds, a stop, a delay and a routine chart with two players.
+    fn make_full_charts() -> Vec<Simfile> {
+        let mut charts = make_charts();
+        charts[0].keysounds = vec!["kick.wav".into(), "snare.wav".into()];
+        charts[0].notes[0].keysound = Some(1);
+        charts[0].notes[3].keysound = Some(0);
+        charts[1].stops = vec![(2., 0.25)];
+        charts[1].delays = vec![(3., 0.5)];
+        let mut routine = Simfile {
+            gamemode: Gamemode::DanceRoutine,
+            chart_name: "Both Players".to_string(),
+            desc: "Both Players".to_string(),
+            difficulty: Difficulty::Medium,
+            notes: vec![
+                note(Note::KIND_HIT, 0., 0),
+                note(Note::KIND_HIT, 0., 7),
+                note(Note::KIND_HEAD, 1., 4),
+                note(Note::KIND_HIT, 1.5, 2),
+                note(Note::KIND_TAIL, 2., 4),
+            ],
+            ..charts[0].clone()
+        };
+        routine.notes[0].keysound = Some(0);
+        routine.notes[1].player = 1;
+        routine.notes[2].player = 1;
+        routine.notes[4].player = 1;
+        charts.push(routine);
+        charts
+    }
+
+    /// Save the charts, add inline attacks to every keysounded note, and load them back.
+    fn roundtrip(name: &str, save: impl FnOnce(&Path) -> Result<()>) -> Vec<Simfile> {
+        let path =
+            std::env::temp_dir().join(format!("osu2sm-test-{}-{}", std::process::id(), name));
+        save(&path).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.contains("&"));
+        fs::write(&path, text.replace("1[", "1{drunk,50% tipsy:1.5}[")).unwrap();
+        let loaded = crate::smfile::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        loaded
+    }
+
+    fn assert_same_full_notes(a: &Simfile, b: &Simfile) {
+        let simplify = |sm: &Simfile| {
+            sm.notes
+                .iter()
+                .map(|note| {
+                    (
+                        note.kind,
+                        note.beat,
+                        note.key,
+                        note.player,
+                        note.keysound.map(|idx| sm.keysounds[idx as usize].clone()),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(simplify(a), simplify(b));
+    }
+
+    #[test]
+    fn ssc_roundtrip() {
+        let charts = make_full_charts();
+        let loaded = roundtrip("full.ssc", |path| Simfile::save_ssc(path, charts.iter()));
+        assert_eq!(loaded.len(), charts.len());
+        for (orig, loaded) in charts.iter().zip(loaded.iter()) {
+            assert_eq!(orig.gamemode, loaded.gamemode);
+            assert_eq!(orig.chart_name, loaded.chart_name);
+            assert_eq!(orig.bpms, loaded.bpms);
+            assert_eq!(orig.stops, loaded.stops);
+            assert_eq!(orig.delays, loaded.delays);
+            assert_eq!(orig.offset, loaded.offset);
+            assert_same_full_notes(orig, loaded);
+        }
+    }
+
+    #[test]
+    fn sm_roundtrip() {
+        let charts = make_full_charts();
+        let loaded = roundtrip("full.sm", |path| Simfile::save(path, charts.iter()));
+        assert_eq!(loaded.len(), charts.len());
+        for (orig, loaded) in charts.iter().zip(loaded.iter()) {
+            assert_eq!(orig.gamemode, loaded.gamemode);
+            assert_eq!(orig.desc, loaded.desc);
+            assert_eq!(loaded.bpms, charts[0].bpms);
+            assert_eq!(loaded.stops, charts[0].stops);
+            assert_same_full_notes(orig, loaded);
+        }
+    }
+}
@@ -0,0 +1,426 @@
+//! Parse existing StepMania `.sm` and `.ssc` simfiles.
+
+use crate::prelude::*;
+
+/// Parse all charts in an `.sm` or `.ssc` file, one `Simfile` per chart.
+///
+/// Charts in unknown gamemodes or with invalid note data are skipped with a warning.
+pub fn load(path: &Path) -> Result<Vec<Simfile>> {
+    let mut raw = Vec::new();
+    File::open(path)
+        .context("open file")?
+        .read_to_end(&mut raw)
+        .context("read file")?;
+    let text = String::from_utf8_lossy(&raw);
+    let is_ssc = path
+        .extension()
+        .map(|ext| ext.eq_ignore_ascii_case("ssc"))
+        .unwrap_or(false);
+    let tags = parse_tags(&text);
+    //Song-wide data acts as the template for every chart
+    let mut song = Simfile::default();
+    let mut charts = Vec::new();
+    //In `.ssc` files, the chart currently being defined
+    let mut cur_chart: Option<Simfile> = None;
+    let load_chart = |sm: Simfile, notes: &str, charts: &mut Vec<Simfile>| {
+        let mut sm = sm;
+        match parse_notes(&mut sm, notes) {
+            Ok(()) => charts.push(sm),
+            Err(err) => warn!(
+                "  skipping {} chart \"{}\" in \"{}\": {:#}",
+                sm.gamemode.id(),
+                sm.desc,
+                path.display(),
+                err
+            ),
+        }
+    };
+    for (name, value) in tags {
+        let name = name.to_ascii_uppercase();
+        if is_ssc && name == "NOTEDATA" {
+            cur_chart = Some(song.clone());
+            continue;
+        }
+        let result = (|| -> Result<()> {
+            match cur_chart.as_mut() {
+                Some(chart) => match &name[..] {
+                    "NOTES" | "NOTES2" => {
+                        load_chart(cur_chart.take().unwrap(), &value, &mut charts);
+                    }
+                    _ => parse_chart_tag(chart, &name, &value)?,
+                },
+                None => match &name[..] {
+                    "NOTES" | "NOTES2" if !is_ssc => {
+                        //`.sm` charts carry their metadata inline, separated by colons
+                        let mut comps = value.splitn(6, ':');
+                        let mut next = || comps.next().unwrap_or_default().trim();
+                        let mut chart = song.clone();
+                        parse_chart_tag(&mut chart, "STEPSTYPE", next())?;
+                        parse_chart_tag(&mut chart, "DESCRIPTION", next())?;
+                        parse_chart_tag(&mut chart, "DIFFICULTY", next())?;
+                        parse_chart_tag(&mut chart, "METER", next())?;
+                        parse_chart_tag(&mut chart, "RADARVALUES", next())?;
+                        load_chart(chart, next(), &mut charts);
+                    }
+                    _ => parse_song_tag(&mut song, &name, &value)?,
+                },
+            }
+            Ok(())
+        })();
+        if let Err(err) = result {
+            warn!(
+                "  invalid #{} tag in \"{}\": {:#}",
+                name,
+                path.display(),
+                err
+            );
+        }
+    }
+    Ok(charts)
+}
+
+/// Split the file into `#NAME:VALUE;` tags, removing comments.
+/// Tags missing their `;` terminator are closed at the next line starting with `#`, like
+/// StepMania does.
+fn parse_tags(text: &str) -> Vec<(&str, String)> {
+    let mut tags = Vec::new();
+    let mut cur: Option<(&str, String)> = None;
+    for line in text.lines() {
+        let line = match line.find("//") {
+            Some(idx) => &line[..idx],
+            None => line,
+        };
+        let mut rest = line;
+        if let Some((name, value)) = cur.as_mut() {
+            if rest.trim_start().starts_with('#') {
+                //Unterminated tag
+                tags.push((*name, mem::take(value)));
+                cur = None;
+            } else {
+                match rest.find(';') {
+                    Some(end) => {
+                        value.push_str(&rest[..end]);
+                        tags.push((*name, mem::take(value)));
+                        cur = None;
+                        rest = &rest[end + 1..];
+                    }
+                    None => {
+                        value.push_str(rest);
+                        value.push('\n');
+                        continue;
+                    }
+                }
+            }
+        }
+        //Start new tags
+        while let Some(start) = rest.find('#') {
+            rest = &rest[start + 1..];
+            let (name, after) = match rest.find(':') {
+                Some(colon) => (&rest[..colon], &rest[colon + 1..]),
+                None => (rest.trim_end_matches(';'), ""),
+            };
+            match after.find(';') {
+                Some(end) => {
+                    tags.push((name.trim(), after[..end].to_string()));
+                    rest = &after[end + 1..];
+                }
+                None => {
+                    let mut value = after.to_string();
+                    value.push('\n');
+                    cur = Some((name.trim(), value));
+                    break;
+                }
+            }
+        }
+    }
+    if let Some(tag) = cur {
+        tags.push(tag);
+    }
+    tags
+}
+
+fn parse_path(value: &str) -> Option<PathBuf> {
+    let value = value.trim();
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.into())
+    }
+}
+
+fn parse_num(value: &str) -> Result<f64> {
+    value
+        .trim()
+        .parse::<f64>()
+        .map_err(|_| anyhow!("invalid number \"{}\"", value.trim()))
+}
+
+/// Parse a comma-separated list of `=`-separated values, such as `#BPMS`.
+fn parse_list(value: &str) -> Result<Vec<Vec<f64>>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| item.split('=').map(parse_num).collect())
+        .collect()
+}
+
+fn parse_song_tag(sm: &mut Simfile, name: &str, value: &str) -> Result<()> {
+    let text = || value.trim().to_string();
+    match name {
+        "TITLE" => sm.title = text(),
+        "SUBTITLE" => sm.subtitle = text(),
+        "ARTIST" => sm.artist = text(),
+        "TITLETRANSLIT" => sm.title_trans = text(),
+        "SUBTITLETRANSLIT" => sm.subtitle_trans = text(),
+        "ARTISTTRANSLIT" => sm.artist_trans = text(),
+        "GENRE" => sm.genre = text(),
+        "BANNER" => sm.banner = parse_path(value),
+        "BACKGROUND" => sm.background = parse_path(value),
+        "LYRICSPATH" => sm.lyrics = parse_path(value),
+        "CDTITLE" => sm.cdtitle = parse_path(value),
+        "MUSIC" => sm.music = parse_path(value),
+        "SAMPLESTART" => sm.sample_start = Some(parse_num(value)?),
+        "SAMPLELENGTH" => sm.sample_len = Some(parse_num(value)?),
+        "KEYSOUNDS" => {
+            sm.keysounds = value
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(PathBuf::from)
+                .collect()
+        }
+        _ => parse_chart_tag(sm, name, value)?,
+    }
+    Ok(())
+}
+
+fn parse_chart_tag(sm: &mut Simfile, name: &str, value: &str) -> Result<()> {
+    match name {
+        "STEPSTYPE" => {
+            sm.gamemode = Gamemode::from_id(&value.trim().to_ascii_lowercase())
+                .ok_or_else(|| anyhow!("unknown gamemode \"{}\"", value.trim()))?
+        }
+        "CHARTNAME" => sm.chart_name = value.trim().to_string(),
+        "DESCRIPTION" => sm.desc = value.trim().to_string(),
+        "CREDIT" => sm.credit = value.trim().to_string(),
+        "DIFFICULTY" => {
+            sm.difficulty = Difficulty::from_name(value.trim())
+                .ok_or_else(|| anyhow!("unknown difficulty \"{}\"", value.trim()))?
+        }
+        "METER" => sm.difficulty_num = parse_num(value)?,
+        "RADARVALUES" => {
+            for (radar, value) in sm.radar.iter_mut().zip(value.split(',')) {
+                *radar = parse_num(value)?;
+            }
+        }
+        "OFFSET" => sm.offset = parse_num(value)?,
+        "BPMS" => {
+            sm.bpms = parse_list(value)?
+                .into_iter()
+                .map(|point| match point[..] {
+                    [beat, bpm] if bpm > 0. => Ok(ControlPoint {
+                        beat: BeatPos::from(beat),
+                        beat_len: 60. / bpm,
+                    }),
+                    _ => bail!("invalid bpm change {:?}", point),
+                })
+                .collect::<Result<_>>()?;
+            if let DisplayBpm::Random = sm.display_bpm {
+                sm.display_bpm = display_bpm_from(&sm.bpms);
+            }
+        }
+        "STOPS" | "FREEZES" => {
+            sm.stops = parse_list(value)?
+                .into_iter()
+                .map(|stop| match stop[..] {
+                    [beat, secs] => Ok((beat, secs)),
+                    _ => bail!("invalid stop {:?}", stop),
+                })
+                .collect::<Result<_>>()?
+        }
+        "DELAYS" => {
+            sm.delays = parse_list(value)?
+                .into_iter()
+                .map(|delay| match delay[..] {
+                    [beat, secs] => Ok((beat, secs)),
+                    _ => bail!("invalid delay {:?}", delay),
+                })
+                .collect::<Result<_>>()?
+        }
+        "WARPS" => {
+            sm.warps = parse_list(value)?
+                .into_iter()
+                .map(|warp| match warp[..] {
+                    [beat, len] => Ok((beat, len)),
+                    _ => bail!("invalid warp {:?}", warp),
+                })
+                .collect::<Result<_>>()?
+        }
+        "TIMESIGNATURES" => {
+            sm.time_signatures = parse_list(value)?
+                .into_iter()
+                .map(|sig| match sig[..] {
+                    [beat, num, den] => Ok((beat, num as i32, den as i32)),
+                    _ => bail!("invalid time signature {:?}", sig),
+                })
+                .collect::<Result<_>>()?
+        }
+        "SCROLLS" => {
+            sm.scrolls = parse_list(value)?
+                .into_iter()
+                .map(|scroll| match scroll[..] {
+                    [beat, ratio] => Ok((beat, ratio)),
+                    _ => bail!("invalid scroll segment {:?}", scroll),
+                })
+                .collect::<Result<_>>()?
+        }
+        "DISPLAYBPM" => {
+            let value = value.trim();
+            sm.display_bpm = if value == "*" {
+                DisplayBpm::Random
+            } else if value.is_empty() {
+                display_bpm_from(&sm.bpms)
+            } else {
+                match value.split_once(':') {
+                    Some((min, max)) => DisplayBpm::Range(parse_num(min)?, parse_num(max)?),
+                    None => DisplayBpm::Single(parse_num(value)?),
+                }
+            };
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Compute the displayed BPM from the actual BPMs, as StepMania does when no `#DISPLAYBPM` is
+/// given.
+pub fn display_bpm_from(bpms: &[ControlPoint]) -> DisplayBpm {
+    let min = bpms
+        .iter()
+        .map(ControlPoint::bpm)
+        .fold(f64::INFINITY, f64::min);
+    let max = bpms
+        .iter()
+        .map(ControlPoint::bpm)
+        .fold(f64::NEG_INFINITY, f64::max);
+    if bpms.is_empty() {
+        DisplayBpm::Random
+    } else if min == max {
+        DisplayBpm::Single(min)
+    } else {
+        DisplayBpm::Range(min, max)
+    }
+}
+
+/// Parse the measures of note data into `sm.notes`.
+/// Routine charts have one section of note data per player, separated by `&`.
+fn parse_notes(sm: &mut Simfile, data: &str) -> Result<()> {
+    ensure!(!sm.bpms.is_empty(), "no bpms");
+    sm.notes.clear();
+    if sm.gamemode.is_routine() {
+        for (player, section) in split_notes(data, '&').into_iter().enumerate() {
+            ensure!(player < 2, "more than 2 players in routine chart");
+            parse_note_section(sm, section, player as u8)?;
+        }
+        //Interleave the notes of both players
+        sm.notes.sort_by_key(|note| note.beat);
+    } else {
+        parse_note_section(sm, data, 0)?;
+    }
+    Ok(())
+}
+
+/// Split note data at every `sep`, except for those inside inline attacks such as
+/// `{tipsy,50% drunk:15.2}`.
+fn split_notes(data: &str, sep: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut in_attack = false;
+    for (idx, c) in data.char_indices() {
+        match c {
+            '{' => in_attack = true,
+            '}' => in_attack = false,
+            c if c == sep && !in_attack => {
+                parts.push(&data[start..idx]);
+                start = idx + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&data[start..]);
+    parts
+}
+
+fn parse_note_section(sm: &mut Simfile, data: &str, player: u8) -> Result<()> {
+    let key_count = sm.gamemode.key_count() as usize;
+    for (measure_idx, measure) in split_notes(data, ',').into_iter().enumerate() {
+        let rows = measure
+            .lines()
+            .map(str::trim)
+            .filter(|row| !row.is_empty())
+            .collect::<Vec<_>>();
+        for (row_idx, row) in rows.iter().enumerate() {
+            let beat =
+                BeatPos::from((measure_idx * 4) as f64 + 4. * row_idx as f64 / rows.len() as f64);
+            let mut chars = row.chars().peekable();
+            let mut key = 0;
+            while let Some(c) = chars.next() {
+                let kind = match c {
+                    '1' => Some(Note::KIND_HIT),
+                    '2' => Some(Note::KIND_HEAD),
+                    '3' => Some(Note::KIND_TAIL),
+                    '4' => Some(Note::KIND_ROLL),
+                    'M' => Some(Note::KIND_MINE),
+                    'L' => Some(Note::KIND_LIFT),
+                    'F' => Some(Note::KIND_FAKE),
+                    //Empty, keysound-only and attack notes
+                    '0' | 'K' | 'A' => None,
+                    c => bail!(
+                        "invalid note '{}' in measure {}, row \"{}\"",
+                        c,
+                        measure_idx,
+                        row
+                    ),
+                };
+                //Skip inline attacks, which follow their note in curly braces
+                if chars.peek() == Some(&'{') {
+                    chars.by_ref().take_while(|&c| c != '}').for_each(drop);
+                }
+                //Keysounds come after that, in square brackets
+                let mut keysound = None;
+                if chars.peek() == Some(&'[') {
+                    chars.next();
+                    let idx = chars.by_ref().take_while(|&c| c != ']').collect::<String>();
+                    keysound = idx.trim().parse::<u32>().ok();
+                }
+                ensure!(
+                    key < key_count,
+                    "row \"{}\" in measure {} has more than {} keys",
+                    row,
+                    measure_idx,
+                    key_count
+                );
+                if let Some(kind) = kind {
+                    sm.notes.push(Note {
+                        kind,
+                        beat,
+                        key: key as i32,
+                        keysound: keysound.filter(|&idx| (idx as usize) < sm.keysounds.len()),
+                        player,
+                    });
+                }
+                key += 1;
+            }
+            ensure!(
+                key == key_count,
+                "row \"{}\" in measure {} has {} keys instead of {}",
+                row,
+                measure_idx,
+                key,
+                key_count
+            );
+        }
+    }
+    Ok(())
+}